@@ -1,6 +1,30 @@
+#[cfg(feature = "compress-frontend")]
+use std::path::Path;
 use std::process::Command;
 
 fn main() {
+    emit_git_sha();
+
+    println!("cargo:rerun-if-env-changed=CARGO_FEATURE_NO_FRONTEND");
+    println!("cargo:rerun-if-env-changed=CARGO_FEATURE_COMPRESS_FRONTEND");
+
+    let no_frontend = std::env::var_os("CARGO_FEATURE_NO_FRONTEND").is_some();
+    if !no_frontend {
+        build_frontend();
+    }
+
+    #[cfg(feature = "compress-frontend")]
+    compress_assets(
+        Path::new(if no_frontend {
+            "frontend/placeholder"
+        } else {
+            "frontend/dist"
+        }),
+        &Path::new(&std::env::var("OUT_DIR").expect("OUT_DIR expected")).join("frontend"),
+    );
+}
+
+fn build_frontend() {
     let frontend_dir = "frontend";
 
     println!("cargo:rerun-if-changed={frontend_dir}/public");
@@ -22,3 +46,70 @@ fn main() {
         panic!("Failed to build frontend");
     }
 }
+
+/// Writes a gzipped copy of every file below `source` to the same path below `target`, which is
+/// what gets embedded with `compress-frontend`.
+#[cfg(feature = "compress-frontend")]
+fn compress_assets(source: &Path, target: &Path) {
+    use flate2::{Compression, write::GzEncoder};
+    use std::io::Write;
+
+    println!("cargo:rerun-if-changed={}", source.display());
+
+    // files left from an earlier build would be served as well
+    let _ = std::fs::remove_dir_all(target);
+    for entry in walk(source) {
+        let relative = entry
+            .strip_prefix(source)
+            .expect("File below source expected");
+        let target = target.join(relative);
+        std::fs::create_dir_all(target.parent().expect("Parent directory expected"))
+            .expect("Failed to create compressed frontend directory");
+        let data = std::fs::read(&entry).expect("Failed to read frontend file");
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder
+            .write_all(&data)
+            .expect("Failed to compress frontend file");
+        std::fs::write(
+            &target,
+            encoder.finish().expect("Failed to compress frontend file"),
+        )
+        .expect("Failed to write compressed frontend file");
+    }
+}
+
+#[cfg(feature = "compress-frontend")]
+fn walk(dir: &Path) -> Vec<std::path::PathBuf> {
+    std::fs::read_dir(dir)
+        .expect("Failed to read frontend directory")
+        .map(|entry| entry.expect("Failed to read frontend directory").path())
+        .flat_map(|path| {
+            if path.is_dir() {
+                walk(&path)
+            } else {
+                vec![path]
+            }
+        })
+        .collect()
+}
+
+fn emit_git_sha() {
+    // builds outside a git checkout (e.g. from a source tarball) simply report no SHA
+    if !std::path::Path::new(".git/HEAD").exists() {
+        return;
+    }
+
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+
+    let sha = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+
+    if let Some(sha) = sha {
+        println!("cargo:rustc-env=IMAGE_WATCH_GIT_SHA={}", sha.trim());
+    }
+}
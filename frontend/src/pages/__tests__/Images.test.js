@@ -132,6 +132,199 @@ describe("Images page", () => {
     );
   });
 
+  it("orders ISO 8601 and epoch millisecond timestamps alike", async () => {
+    render(ImagesHarness, { props: { token: "abc" } });
+
+    sockets[0]._emit("message", {
+      data: JSON.stringify({
+        added: [
+          ["iso.jpg", "2024-01-02T01:00:00.000+01:00"],
+          ["epoch.jpg", Date.UTC(2024, 0, 1)],
+        ],
+      }),
+    });
+    sockets[0]._emit("message", {
+      data: JSON.stringify({
+        added: [
+          ["newest.jpg", Date.UTC(2024, 0, 3)],
+          ["middle.jpg", "2024-01-01T12:00:00.000Z"],
+        ],
+      }),
+    });
+
+    await waitFor(() =>
+      expect(screen.getAllByRole("img").map((img) => img.alt)).toEqual([
+        "newest.jpg",
+        "iso.jpg",
+        "middle.jpg",
+        "epoch.jpg",
+      ]),
+    );
+  });
+
+  it("loads images below the data route prefix reported by the backend", async () => {
+    global.fetch = vi.fn((url) =>
+      Promise.resolve({
+        status: 200,
+        text: () =>
+          Promise.resolve(
+            url === "/backend/data_route_prefix" ? "/media" : "",
+          ),
+        blob: () => Promise.resolve(new Blob()),
+      }),
+    );
+    global.IntersectionObserver = class {
+      constructor(callback) {
+        this.callback = callback;
+      }
+      observe() {
+        this.callback([{ isIntersecting: true }]);
+      }
+      disconnect() {}
+    };
+
+    render(ImagesHarness, { props: { token: "abc" } });
+
+    sockets[0]._emit("message", {
+      data: JSON.stringify({ added: [["album/a.jpg", 1]] }),
+    });
+
+    await waitFor(() =>
+      expect(global.fetch).toHaveBeenCalledWith(
+        "/media/album/a.jpg",
+        expect.objectContaining({
+          headers: { Authorization: "Bearer abc" },
+        }),
+      ),
+    );
+  });
+
+  it("renames moved images in place", async () => {
+    render(ImagesHarness, { props: { token: "abc" } });
+
+    sockets[0]._emit("message", {
+      data: JSON.stringify({
+        added: [
+          ["a.jpg", 2],
+          ["b.jpg", 1],
+        ],
+      }),
+    });
+    sockets[0]._emit("message", {
+      data: JSON.stringify({
+        removed: ["b.jpg"],
+        added: [],
+        moved: [
+          ["a.jpg", "c.jpg"],
+          ["b.jpg", "d.jpg"],
+        ],
+      }),
+    });
+
+    await waitFor(() =>
+      expect(screen.getAllByRole("img").map((img) => img.alt)).toEqual([
+        "c.jpg",
+        "d.jpg",
+      ]),
+    );
+  });
+
+  it("replaces all images when a full update arrives", async () => {
+    render(ImagesHarness, { props: { token: "abc" } });
+
+    sockets[0]._emit("message", {
+      data: JSON.stringify({ added: [["stale.jpg", 1]] }),
+    });
+
+    await waitFor(() => {
+      expect(screen.getAllByRole("img")).toHaveLength(1);
+    });
+
+    sockets[0]._emit("message", {
+      data: JSON.stringify({
+        removed: [],
+        added: [
+          ["second.jpg", 3],
+          ["first.jpg", 2],
+        ],
+        full: true,
+      }),
+    });
+
+    await waitFor(() =>
+      expect(screen.getAllByRole("img").map((img) => img.alt)).toEqual([
+        "second.jpg",
+        "first.jpg",
+      ]),
+    );
+  });
+
+  it("keeps full updates in the order the backend sent them", async () => {
+    render(ImagesHarness, { props: { token: "abc" } });
+
+    sockets[0]._emit("message", {
+      data: JSON.stringify({
+        removed: [],
+        added: [
+          ["b.jpg", 1],
+          ["a.jpg", 3],
+        ],
+        full: true,
+      }),
+    });
+
+    await waitFor(() =>
+      expect(screen.getAllByRole("img").map((img) => img.alt)).toEqual([
+        "b.jpg",
+        "a.jpg",
+      ]),
+    );
+  });
+
+  it("places added and moved images by the backend's sort order", async () => {
+    global.fetch = vi.fn((url) =>
+      Promise.resolve({
+        status: 200,
+        text: () =>
+          Promise.resolve(
+            url === "/backend/sort_order" ? "name_asc" : "/backend/data",
+          ),
+      }),
+    );
+
+    render(ImagesHarness, { props: { token: "abc" } });
+
+    sockets[0]._emit("message", {
+      data: JSON.stringify({
+        removed: [],
+        added: [
+          ["a.jpg", 1],
+          ["c.jpg", 3],
+        ],
+        full: true,
+      }),
+    });
+    await waitFor(() => {
+      expect(screen.getAllByRole("img")).toHaveLength(2);
+    });
+
+    sockets[0]._emit("message", {
+      data: JSON.stringify({
+        removed: [],
+        added: [["b.jpg", 2]],
+        moved: [["a.jpg", "d.jpg"]],
+      }),
+    });
+
+    await waitFor(() =>
+      expect(screen.getAllByRole("img").map((img) => img.alt)).toEqual([
+        "b.jpg",
+        "c.jpg",
+        "d.jpg",
+      ]),
+    );
+  });
+
   it("removes the auth token when the backend reports 401 on reconnect", async () => {
     vi.useFakeTimers();
     const removeAuthToken = vi.fn();
@@ -149,6 +342,34 @@ describe("Images page", () => {
     expect(global.WebSocket).toHaveBeenCalledTimes(1);
   });
 
+  it("keeps the auth token when an expired token can still be refreshed", async () => {
+    vi.useFakeTimers();
+    const removeAuthToken = vi.fn();
+    global.fetch = vi.fn((url) =>
+      Promise.resolve({
+        status: url === "/backend/refresh" ? 200 : 401,
+        text: () => Promise.resolve(""),
+      }),
+    );
+
+    render(ImagesHarness, { props: { token: "abc", removeAuthToken } });
+
+    sockets[0]._emit("close");
+
+    await waitFor(() =>
+      expect(global.fetch).toHaveBeenCalledWith(
+        "/backend/refresh",
+        expect.objectContaining({ method: "POST" }),
+      ),
+    );
+    await Promise.resolve();
+    await Promise.resolve();
+
+    vi.runOnlyPendingTimers();
+    expect(removeAuthToken).not.toHaveBeenCalled();
+    expect(global.WebSocket).toHaveBeenCalledTimes(2);
+  });
+
   it("schedules reconnects with growing delays when the socket closes", async () => {
     vi.useFakeTimers();
     const setTimeoutSpy = vi.spyOn(global, "setTimeout");
@@ -171,7 +392,13 @@ describe("Images page", () => {
     expect(global.WebSocket).toHaveBeenCalledTimes(2);
 
     sockets[1]._emit("error");
-    await waitFor(() => expect(global.fetch).toHaveBeenCalledTimes(2));
+    await waitFor(() =>
+      expect(
+        global.fetch.mock.calls.filter(
+          ([url]) => url === "/backend/checkauth",
+        ),
+      ).toHaveLength(2),
+    );
     await Promise.resolve();
     await Promise.resolve();
 
@@ -0,0 +1,77 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+use tokio::{
+    sync::mpsc,
+    time::{Interval, MissedTickBehavior},
+};
+use tracing::instrument;
+
+/// The shared counters of the other actors that go into the summary.
+#[derive(Debug)]
+pub struct ActivityCounters {
+    pub tracked_files: Arc<AtomicUsize>,
+    pub web_sockets: Arc<AtomicUsize>,
+    /// Running total, the summary reports the difference to the previous one.
+    pub changes_broadcast: Arc<AtomicUsize>,
+    /// Running total, the summary reports the difference to the previous one.
+    pub logins: Arc<AtomicUsize>,
+    pub tokens: Arc<AtomicUsize>,
+}
+
+/// Periodically logs a single line summarizing what the server has been doing.
+#[derive(Debug)]
+pub struct ActivitySummaryActor {
+    counters: ActivityCounters,
+    summary_timer: Interval,
+    last_changes_broadcast: usize,
+    last_logins: usize,
+}
+
+impl ActivitySummaryActor {
+    pub fn new(counters: ActivityCounters, summary_interval: std::time::Duration) -> Self {
+        // the first summary is due after a full interval rather than at startup
+        let mut summary_timer = tokio::time::interval_at(
+            tokio::time::Instant::now() + summary_interval,
+            summary_interval,
+        );
+        summary_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Self {
+            counters,
+            summary_timer,
+            last_changes_broadcast: 0,
+            last_logins: 0,
+        }
+    }
+
+    fn log_summary(&mut self) {
+        let changes_broadcast = self.counters.changes_broadcast.load(Ordering::Relaxed);
+        let logins = self.counters.logins.load(Ordering::Relaxed);
+        tracing::info!(
+            tracked_files = self.counters.tracked_files.load(Ordering::Relaxed),
+            web_sockets = self.counters.web_sockets.load(Ordering::Relaxed),
+            changes_broadcast = changes_broadcast - self.last_changes_broadcast,
+            logins = logins - self.last_logins,
+            tokens = self.counters.tokens.load(Ordering::Relaxed),
+            "activity summary"
+        );
+        self.last_changes_broadcast = changes_broadcast;
+        self.last_logins = logins;
+    }
+
+    /// Runs until all senders of `receiver` are dropped; nothing is ever sent on it.
+    #[instrument(level = "trace")]
+    pub async fn run(mut self, mut receiver: mpsc::Receiver<()>) {
+        loop {
+            tokio::select! {
+                msg = receiver.recv() => if msg.is_none() {
+                    break;
+                },
+                _ = self.summary_timer.tick() => self.log_summary(),
+            }
+        }
+
+        tracing::info!("activity summary shut down");
+    }
+}
@@ -1,18 +1,37 @@
-use crate::authentication::{
-    Token, Username,
-    authentication_token_store_actor::{
-        AuthenticationTokenStoreActor, AuthenticationTokenStoreActorEvent,
+use crate::{
+    authentication::{
+        self, Token, Username,
+        authentication_token_store_actor::{
+            AuthenticationTokenStoreActor, AuthenticationTokenStoreActorEvent,
+        },
+        is_path_under,
+        login_limiter::{LoginLimiter, LoginLimits},
+        public_paths::{DEFAULT_PUBLIC_PATHS, PublicPaths},
+        url_signer::UrlSigner,
     },
+    axum_util::ApiError,
+    config::AuthMode,
+    health::Heartbeat,
+    tls::ClientIdentity,
+    tokio_util,
+};
+use argon2::{
+    Params, PasswordHash, PasswordHasher, PasswordVerifier,
+    password_hash::{Error, SaltString},
 };
-use argon2::{Argon2, PasswordHash, PasswordVerifier, password_hash::Error};
 use axum::{
     body::Body,
-    http::{HeaderMap, HeaderValue, Request, StatusCode, Uri, header},
+    http::{HeaderMap, HeaderValue, Request, Uri, header},
     middleware::Next,
-    response::{IntoResponse, Response},
+    response::Response,
 };
 use serde::Deserialize;
-use tokio::sync::mpsc;
+use std::{collections::HashMap, net::IpAddr, time::Duration};
+use subtle::ConstantTimeEq;
+use tokio::{
+    sync::mpsc,
+    time::{Interval, MissedTickBehavior},
+};
 use tracing::instrument;
 
 #[derive(Debug, Deserialize)]
@@ -21,83 +40,335 @@ pub struct Credentials {
     password: String,
 }
 
+/// Carries the token for requests that cannot set a header, such as image links.
+pub const TOKEN_COOKIE: &str = "image_watch_token";
+/// Readable by the frontend, which echoes it in `X-CSRF-Token` to prove a cookie-authenticated
+/// request did not come from another site.
+pub const CSRF_COOKIE: &str = "csrf";
+const CSRF_HEADER: &str = "x-csrf-token";
+
+/// Where a request presented its token.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TokenSource {
+    Authorization,
+    WebSocketProtocol,
+    /// Sent by the browser on its own, so it needs protection against cross-site requests.
+    Cookie,
+}
+
+/// How often usernames and addresses without recent failed logins are forgotten.
+const FORGET_IDLE_LOGINS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Salt of the hash verified in place of a real one when the username is unknown.
+const UNKNOWN_USER_SALT: &str = "aW1hZ2Utd2F0Y2gtZHVtbXk";
+
+/// Introspection routes, which check the token without extending it.
+const READ_ONLY_PATHS: &[&str] = &["/backend/checkauth", "/backend/debug/queues"];
+
+#[derive(Debug, PartialEq)]
+pub enum RequestAuthentication {
+    Authorized,
+    Unauthorized,
+    Forbidden,
+}
+
+/// The answer to a login attempt.
+#[derive(Debug)]
+pub enum LoginOutcome {
+    Granted(Token),
+    Denied,
+    /// Too many failed attempts for the username or from the address.
+    LockedOut {
+        retry_after: Duration,
+    },
+}
+
 #[derive(Debug)]
 pub enum AuthenticationActorEvent {
     AuthenticateRequest {
         token: Option<Token>,
         uri: Uri,
-        response_sender: tokio::sync::oneshot::Sender<bool>,
+        /// The subject CN of the verified client certificate the connection presented, if any.
+        client_common_name: Option<String>,
+        response_sender: tokio::sync::oneshot::Sender<RequestAuthentication>,
     },
     GetToken {
         credentials: Credentials,
-        response_sender: tokio::sync::oneshot::Sender<Option<Token>>,
+        remote_ip: IpAddr,
+        response_sender: tokio::sync::oneshot::Sender<LoginOutcome>,
+    },
+    /// Logs in the subject of a verified client certificate.
+    GetClientToken {
+        common_name: String,
+        response_sender: tokio::sync::oneshot::Sender<LoginOutcome>,
     },
 }
 
 #[derive(Debug)]
 pub struct AuthenticationActor {
-    username: String,
-    password_argon2: String,
+    /// Accepted argon2 hashes by username.
+    users: HashMap<String, Vec<String>>,
+    argon2_params: Params,
+    /// Verified when the username is unknown, so that the login takes as long as one for an
+    /// existing user. Nothing hashes to it.
+    unknown_user_hash: String,
+    data_route_prefix: String,
+    public_data_prefixes: Vec<String>,
+    /// The default public paths and those configured in `PUBLIC_PATHS`.
+    public_paths: PublicPaths,
+    url_signer: Option<UrlSigner>,
     authentication_token_store_actor_sender: mpsc::Sender<AuthenticationTokenStoreActorEvent>,
+    login_limiter: LoginLimiter,
+    auth_mode: AuthMode,
+    forget_idle_logins_timer: Interval,
+    heartbeat: Heartbeat,
+    heartbeat_timer: Interval,
 }
 
 impl AuthenticationActor {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        username: String,
-        password_argon2: String,
+        users: HashMap<String, Vec<String>>,
+        argon2_params: Params,
+        data_route_prefix: String,
+        public_data_prefixes: Vec<String>,
+        public_paths: Vec<String>,
+        url_signer: Option<UrlSigner>,
         authentication_token_store_actor_sender: mpsc::Sender<AuthenticationTokenStoreActorEvent>,
-    ) -> Self {
-        Self {
-            username,
-            password_argon2,
-            authentication_token_store_actor_sender,
+        login_limits: LoginLimits,
+        auth_mode: AuthMode,
+    ) -> crate::error::Result<Self> {
+        let public_paths = PublicPaths::new(
+            DEFAULT_PUBLIC_PATHS
+                .iter()
+                .copied()
+                .chain(public_paths.iter().map(String::as_str)),
+        )?;
+        for (username, hashes) in &users {
+            let differs = hashes.iter().any(|hash| {
+                PasswordHash::new(hash)
+                    .and_then(|hash| Params::try_from(&hash))
+                    // parsed hashes also carry their output length, which costs nothing
+                    .is_ok_and(|params| {
+                        (params.m_cost(), params.t_cost(), params.p_cost())
+                            != (
+                                argon2_params.m_cost(),
+                                argon2_params.t_cost(),
+                                argon2_params.p_cost(),
+                            )
+                    })
+            });
+            if differs {
+                tracing::warn!(
+                    "Password hash of {} uses other argon2 costs than configured, so its logins take a different time than those of unknown users",
+                    username
+                );
+            }
         }
+        let unknown_user_hash = SaltString::from_b64(UNKNOWN_USER_SALT)
+            .and_then(|salt| {
+                authentication::argon2(argon2_params.clone())
+                    .hash_password(b"unknown user", &salt)
+                    .map(|hash| hash.to_string())
+            })
+            .map_err(|e| {
+                crate::error::Error::AuthConfigError(format!(
+                    "Failed to hash with the configured argon2 costs: {}",
+                    e
+                ))
+            })?;
+        let mut forget_idle_logins_timer = tokio::time::interval(FORGET_IDLE_LOGINS_INTERVAL);
+        forget_idle_logins_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Ok(Self {
+            users,
+            argon2_params,
+            unknown_user_hash,
+            data_route_prefix,
+            public_data_prefixes,
+            public_paths,
+            url_signer,
+            authentication_token_store_actor_sender,
+            login_limiter: LoginLimiter::new(login_limits),
+            auth_mode,
+            forget_idle_logins_timer,
+            heartbeat: Heartbeat::default(),
+            heartbeat_timer: Heartbeat::timer(),
+        })
     }
 
-    fn verify_password(hash: &str, password: &str) -> Result<bool, Error> {
+    /// Shared heartbeat, beaten from the run loop for the health check.
+    pub fn heartbeat(&self) -> Heartbeat {
+        self.heartbeat.clone()
+    }
+
+    /// Checks every hash without short-circuiting, so timing does not reveal which one matched.
+    fn verify_password_any(&self, hashes: &[String], password: &str) -> bool {
+        hashes.iter().fold(false, |matched, hash| {
+            let verified = self
+                .verify_password(hash, password)
+                .inspect_err(|e| tracing::error!("Error verifying password: {:?}", e))
+                .unwrap_or(false);
+            matched | verified
+        })
+    }
+
+    /// Compares `username` with every known one in constant time, so neither the lookup nor the
+    /// comparison reveals how close a guess came.
+    fn hashes_of(&self, username: &str) -> Option<&[String]> {
+        self.users
+            .iter()
+            .fold(None, |found, (known_username, hashes)| {
+                let matches: bool = known_username.as_bytes().ct_eq(username.as_bytes()).into();
+                if matches {
+                    Some(hashes.as_slice())
+                } else {
+                    found
+                }
+            })
+    }
+
+    fn verify_password(&self, hash: &str, password: &str) -> Result<bool, Error> {
         let parsed_hash = PasswordHash::new(hash)?;
-        Ok(Argon2::default()
+        Ok(authentication::argon2(self.argon2_params.clone())
             .verify_password(password.as_bytes(), &parsed_hash)
             .is_ok())
     }
 
-    async fn authenticate_request(&mut self, token: Option<Token>, uri: Uri) -> bool {
+    async fn authenticate_request(
+        &mut self,
+        token: Option<Token>,
+        uri: Uri,
+        client_common_name: Option<String>,
+    ) -> RequestAuthentication {
         let path = uri.path();
-        // TODO: more flexible check
-        if !path.starts_with("/backend")
-            || path == "/backend/login"
-            || path == "/backend/frontend_hash"
+        let is_data_path = is_path_under(path, &self.data_route_prefix);
+        if (!path.starts_with("/backend") && !is_data_path)
+            || self.public_paths.matches(path)
+            || (is_data_path
+                && self
+                    .public_data_prefixes
+                    .iter()
+                    .any(|prefix| is_path_under(path, prefix)))
         {
-            return true;
+            return RequestAuthentication::Authorized;
+        }
+        if is_data_path
+            && let Some(url_signer) = &self.url_signer
+            && let Some(valid) = url_signer.verify(path, uri.query())
+        {
+            return if valid {
+                RequestAuthentication::Authorized
+            } else {
+                RequestAuthentication::Forbidden
+            };
+        }
+        let Some(token) = token else {
+            return RequestAuthentication::Unauthorized;
+        };
+        // a token of a certified client is only good together with that client's certificate; the
+        // token store keeps the CN it was minted for as its username
+        if self.auth_mode == AuthMode::Mtls {
+            let Some(common_name) = client_common_name else {
+                return RequestAuthentication::Unauthorized;
+            };
+            let issued_to = AuthenticationTokenStoreActor::get_token_info(
+                &self.authentication_token_store_actor_sender,
+                token.clone(),
+            )
+            .await
+            .ok()
+            .flatten()
+            .map(|token_info| token_info.username);
+            if issued_to.is_none_or(|username| username.0 != common_name) {
+                tracing::warn!(
+                    "Rejecting a token not minted for the client certificate of {:?}",
+                    common_name
+                );
+                return RequestAuthentication::Unauthorized;
+            }
         }
-        if let Some(token) = token {
-            return AuthenticationTokenStoreActor::check_and_refresh_token(
+        // looking at a session must not keep it alive
+        let valid = if READ_ONLY_PATHS.contains(&path) {
+            AuthenticationTokenStoreActor::peek_token(
+                &self.authentication_token_store_actor_sender,
+                token,
+            )
+            .await
+            .is_ok_and(|ttl_remaining| ttl_remaining.is_some())
+        } else {
+            AuthenticationTokenStoreActor::check_and_refresh_token(
                 &mut self.authentication_token_store_actor_sender,
                 token,
             )
             .await
-            .unwrap_or(false);
+            .unwrap_or(false)
+        };
+        if valid {
+            return RequestAuthentication::Authorized;
         }
-        false
+        RequestAuthentication::Unauthorized
     }
 
     async fn authenticate(
         &mut self,
         Credentials { username, password }: Credentials,
-    ) -> Option<Token> {
-        if username == self.username
-            && Self::verify_password(&self.password_argon2, &password)
-                .inspect_err(|e| tracing::error!("Error verifying password: {:?}", e))
-                .unwrap_or(false)
+        remote_ip: IpAddr,
+    ) -> LoginOutcome {
+        if self.auth_mode != AuthMode::Password {
+            return LoginOutcome::Denied;
+        }
+        // checked before the password so a locked out guess costs no hashing
+        if let Some(retry_after) = self.login_limiter.locked_out(&username, remote_ip) {
+            return LoginOutcome::LockedOut { retry_after };
+        }
+        let verified = match self.hashes_of(&username) {
+            Some(hashes) => self.verify_password_any(hashes, &password),
+            None => {
+                let _ = self.verify_password(&self.unknown_user_hash, &password);
+                false
+            }
+        };
+        if !verified {
+            return match self.login_limiter.record_failure(&username, remote_ip) {
+                Some(retry_after) => {
+                    tracing::warn!(
+                        target: "audit",
+                        username,
+                        %remote_ip,
+                        retry_after_secs = retry_after.as_secs(),
+                        "login locked out"
+                    );
+                    LoginOutcome::LockedOut { retry_after }
+                }
+                None => LoginOutcome::Denied,
+            };
+        }
+        self.login_limiter.record_success(&username);
+        match AuthenticationTokenStoreActor::get_token(
+            &mut self.authentication_token_store_actor_sender,
+            Username(username),
+        )
+        .await
         {
-            AuthenticationTokenStoreActor::get_token(
-                &mut self.authentication_token_store_actor_sender,
-                Username(username),
-            )
-            .await
-            .ok()
-        } else {
-            None
+            Ok(token) => LoginOutcome::Granted(token),
+            Err(_) => LoginOutcome::Denied,
+        }
+    }
+
+    /// Grants a token to the subject CN of a certificate rustls already verified, so there is no
+    /// password to check and no failure to limit.
+    async fn authenticate_client(&mut self, common_name: String) -> LoginOutcome {
+        if self.auth_mode != AuthMode::Mtls {
+            return LoginOutcome::Denied;
+        }
+        match AuthenticationTokenStoreActor::get_token(
+            &mut self.authentication_token_store_actor_sender,
+            Username(common_name),
+        )
+        .await
+        {
+            Ok(token) => LoginOutcome::Granted(token),
+            Err(_) => LoginOutcome::Denied,
         }
     }
 
@@ -111,10 +382,11 @@ impl AuthenticationActor {
                             AuthenticationActorEvent::AuthenticateRequest {
                                 token,
                                 uri,
+                                client_common_name,
                                 response_sender: response,
                             } => {
                                 let _ = response
-                                    .send(self.authenticate_request(token, uri).await)
+                                    .send(self.authenticate_request(token, uri, client_common_name).await)
                                     .inspect_err(|e| {
                                         tracing::error!(
                                             "Error responding to AuthenticatorEvent::VerifyToken: {:?}",
@@ -124,10 +396,11 @@ impl AuthenticationActor {
                             }
                             AuthenticationActorEvent::GetToken {
                                 credentials,
+                                remote_ip,
                                 response_sender: response,
                             } => {
                                 let _ = response
-                                    .send(self.authenticate(credentials).await)
+                                    .send(self.authenticate(credentials, remote_ip).await)
                                     .inspect_err(|e| {
                                         tracing::error!(
                                             "Error responding to AuthenticatorEvent::Authenticate: {:?}",
@@ -135,9 +408,24 @@ impl AuthenticationActor {
                                         )
                                     });
                             }
+                            AuthenticationActorEvent::GetClientToken {
+                                common_name,
+                                response_sender: response,
+                            } => {
+                                let _ = response
+                                    .send(self.authenticate_client(common_name).await)
+                                    .inspect_err(|e| {
+                                        tracing::error!(
+                                            "Error responding to AuthenticatorEvent::GetClientToken: {:?}",
+                                            e
+                                        )
+                                    });
+                            }
                         }},
                     None => break,
                 },
+                _ = self.forget_idle_logins_timer.tick() => self.login_limiter.forget_idle(),
+                _ = self.heartbeat_timer.tick() => self.heartbeat.beat(),
             }
         }
     }
@@ -146,61 +434,602 @@ impl AuthenticationActor {
         sender: mpsc::WeakSender<AuthenticationActorEvent>,
         req: Request<Body>,
         next: Next,
-    ) -> Result<Response, Response> {
-        if let Some(sender) = sender.upgrade() {
-            let token = Self::extract_token(&req.headers());
+    ) -> Result<Response, ApiError> {
+        let Some(sender) = sender.upgrade() else {
+            return Err(ApiError::ServiceRestarting);
+        };
+        let (token, source) = Self::extract_token_with_source(req.headers()).unzip();
+        // a cross-site request carries the cookie but cannot read it to set the header, so it
+        // goes on as unauthenticated; public routes such as login still work with a stale cookie
+        let csrf_rejected = source == Some(TokenSource::Cookie)
+            && !req.method().is_safe()
+            && !Self::csrf_token_matches(req.headers());
+        let token = token.filter(|_| !csrf_rejected);
 
-            let (response_sender, response_receiver) = tokio::sync::oneshot::channel();
+        let (response_sender, response_receiver) = tokio::sync::oneshot::channel();
 
-            let uri = req.uri().clone();
+        let uri = req.uri().clone();
+        let client_common_name = req
+            .extensions()
+            .get::<ClientIdentity>()
+            .and_then(|ClientIdentity(common_name)| common_name.clone());
 
-            if let Ok(_) = sender
-                .send(AuthenticationActorEvent::AuthenticateRequest {
-                    token,
-                    uri,
-                    response_sender,
-                })
-                .await
-                && let Ok(authenticated) = response_receiver.await
-                && authenticated
-            {
-                return Ok(next.run(req).await);
+        if sender
+            .send(AuthenticationActorEvent::AuthenticateRequest {
+                token,
+                uri,
+                client_common_name,
+                response_sender,
+            })
+            .await
+            .is_ok()
+        {
+            match tokio_util::receive_response(response_receiver).await {
+                Ok(RequestAuthentication::Authorized) => return Ok(next.run(req).await),
+                Ok(RequestAuthentication::Forbidden) => return Err(ApiError::Forbidden),
+                Err(crate::error::Error::ResponseTimeout) => {
+                    tracing::error!("Authentication actor did not respond in time");
+                    return Err(ApiError::ServiceUnavailable);
+                }
+                Ok(RequestAuthentication::Unauthorized) if csrf_rejected => {
+                    return Err(ApiError::CsrfTokenMismatch);
+                }
+                Ok(RequestAuthentication::Unauthorized) | Err(_) => {}
             }
-        } else {
-            let resp = (StatusCode::SERVICE_UNAVAILABLE, "Service restarting").into_response();
-            return Err(resp);
         }
-        let resp = (StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
-        Err(resp)
+        Err(ApiError::Unauthorized)
+    }
+
+    fn csrf_token_matches(headers: &HeaderMap<HeaderValue>) -> bool {
+        match (
+            headers
+                .get(CSRF_HEADER)
+                .and_then(|csrf_header| csrf_header.to_str().ok()),
+            Self::find_cookie(headers, CSRF_COOKIE),
+        ) {
+            (Some(csrf_header), Some(csrf_cookie)) if !csrf_cookie.is_empty() => csrf_header
+                .trim()
+                .as_bytes()
+                .ct_eq(csrf_cookie.as_bytes())
+                .into(),
+            _ => false,
+        }
     }
 
     pub async fn get_token(
         sender: mpsc::Sender<AuthenticationActorEvent>,
         credentials: Credentials,
-    ) -> crate::error::Result<Option<Token>> {
+        remote_ip: IpAddr,
+    ) -> crate::error::Result<LoginOutcome> {
         let (response_sender, response_receiver) = tokio::sync::oneshot::channel();
 
+        // logins fail fast instead of queueing behind slow password hashing
         sender
-            .send(AuthenticationActorEvent::GetToken {
+            .try_send(AuthenticationActorEvent::GetToken {
                 credentials,
+                remote_ip,
+                response_sender,
+            })
+            .map_err(|e| match e {
+                mpsc::error::TrySendError::Full(_) => crate::error::Error::ServiceBusy,
+                mpsc::error::TrySendError::Closed(e) => mpsc::error::SendError(e).into(),
+            })?;
+        tokio_util::receive_response(response_receiver).await
+    }
+
+    pub async fn get_client_token(
+        sender: mpsc::Sender<AuthenticationActorEvent>,
+        common_name: String,
+    ) -> crate::error::Result<LoginOutcome> {
+        let (response_sender, response_receiver) = tokio::sync::oneshot::channel();
+        sender
+            .send(AuthenticationActorEvent::GetClientToken {
+                common_name,
                 response_sender,
             })
             .await?;
-        Ok(response_receiver.await?)
+        tokio_util::receive_response(response_receiver).await
     }
 
-    pub fn extract_token(headers: &HeaderMap<HeaderValue>) -> Option<Token> {
+    fn parse_bearer_authorization(value: &str) -> Option<Token> {
+        let (scheme, token) = value.trim().split_once(char::is_whitespace)?;
+        let token = token.trim();
+        (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty())
+            .then(|| Token(token.to_string()))
+    }
+
+    fn parse_bearer_web_socket_protocol(value: &str) -> Option<Token> {
+        let mut protocols = value.split(',').map(str::trim);
+        protocols.find(|protocol| protocol.eq_ignore_ascii_case("bearer"))?;
+        protocols
+            .next()
+            .filter(|token| !token.is_empty())
+            .map(|token| Token(token.to_string()))
+    }
+
+    /// The value of the first non-empty cookie called `name` across all `Cookie` headers.
+    fn find_cookie<'a>(headers: &'a HeaderMap<HeaderValue>, name: &str) -> Option<&'a str> {
+        headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|cookie_header| cookie_header.to_str().ok())
+            .flat_map(|cookie_header| cookie_header.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .filter(|(cookie_name, _)| *cookie_name == name)
+            .map(|(_, value)| value.trim_matches('"'))
+            .find(|value| !value.is_empty())
+    }
+
+    /// The token from the `Authorization` header, the web socket subprotocol or the token cookie,
+    /// in that order.
+    pub fn extract_token_with_source(
+        headers: &HeaderMap<HeaderValue>,
+    ) -> Option<(Token, TokenSource)> {
         headers
             .get(header::AUTHORIZATION)
             .and_then(|auth_header| auth_header.to_str().ok())
-            .and_then(|auth_str| auth_str.strip_prefix("Bearer "))
-            .map(|token| Token(token.to_string()))
+            .and_then(Self::parse_bearer_authorization)
+            .map(|token| (token, TokenSource::Authorization))
             .or_else(|| {
                 headers
                     .get(header::SEC_WEBSOCKET_PROTOCOL)
                     .and_then(|auth_header| auth_header.to_str().ok())
-                    .and_then(|auth_str| auth_str.strip_prefix("bearer, "))
-                    .map(|token| Token(token.to_string()))
+                    .and_then(Self::parse_bearer_web_socket_protocol)
+                    .map(|token| (token, TokenSource::WebSocketProtocol))
+            })
+            .or_else(|| {
+                Self::find_cookie(headers, TOKEN_COOKIE)
+                    .map(|token| (Token(token.to_string()), TokenSource::Cookie))
             })
     }
+
+    pub fn extract_token(headers: &HeaderMap<HeaderValue>) -> Option<Token> {
+        Self::extract_token_with_source(headers).map(|(token, _)| token)
+    }
+
+    /// `Set-Cookie` values storing the token and a fresh CSRF token for the browser session.
+    /// Without `secure` the cookies are also sent over plain HTTP.
+    pub fn login_cookies(Token(token): &Token, secure: bool) -> [String; 2] {
+        let Token(csrf_token) = Token::generate();
+        [
+            Self::cookie(TOKEN_COOKIE, token, true, secure),
+            Self::cookie(CSRF_COOKIE, &csrf_token, false, secure),
+        ]
+    }
+
+    /// `Set-Cookie` values removing the cookies set at login.
+    pub fn expired_login_cookies(secure: bool) -> [String; 2] {
+        [TOKEN_COOKIE, CSRF_COOKIE].map(|name| {
+            format!(
+                "{}; Max-Age=0",
+                Self::cookie(name, "", name == TOKEN_COOKIE, secure)
+            )
+        })
+    }
+
+    fn cookie(name: &str, value: &str, http_only: bool, secure: bool) -> String {
+        format!(
+            "{}={}; Path=/; SameSite=Strict{}{}",
+            name,
+            value,
+            if http_only { "; HttpOnly" } else { "" },
+            if secure { "; Secure" } else { "" }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Router,
+        http::StatusCode,
+        middleware,
+        routing::{get, post},
+    };
+    use std::net::Ipv4Addr;
+    use tower::ServiceExt;
+
+    const REMOTE_IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    fn hash(password: &str) -> String {
+        let params = Params::new(Params::MIN_M_COST, 1, 1, None).unwrap();
+        authentication::argon2(params)
+            .hash_password(
+                password.as_bytes(),
+                &SaltString::generate(&mut rand_core::OsRng),
+            )
+            .unwrap()
+            .to_string()
+    }
+
+    fn no_login_limits() -> LoginLimits {
+        LoginLimits {
+            max_failures_per_user: 0,
+            max_failures_per_ip: 0,
+            failure_window: Duration::from_secs(60),
+            lockout: Duration::from_secs(60),
+            max_lockout: Duration::from_secs(60),
+        }
+    }
+
+    /// An actor with cheap argon2 costs, backed by a running token store.
+    fn actor(users: &[(&str, &[String])], login_limits: LoginLimits) -> AuthenticationActor {
+        let (authentication_token_store_actor_sender, authentication_token_store_actor_receiver) =
+            mpsc::channel(8);
+        tokio::spawn(
+            AuthenticationTokenStoreActor::for_test(
+                Duration::from_secs(3600),
+                Duration::ZERO,
+                usize::MAX,
+                usize::MAX,
+            )
+            .run(authentication_token_store_actor_receiver),
+        );
+        AuthenticationActor::new(
+            users
+                .iter()
+                .map(|(username, hashes)| (username.to_string(), hashes.to_vec()))
+                .collect(),
+            Params::new(Params::MIN_M_COST, 1, 1, None).unwrap(),
+            "/backend/data".to_string(),
+            Vec::new(),
+            Vec::new(),
+            None,
+            authentication_token_store_actor_sender,
+            login_limits,
+            AuthMode::Password,
+        )
+        .unwrap()
+    }
+
+    fn credentials(username: &str, password: &str) -> Credentials {
+        Credentials {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    fn headers<'a>(pairs: impl IntoIterator<Item = (header::HeaderName, &'a str)>) -> HeaderMap {
+        pairs
+            .into_iter()
+            .map(|(name, value)| (name, HeaderValue::from_str(value).unwrap()))
+            .collect()
+    }
+
+    fn token(value: &str) -> Option<Token> {
+        Some(Token(value.to_string()))
+    }
+
+    #[test]
+    fn bearer_scheme_is_case_insensitive_and_tolerates_whitespace() {
+        for value in [
+            "Bearer abc",
+            "bearer abc",
+            "  BeArEr   abc  ",
+            "Bearer\tabc",
+        ] {
+            let headers = headers([(header::AUTHORIZATION, value)]);
+            assert_eq!(AuthenticationActor::extract_token(&headers), token("abc"));
+        }
+        for value in ["Bearer", "Bearer   ", "Basic abc", "Bearerabc"] {
+            let headers = headers([(header::AUTHORIZATION, value)]);
+            assert_eq!(AuthenticationActor::extract_token(&headers), None);
+        }
+    }
+
+    #[test]
+    fn web_socket_protocol_is_case_insensitive_and_tolerates_whitespace() {
+        for value in [
+            "bearer, abc",
+            " Bearer ,abc ",
+            "image-watch.msgpack, BEARER, abc",
+        ] {
+            let headers = headers([(header::SEC_WEBSOCKET_PROTOCOL, value)]);
+            assert_eq!(AuthenticationActor::extract_token(&headers), token("abc"));
+        }
+        for value in ["bearer", "bearer, ", "abc"] {
+            let headers = headers([(header::SEC_WEBSOCKET_PROTOCOL, value)]);
+            assert_eq!(AuthenticationActor::extract_token(&headers), None);
+        }
+    }
+
+    #[test]
+    fn tokens_are_taken_from_the_header_the_protocol_or_the_cookie_in_that_order() {
+        let cookie = format!("theme=dark; {}=from-cookie", TOKEN_COOKIE);
+        let sources = [
+            (header::AUTHORIZATION, "Bearer from-header"),
+            (header::SEC_WEBSOCKET_PROTOCOL, "bearer, from-protocol"),
+            (header::COOKIE, cookie.as_str()),
+        ];
+        let expected = [
+            ("from-header", TokenSource::Authorization),
+            ("from-protocol", TokenSource::WebSocketProtocol),
+            ("from-cookie", TokenSource::Cookie),
+        ];
+
+        for skip in 0..sources.len() {
+            let headers = headers(sources[skip..].iter().cloned());
+            let (token, source) = expected[skip];
+            assert_eq!(
+                AuthenticationActor::extract_token_with_source(&headers),
+                Some((Token(token.to_string()), source))
+            );
+        }
+        assert_eq!(
+            AuthenticationActor::extract_token_with_source(&HeaderMap::new()),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn either_of_two_hashes_authenticates() {
+        let hashes = [hash("old password"), hash("new password")];
+        let mut actor = actor(&[("alice", &hashes)], no_login_limits());
+
+        for password in ["old password", "new password"] {
+            let outcome = actor
+                .authenticate(credentials("alice", password), REMOTE_IP)
+                .await;
+            assert!(matches!(outcome, LoginOutcome::Granted(_)), "{:?}", outcome);
+        }
+        let outcome = actor
+            .authenticate(credentials("alice", "other password"), REMOTE_IP)
+            .await;
+        assert!(matches!(outcome, LoginOutcome::Denied), "{:?}", outcome);
+    }
+
+    #[tokio::test]
+    async fn each_user_logs_in_with_their_own_password() {
+        let (alice, bob) = ([hash("alice password")], [hash("bob password")]);
+        let mut actor = actor(&[("alice", &alice), ("bob", &bob)], no_login_limits());
+
+        for (username, password, granted) in [
+            ("alice", "alice password", true),
+            ("bob", "bob password", true),
+            ("alice", "bob password", false),
+            ("bob", "alice password", false),
+            ("carol", "alice password", false),
+        ] {
+            let outcome = actor
+                .authenticate(credentials(username, password), REMOTE_IP)
+                .await;
+            assert_eq!(
+                matches!(outcome, LoginOutcome::Granted(_)),
+                granted,
+                "{} with {:?}: {:?}",
+                username,
+                password,
+                outcome
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_users_are_verified_at_the_configured_cost() {
+        let hashes = [hash("password")];
+        let mut actor = actor(&[("alice", &hashes)], no_login_limits());
+
+        // the stand-in hash costs as much to verify as the hash of a known user
+        let costs = |hash: &str| {
+            let params = Params::try_from(&PasswordHash::new(hash).unwrap()).unwrap();
+            (params.m_cost(), params.t_cost(), params.p_cost())
+        };
+        assert_eq!(costs(&actor.unknown_user_hash), costs(&hashes[0]));
+
+        assert!(actor.hashes_of("alice").is_some());
+        for username in ["", "alic", "alice ", "Alice", "alicea"] {
+            assert!(actor.hashes_of(username).is_none(), "{:?}", username);
+        }
+        // not even the password the stand-in was hashed from logs in
+        let outcome = actor
+            .authenticate(credentials("bob", "unknown user"), REMOTE_IP)
+            .await;
+        assert!(matches!(outcome, LoginOutcome::Denied), "{:?}", outcome);
+    }
+
+    #[tokio::test]
+    async fn only_hashes_with_other_costs_are_warned_about() {
+        let (logs, _guard) = crate::test_util::capture_logs();
+        let costlier =
+            authentication::argon2(Params::new(Params::MIN_M_COST * 2, 1, 1, None).unwrap())
+                .hash_password(
+                    b"bob password",
+                    &SaltString::generate(&mut rand_core::OsRng),
+                )
+                .unwrap()
+                .to_string();
+        let mut actor = actor(
+            &[("alice", &[hash("alice password")]), ("bob", &[costlier])],
+            no_login_limits(),
+        );
+        let warnings: Vec<_> = logs
+            .lines()
+            .into_iter()
+            .filter(|line| line.contains("uses other argon2 costs"))
+            .collect();
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        assert!(warnings[0].contains("of bob"));
+
+        // the hash is verified with its own costs
+        let outcome = actor
+            .authenticate(credentials("bob", "bob password"), REMOTE_IP)
+            .await;
+        assert!(matches!(outcome, LoginOutcome::Granted(_)), "{:?}", outcome);
+    }
+
+    #[tokio::test]
+    async fn repeated_failures_lock_out_even_the_right_password() {
+        let hashes = [hash("password")];
+        let lockout = Duration::from_millis(100);
+        let mut actor = actor(
+            &[("alice", &hashes)],
+            LoginLimits {
+                max_failures_per_user: 3,
+                max_failures_per_ip: 0,
+                failure_window: Duration::from_secs(60),
+                lockout,
+                max_lockout: lockout,
+            },
+        );
+
+        for _ in 0..2 {
+            let outcome = actor
+                .authenticate(credentials("alice", "guess"), REMOTE_IP)
+                .await;
+            assert!(matches!(outcome, LoginOutcome::Denied), "{:?}", outcome);
+        }
+        let outcome = actor
+            .authenticate(credentials("alice", "guess"), REMOTE_IP)
+            .await;
+        assert!(
+            matches!(outcome, LoginOutcome::LockedOut { retry_after } if retry_after == lockout),
+            "{:?}",
+            outcome
+        );
+        let outcome = actor
+            .authenticate(credentials("alice", "password"), REMOTE_IP)
+            .await;
+        assert!(
+            matches!(outcome, LoginOutcome::LockedOut { .. }),
+            "{:?}",
+            outcome
+        );
+
+        tokio::time::sleep(lockout).await;
+        let outcome = actor
+            .authenticate(credentials("alice", "password"), REMOTE_IP)
+            .await;
+        assert!(matches!(outcome, LoginOutcome::Granted(_)), "{:?}", outcome);
+    }
+
+    #[tokio::test]
+    async fn cookie_authenticated_posts_need_the_csrf_header() {
+        let hashes = [hash("password")];
+        let mut actor = actor(&[("alice", &hashes)], no_login_limits());
+        let LoginOutcome::Granted(Token(token)) = actor
+            .authenticate(credentials("alice", "password"), REMOTE_IP)
+            .await
+        else {
+            panic!("login failed");
+        };
+        let (sender, receiver) = mpsc::channel(8);
+        tokio::spawn(actor.run(receiver));
+        let app = Router::new()
+            .route("/backend/logout", post(async || ()))
+            .layer(middleware::from_fn({
+                let sender = sender.downgrade();
+                move |req, next| AuthenticationActor::auth_request(sender.clone(), req, next)
+            }));
+        let cookies = format!("{}={}; {}=csrf-value", TOKEN_COOKIE, token, CSRF_COOKIE);
+        let request = |csrf_header: Option<&str>| {
+            let request = Request::post("/backend/logout").header(header::COOKIE, &cookies);
+            match csrf_header {
+                Some(value) => request.header(CSRF_HEADER, value),
+                None => request,
+            }
+            .body(Body::empty())
+            .unwrap()
+        };
+
+        for csrf_header in [None, Some("other-value")] {
+            let response = app.clone().oneshot(request(csrf_header)).await.unwrap();
+            assert_eq!(
+                response.status(),
+                StatusCode::FORBIDDEN,
+                "{:?}",
+                csrf_header
+            );
+        }
+        let response = app
+            .clone()
+            .oneshot(request(Some("csrf-value")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let bearer_request = Request::post("/backend/logout")
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(bearer_request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn client_certificates_replace_passwords() {
+        let hashes = [hash("password")];
+        let mut actor = actor(&[("alice", &hashes)], no_login_limits());
+        actor.auth_mode = AuthMode::Mtls;
+        let outcome = actor
+            .authenticate(credentials("alice", "password"), REMOTE_IP)
+            .await;
+        assert!(matches!(outcome, LoginOutcome::Denied), "{:?}", outcome);
+        let LoginOutcome::Granted(Token(token)) =
+            actor.authenticate_client("bob".to_string()).await
+        else {
+            panic!("login failed");
+        };
+
+        let (sender, receiver) = mpsc::channel(8);
+        tokio::spawn(actor.run(receiver));
+        let app = Router::new()
+            .route("/backend/files", get(async || ()))
+            .layer(middleware::from_fn({
+                let sender = sender.downgrade();
+                move |req, next| AuthenticationActor::auth_request(sender.clone(), req, next)
+            }));
+        let request = |client_identity: Option<ClientIdentity>| {
+            let mut request = Request::get("/backend/files")
+                .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap();
+            if let Some(client_identity) = client_identity {
+                request.extensions_mut().insert(client_identity);
+            }
+            request
+        };
+
+        for client_identity in [None, Some(ClientIdentity(None))] {
+            let response = app.clone().oneshot(request(client_identity)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        let response = app
+            .oneshot(request(Some(ClientIdentity(Some("bob".to_string())))))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn certified_tokens_need_the_certificate_they_were_minted_for() {
+        let mut actor = actor(&[], no_login_limits());
+        actor.auth_mode = AuthMode::Mtls;
+        let LoginOutcome::Granted(token) = actor.authenticate_client("alice".to_string()).await
+        else {
+            panic!("expected a certified login to be granted");
+        };
+        let mut authenticate = async |common_name: Option<&str>| {
+            actor
+                .authenticate_request(
+                    Some(token.clone()),
+                    Uri::from_static("/backend/files"),
+                    common_name.map(str::to_string),
+                )
+                .await
+        };
+
+        assert_eq!(
+            authenticate(Some("alice")).await,
+            RequestAuthentication::Authorized
+        );
+        assert_eq!(
+            authenticate(Some("mallory")).await,
+            RequestAuthentication::Unauthorized
+        );
+        assert_eq!(
+            authenticate(None).await,
+            RequestAuthentication::Unauthorized
+        );
+    }
 }
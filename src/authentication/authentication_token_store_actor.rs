@@ -1,13 +1,27 @@
-use crate::authentication::{Deadline, Token, Username};
+use crate::{
+    authentication::{Deadline, SessionId, Token, TokenInfo, Username},
+    health::Heartbeat,
+    tokio_util,
+};
+use std::{
+    cmp::Reverse,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
 use tokio::{
     sync::{mpsc, oneshot},
     time::{Interval, MissedTickBehavior},
 };
 use tracing::instrument;
 
+#[allow(clippy::enum_variant_names)]
 pub enum AuthenticationTokenStoreActorEvent {
     CheckAndRefreshToken {
         token: Token,
+        /// Also accept a token that expired no longer than the refresh grace period ago.
+        within_grace: bool,
         response_sender: oneshot::Sender<bool>,
     },
     GetToken {
@@ -17,28 +31,119 @@ pub enum AuthenticationTokenStoreActorEvent {
     RevokeToken {
         token: Token,
     },
+    GetTokenInfo {
+        token: Token,
+        response_sender: oneshot::Sender<Option<TokenInfo>>,
+    },
+    /// Checks a token without extending its deadline.
+    PeekToken {
+        token: Token,
+        /// The remaining time to live, or `None` if the token is not valid.
+        response_sender: oneshot::Sender<Option<std::time::Duration>>,
+    },
+    RevokeSession {
+        username: Username,
+        session_id: SessionId,
+        /// The revoked token, or `None` if the user has no such session.
+        response_sender: oneshot::Sender<Option<Token>>,
+    },
+    ListSessions {
+        username: Username,
+        response_sender: oneshot::Sender<Vec<TokenInfo>>,
+    },
+    RevokeAllForUser {
+        username: Username,
+        /// The revoked tokens.
+        response_sender: oneshot::Sender<Vec<Token>>,
+    },
+}
+
+#[derive(Debug)]
+struct TokenOwner {
+    username: Username,
+    session_id: SessionId,
 }
 
 #[derive(Debug)]
 pub struct AuthenticationTokenStoreActor {
-    tokens: std::collections::HashMap<Token, Username>,
+    tokens: std::collections::HashMap<Token, TokenOwner>,
+    sessions: std::collections::HashMap<SessionId, Token>,
     token_deadlines:
         std::collections::HashMap<Username, std::collections::HashMap<Token, Deadline>>,
     cleanup_timer: Interval,
     auth_token_ttl: std::time::Duration,
+    auth_refresh_grace: std::time::Duration,
     auth_token_max_per_user: usize,
+    auth_token_max_users: usize,
+    token_count: Arc<AtomicUsize>,
+    login_count: Arc<AtomicUsize>,
+    heartbeat: Heartbeat,
+    heartbeat_timer: Interval,
 }
 
 impl AuthenticationTokenStoreActor {
-    fn do_check_and_refresh_token(&mut self, token: Token) -> bool {
-        if let Some(username) = self.tokens.get(&token) {
-            self.token_deadlines
-                .entry(username.clone())
-                .or_default()
-                .insert(token, Self::make_deadline(self.auth_token_ttl));
-            return true;
+    /// Shared number of stored tokens, readable without querying the actor.
+    pub fn token_count(&self) -> Arc<AtomicUsize> {
+        self.token_count.clone()
+    }
+
+    /// Shared running total of tokens handed out on login.
+    pub fn login_count(&self) -> Arc<AtomicUsize> {
+        self.login_count.clone()
+    }
+
+    /// Shared heartbeat, beaten from the run loop for the health check.
+    pub fn heartbeat(&self) -> Heartbeat {
+        self.heartbeat.clone()
+    }
+
+    fn deadline(&self, token: &Token) -> Option<(&TokenOwner, &Deadline)> {
+        let owner = self.tokens.get(token)?;
+        let deadline = self.token_deadlines.get(&owner.username)?.get(token)?;
+        Some((owner, deadline))
+    }
+
+    fn forget_token(&mut self, token: &Token) {
+        if let Some(owner) = self.tokens.remove(token) {
+            self.sessions.remove(&owner.session_id);
         }
-        false
+    }
+
+    fn do_check_and_refresh_token(&mut self, token: Token, within_grace: bool) -> bool {
+        let now = std::time::Instant::now();
+        let grace = if within_grace {
+            self.auth_refresh_grace
+        } else {
+            std::time::Duration::ZERO
+        };
+        let Some((owner, deadline)) = self.deadline(&token) else {
+            return false;
+        };
+        if deadline.0 + grace < now {
+            return false;
+        }
+        let username = owner.username.clone();
+        self.token_deadlines
+            .entry(username)
+            .or_default()
+            .insert(token, Self::make_deadline(self.auth_token_ttl));
+        true
+    }
+
+    fn do_peek_token(&self, token: &Token) -> Option<std::time::Duration> {
+        let (_, deadline) = self.deadline(token)?;
+        deadline.0.checked_duration_since(std::time::Instant::now())
+    }
+
+    fn do_get_token_info(&self, token: &Token) -> Option<TokenInfo> {
+        let ttl_remaining = self.do_peek_token(token)?;
+        let (owner, _) = self.deadline(token)?;
+        Some(TokenInfo {
+            username: owner.username.clone(),
+            session_id: owner.session_id.clone(),
+            ttl_secs_remaining: ttl_remaining.as_secs(),
+            expires_at: std::time::SystemTime::now() + ttl_remaining,
+        })
     }
 
     fn make_deadline(auth_token_ttl: std::time::Duration) -> Deadline {
@@ -46,37 +151,90 @@ impl AuthenticationTokenStoreActor {
     }
 
     async fn remove_token(&mut self, token: Token) {
-        self.tokens.remove(&token);
+        if let Some(owner) = self.tokens.remove(&token) {
+            self.sessions.remove(&owner.session_id);
+            if let Some(tokens) = self.token_deadlines.get_mut(&owner.username) {
+                tokens.remove(&token);
+                if tokens.is_empty() {
+                    self.token_deadlines.remove(&owner.username);
+                }
+            }
+        }
+    }
+
+    /// Makes room for a new user by dropping the one whose sessions were refreshed least recently.
+    fn evict_least_recently_active_user(&mut self) {
+        let least_recently_active = self
+            .token_deadlines
+            .iter()
+            .min_by_key(|(_, tokens)| tokens.values().max().map(|deadline| deadline.0))
+            .map(|(username, _)| username.clone());
+
+        if let Some(username) = least_recently_active
+            && let Some(tokens) = self.token_deadlines.remove(&username)
+        {
+            tracing::warn!(
+                "Token store tracks {} users, evicting {} tokens of {}",
+                self.auth_token_max_users,
+                tokens.len(),
+                username
+            );
+            for token in tokens.into_keys() {
+                self.forget_token(&token);
+            }
+        }
     }
 
     #[instrument(level = "trace")]
     async fn cleanup(&mut self) {
         let now = std::time::Instant::now();
 
+        let mut dropped = Vec::new();
         for (_, tokens) in self.token_deadlines.iter_mut() {
             let mut survivors = Vec::new();
             for (token, deadline) in tokens.drain() {
-                if deadline.0 < now {
-                    self.tokens.remove(&token);
+                // expired tokens are kept for the grace period so they can still be refreshed
+                if deadline.0 + self.auth_refresh_grace < now {
+                    dropped.push(token);
                 } else {
                     survivors.push((token, deadline));
                 }
             }
-            if survivors.len() >= self.auth_token_max_per_user {
-                survivors.sort_by_key(|(_, deadline)| deadline.0);
-                for (token, _) in survivors.drain(self.auth_token_max_per_user..) {
-                    self.tokens.remove(&token);
-                }
+            if survivors.len() > self.auth_token_max_per_user {
+                // keep the most recently refreshed tokens, evict the ones closest to expiry
+                survivors.sort_by_key(|(_, deadline)| Reverse(deadline.0));
+                dropped.extend(
+                    survivors
+                        .drain(self.auth_token_max_per_user..)
+                        .map(|(token, _)| token),
+                );
             }
             *tokens = survivors.drain(..).collect();
         }
 
+        for token in &dropped {
+            self.forget_token(token);
+        }
         self.token_deadlines.retain(|_, tokens| !tokens.is_empty());
     }
 
     async fn do_get_token(&mut self, username: Username) -> Token {
+        if !self.token_deadlines.contains_key(&username)
+            && self.token_deadlines.len() >= self.auth_token_max_users
+        {
+            self.evict_least_recently_active_user();
+        }
+        self.login_count.fetch_add(1, Ordering::Relaxed);
         let token = Token::generate();
-        self.tokens.insert(token.clone(), username.clone());
+        let session_id = SessionId::generate();
+        self.sessions.insert(session_id.clone(), token.clone());
+        self.tokens.insert(
+            token.clone(),
+            TokenOwner {
+                username: username.clone(),
+                session_id,
+            },
+        );
         self.token_deadlines
             .entry(username)
             .or_default()
@@ -84,6 +242,45 @@ impl AuthenticationTokenStoreActor {
         token
     }
 
+    /// Revokes the session if it belongs to `username`, so users can only end their own.
+    async fn do_revoke_session(
+        &mut self,
+        username: &Username,
+        session_id: &SessionId,
+    ) -> Option<Token> {
+        let token = self.sessions.get(session_id)?;
+        if &self.tokens.get(token)?.username != username {
+            return None;
+        }
+        let token = token.clone();
+        self.remove_token(token.clone()).await;
+        Some(token)
+    }
+
+    fn do_revoke_all_for_user(&mut self, username: &Username) -> Vec<Token> {
+        let Some(tokens) = self.token_deadlines.remove(username) else {
+            return Vec::new();
+        };
+        let tokens: Vec<_> = tokens.into_keys().collect();
+        for token in &tokens {
+            self.forget_token(token);
+        }
+        tokens
+    }
+
+    /// The user's sessions that have not expired, the most recently refreshed first.
+    fn do_list_sessions(&self, username: &Username) -> Vec<TokenInfo> {
+        let mut sessions: Vec<_> = self
+            .token_deadlines
+            .get(username)
+            .into_iter()
+            .flat_map(|tokens| tokens.keys())
+            .filter_map(|token| self.do_get_token_info(token))
+            .collect();
+        sessions.sort_by_key(|session| Reverse(session.expires_at));
+        sessions
+    }
+
     #[instrument(level = "trace")]
     pub async fn run(mut self, mut receiver: mpsc::Receiver<AuthenticationTokenStoreActorEvent>) {
         loop {
@@ -91,9 +288,9 @@ impl AuthenticationTokenStoreActor {
                 msg = receiver.recv() => match msg {
                     Some(msg) => {
                         match msg {
-                            AuthenticationTokenStoreActorEvent::CheckAndRefreshToken { token, response_sender} => {
+                            AuthenticationTokenStoreActorEvent::CheckAndRefreshToken { token, within_grace, response_sender} => {
                               let _ = response_sender
-                                    .send(self.do_check_and_refresh_token(token.clone()))
+                                    .send(self.do_check_and_refresh_token(token.clone(), within_grace))
                                     .inspect_err(|e| {tracing::error!("Error responding to AuthenticatorEvent::RefreshToken: {:?}", e)});
 
                             },
@@ -103,6 +300,21 @@ impl AuthenticationTokenStoreActor {
                             AuthenticationTokenStoreActorEvent::RevokeToken { token } => {
                                 self.remove_token(token).await;
                             }
+                            AuthenticationTokenStoreActorEvent::GetTokenInfo { token, response_sender } => {
+                                let _ = response_sender.send(self.do_get_token_info(&token)).inspect_err(|e| {tracing::error!("Error responding to AuthenticatorEvent::GetTokenInfo: {:?}", e)});
+                            }
+                            AuthenticationTokenStoreActorEvent::PeekToken { token, response_sender } => {
+                                let _ = response_sender.send(self.do_peek_token(&token)).inspect_err(|e| {tracing::error!("Error responding to AuthenticatorEvent::PeekToken: {:?}", e)});
+                            }
+                            AuthenticationTokenStoreActorEvent::RevokeSession { username, session_id, response_sender } => {
+                                let _ = response_sender.send(self.do_revoke_session(&username, &session_id).await).inspect_err(|e| {tracing::error!("Error responding to AuthenticatorEvent::RevokeSession: {:?}", e)});
+                            }
+                            AuthenticationTokenStoreActorEvent::ListSessions { username, response_sender } => {
+                                let _ = response_sender.send(self.do_list_sessions(&username)).inspect_err(|e| {tracing::error!("Error responding to AuthenticatorEvent::ListSessions: {:?}", e)});
+                            }
+                            AuthenticationTokenStoreActorEvent::RevokeAllForUser { username, response_sender } => {
+                                let _ = response_sender.send(self.do_revoke_all_for_user(&username)).inspect_err(|e| {tracing::error!("Error responding to AuthenticatorEvent::RevokeAllForUser: {:?}", e)});
+                            }
                         }
                     },
                     None => break,
@@ -110,8 +322,16 @@ impl AuthenticationTokenStoreActor {
                 _ = self.cleanup_timer.tick() => {
                     self.cleanup().await;
                 }
+                _ = self.heartbeat_timer.tick() => self.heartbeat.beat(),
             }
+            self.token_count.store(self.tokens.len(), Ordering::Relaxed);
         }
+
+        tracing::info!(
+            tokens = self.tokens.len(),
+            users = self.token_deadlines.len(),
+            "authentication token store shut down"
+        );
     }
 
     pub async fn check_and_refresh_token(
@@ -121,10 +341,27 @@ impl AuthenticationTokenStoreActor {
         let (response_sender, response_receiver) = oneshot::channel();
         let message = AuthenticationTokenStoreActorEvent::CheckAndRefreshToken {
             token,
+            within_grace: false,
             response_sender,
         };
         sender.send(message).await?;
-        Ok(response_receiver.await?)
+        tokio_util::receive_response(response_receiver).await
+    }
+
+    /// Refreshes a token like `check_and_refresh_token`, but also revives one that expired
+    /// within the refresh grace period.
+    pub async fn refresh_token(
+        sender: &mut mpsc::Sender<AuthenticationTokenStoreActorEvent>,
+        token: Token,
+    ) -> crate::error::Result<bool> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        let message = AuthenticationTokenStoreActorEvent::CheckAndRefreshToken {
+            token,
+            within_grace: true,
+            response_sender,
+        };
+        sender.send(message).await?;
+        tokio_util::receive_response(response_receiver).await
     }
 
     pub async fn get_token(
@@ -137,7 +374,7 @@ impl AuthenticationTokenStoreActor {
             response_sender,
         };
         sender.send(message).await?;
-        Ok(response_receiver.await?)
+        tokio_util::receive_response(response_receiver).await
     }
 
     pub async fn revoke_token(
@@ -149,22 +386,283 @@ impl AuthenticationTokenStoreActor {
         Ok(())
     }
 
+    pub async fn revoke_session(
+        sender: &mpsc::Sender<AuthenticationTokenStoreActorEvent>,
+        username: Username,
+        session_id: SessionId,
+    ) -> crate::error::Result<Option<Token>> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        let message = AuthenticationTokenStoreActorEvent::RevokeSession {
+            username,
+            session_id,
+            response_sender,
+        };
+        sender.send(message).await?;
+        tokio_util::receive_response(response_receiver).await
+    }
+
+    pub async fn revoke_all_for_user(
+        sender: &mpsc::Sender<AuthenticationTokenStoreActorEvent>,
+        username: Username,
+    ) -> crate::error::Result<Vec<Token>> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        let message = AuthenticationTokenStoreActorEvent::RevokeAllForUser {
+            username,
+            response_sender,
+        };
+        sender.send(message).await?;
+        tokio_util::receive_response(response_receiver).await
+    }
+
+    pub async fn list_sessions(
+        sender: &mpsc::Sender<AuthenticationTokenStoreActorEvent>,
+        username: Username,
+    ) -> crate::error::Result<Vec<TokenInfo>> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        let message = AuthenticationTokenStoreActorEvent::ListSessions {
+            username,
+            response_sender,
+        };
+        sender.send(message).await?;
+        tokio_util::receive_response(response_receiver).await
+    }
+
+    /// Returns the remaining time to live of a valid token, leaving its deadline untouched.
+    pub async fn peek_token(
+        sender: &mpsc::Sender<AuthenticationTokenStoreActorEvent>,
+        token: Token,
+    ) -> crate::error::Result<Option<std::time::Duration>> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        let message = AuthenticationTokenStoreActorEvent::PeekToken {
+            token,
+            response_sender,
+        };
+        sender.send(message).await?;
+        tokio_util::receive_response(response_receiver).await
+    }
+
+    pub async fn get_token_info(
+        sender: &mpsc::Sender<AuthenticationTokenStoreActorEvent>,
+        token: Token,
+    ) -> crate::error::Result<Option<TokenInfo>> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        let message = AuthenticationTokenStoreActorEvent::GetTokenInfo {
+            token,
+            response_sender,
+        };
+        sender.send(message).await?;
+        tokio_util::receive_response(response_receiver).await
+    }
+
     pub fn new(
         auth_token_cleanup_interval: std::time::Duration,
         auth_token_ttl: std::time::Duration,
+        auth_refresh_grace: std::time::Duration,
         auth_token_max_per_user: usize,
+        auth_token_max_users: usize,
     ) -> Self {
         let tokens = std::collections::HashMap::new();
+        let sessions = std::collections::HashMap::new();
         let token_deadlines = std::collections::HashMap::new();
         let mut cleanup_timer = tokio::time::interval(auth_token_cleanup_interval);
         // continue with intended interval even if the timer is missed
         cleanup_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
         Self {
             tokens,
+            sessions,
             token_deadlines,
             cleanup_timer,
             auth_token_ttl,
+            auth_refresh_grace,
             auth_token_max_per_user,
+            auth_token_max_users,
+            token_count: Arc::new(AtomicUsize::new(0)),
+            login_count: Arc::new(AtomicUsize::new(0)),
+            heartbeat: Heartbeat::default(),
+            heartbeat_timer: Heartbeat::timer(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl AuthenticationTokenStoreActor {
+    /// A store whose cleanup only runs when a test calls `cleanup_now`.
+    pub(crate) fn for_test(
+        auth_token_ttl: std::time::Duration,
+        auth_refresh_grace: std::time::Duration,
+        auth_token_max_per_user: usize,
+        auth_token_max_users: usize,
+    ) -> Self {
+        let mut store = Self::new(
+            std::time::Duration::from_secs(3600),
+            auth_token_ttl,
+            auth_refresh_grace,
+            auth_token_max_per_user,
+            auth_token_max_users,
+        );
+        store.cleanup_timer.reset();
+        store
+    }
+
+    pub(crate) async fn cleanup_now(&mut self) {
+        self.cleanup().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn cleanup_drops_tokens_expired_beyond_the_grace_period() {
+        let mut store = AuthenticationTokenStoreActor::for_test(
+            std::time::Duration::ZERO,
+            std::time::Duration::ZERO,
+            usize::MAX,
+            usize::MAX,
+        );
+        let token = store.do_get_token(Username("alice".to_string())).await;
+        std::thread::sleep(std::time::Duration::from_millis(1));
+
+        store.cleanup_now().await;
+
+        assert!(store.do_get_token_info(&token).is_none());
+        assert!(store.tokens.is_empty());
+        assert!(store.sessions.is_empty());
+        assert!(store.token_deadlines.is_empty());
+    }
+
+    #[tokio::test]
+    async fn expired_tokens_only_refresh_within_the_grace_period() {
+        let grace = std::time::Duration::from_millis(200);
+        let mut store =
+            AuthenticationTokenStoreActor::for_test(std::time::Duration::ZERO, grace, 2, 2);
+        let alice = Username("alice".to_string());
+        let token = store.do_get_token(alice.clone()).await;
+        std::thread::sleep(std::time::Duration::from_millis(1));
+
+        // expired, so no longer good for requests, but kept around and revivable
+        assert!(!store.do_check_and_refresh_token(token.clone(), false));
+        assert!(store.do_get_token_info(&token).is_none());
+        store.cleanup_now().await;
+        assert!(store.do_check_and_refresh_token(token.clone(), true));
+
+        let late_token = store.do_get_token(alice).await;
+        std::thread::sleep(grace + std::time::Duration::from_millis(1));
+        assert!(!store.do_check_and_refresh_token(late_token.clone(), true));
+        store.cleanup_now().await;
+        assert!(!store.tokens.contains_key(&late_token));
+    }
+
+    #[tokio::test]
+    async fn cleanup_evicts_the_soonest_expiring_tokens_beyond_the_cap() {
+        let ttl = std::time::Duration::from_secs(3600);
+        let mut store =
+            AuthenticationTokenStoreActor::for_test(ttl, std::time::Duration::ZERO, 2, usize::MAX);
+        let alice = Username("alice".to_string());
+        let mut tokens = Vec::new();
+        for _ in 0..3 {
+            tokens.push(store.do_get_token(alice.clone()).await);
+            // deadlines are instants, which have to tell the tokens apart
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        // the oldest token is the most recently refreshed one now
+        assert!(store.do_check_and_refresh_token(tokens[0].clone(), false));
+
+        store.cleanup_now().await;
+
+        assert!(store.do_get_token_info(&tokens[0]).is_some());
+        assert!(store.do_get_token_info(&tokens[1]).is_none());
+        assert!(store.do_get_token_info(&tokens[2]).is_some());
+        assert_eq!(store.tokens.len(), 2);
+        assert_eq!(store.sessions.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn churning_usernames_keeps_the_maps_bounded() {
+        let mut store = AuthenticationTokenStoreActor::for_test(
+            std::time::Duration::from_secs(3600),
+            std::time::Duration::ZERO,
+            usize::MAX,
+            3,
+        );
+        let mut last_token = None;
+        for user in 0..100 {
+            last_token = Some(store.do_get_token(Username(format!("user{}", user))).await);
+        }
+        assert_eq!(store.token_deadlines.len(), 3);
+        assert_eq!(store.tokens.len(), 3);
+        assert_eq!(store.sessions.len(), 3);
+
+        // revoking a user's last token drops the user without waiting for a cleanup
+        store.remove_token(last_token.unwrap()).await;
+        assert_eq!(store.token_deadlines.len(), 2);
+        assert_eq!(store.tokens.len(), 2);
+        assert_eq!(store.sessions.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn revoking_one_of_three_sessions_leaves_two() {
+        let mut store = AuthenticationTokenStoreActor::for_test(
+            std::time::Duration::from_secs(3600),
+            std::time::Duration::ZERO,
+            usize::MAX,
+            usize::MAX,
+        );
+        let alice = Username("alice".to_string());
+        let bob = Username("bob".to_string());
+        let mut tokens = Vec::new();
+        for _ in 0..3 {
+            tokens.push(store.do_get_token(alice.clone()).await);
+        }
+        let sessions = store.do_list_sessions(&alice);
+        assert_eq!(sessions.len(), 3);
+        let listing = serde_json::to_string(&sessions).unwrap();
+        assert!(tokens.iter().all(|token| !listing.contains(&token.0)));
+
+        let session_id = store.do_get_token_info(&tokens[1]).unwrap().session_id;
+        // users can only end their own sessions
+        assert!(store.do_revoke_session(&bob, &session_id).await.is_none());
+        assert_eq!(
+            store.do_revoke_session(&alice, &session_id).await,
+            Some(tokens[1].clone())
+        );
+
+        let sessions = store.do_list_sessions(&alice);
+        assert_eq!(sessions.len(), 2);
+        assert!(
+            sessions
+                .iter()
+                .all(|session| session.session_id != session_id)
+        );
+        assert!(store.do_get_token_info(&tokens[1]).is_none());
+    }
+
+    #[tokio::test]
+    async fn revoking_all_sessions_of_a_user_spares_other_users() {
+        let mut store = AuthenticationTokenStoreActor::for_test(
+            std::time::Duration::from_secs(3600),
+            std::time::Duration::ZERO,
+            usize::MAX,
+            usize::MAX,
+        );
+        let alice = Username("alice".to_string());
+        let mut alice_tokens = Vec::new();
+        for _ in 0..3 {
+            alice_tokens.push(store.do_get_token(alice.clone()).await);
+        }
+        let bob_token = store.do_get_token(Username("bob".to_string())).await;
+
+        let mut revoked = store.do_revoke_all_for_user(&alice);
+        revoked.sort_by(|a, b| a.0.cmp(&b.0));
+        alice_tokens.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(revoked, alice_tokens);
+
+        for token in alice_tokens {
+            assert!(!store.do_check_and_refresh_token(token, true));
         }
+        assert!(store.do_check_and_refresh_token(bob_token, false));
+        assert!(store.do_list_sessions(&alice).is_empty());
+        assert_eq!(store.sessions.len(), 1);
     }
 }
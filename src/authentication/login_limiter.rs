@@ -0,0 +1,123 @@
+use std::{collections::HashMap, net::IpAddr, time::Duration};
+use tokio::time::Instant;
+
+/// When repeated failed logins lock out further attempts.
+#[derive(Clone, Debug)]
+pub struct LoginLimits {
+    /// Failures for one username within the window that lock it out, zero never does.
+    pub max_failures_per_user: u32,
+    /// Failures from one address within the window that lock it out, zero never does. Behind a
+    /// proxy every client shares the proxy's address.
+    pub max_failures_per_ip: u32,
+    pub failure_window: Duration,
+    /// The first lockout, doubled with each further one up to `max_lockout`.
+    pub lockout: Duration,
+    pub max_lockout: Duration,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+enum Source {
+    User(String),
+    Ip(IpAddr),
+}
+
+#[derive(Debug)]
+struct Failures {
+    /// Failures since `window_start`.
+    count: u32,
+    window_start: Instant,
+    last_failure: Instant,
+    /// Lockouts so far, each one longer than the last.
+    lockouts: u32,
+    locked_until: Option<Instant>,
+}
+
+/// Counts failed logins per username and per source address and locks out either one once it
+/// fails too often.
+#[derive(Debug)]
+pub struct LoginLimiter {
+    limits: LoginLimits,
+    failures: HashMap<Source, Failures>,
+}
+
+impl LoginLimiter {
+    pub fn new(limits: LoginLimits) -> Self {
+        Self {
+            limits,
+            failures: HashMap::new(),
+        }
+    }
+
+    /// The remaining lockout of the username or the address, whichever ends later.
+    pub fn locked_out(&self, username: &str, ip: IpAddr) -> Option<Duration> {
+        let now = Instant::now();
+        [Source::User(username.to_string()), Source::Ip(ip)]
+            .iter()
+            .filter_map(|source| self.failures.get(source)?.locked_until)
+            .filter_map(|locked_until| locked_until.checked_duration_since(now))
+            .filter(|remaining| !remaining.is_zero())
+            .max()
+    }
+
+    /// Counts a failed login, returning the lockout it started if any.
+    pub fn record_failure(&mut self, username: &str, ip: IpAddr) -> Option<Duration> {
+        let now = Instant::now();
+        let mut started_lockout = None;
+        for (source, max_failures) in [
+            (
+                Source::User(username.to_string()),
+                self.limits.max_failures_per_user,
+            ),
+            (Source::Ip(ip), self.limits.max_failures_per_ip),
+        ] {
+            if max_failures == 0 {
+                continue;
+            }
+            let failures = self.failures.entry(source).or_insert(Failures {
+                count: 0,
+                window_start: now,
+                last_failure: now,
+                lockouts: 0,
+                locked_until: None,
+            });
+            if now.duration_since(failures.window_start) > self.limits.failure_window {
+                failures.count = 0;
+                failures.window_start = now;
+            }
+            failures.count += 1;
+            failures.last_failure = now;
+            if failures.count >= max_failures {
+                let lockout = self
+                    .limits
+                    .lockout
+                    .saturating_mul(2u32.saturating_pow(failures.lockouts))
+                    .min(self.limits.max_lockout);
+                failures.count = 0;
+                failures.window_start = now;
+                failures.lockouts += 1;
+                failures.locked_until = Some(now + lockout);
+                started_lockout = started_lockout.max(Some(lockout));
+            }
+        }
+        started_lockout
+    }
+
+    /// Clears the failures of the username. Those of the address stay, so knowing one password
+    /// does not reset guessing others.
+    pub fn record_success(&mut self, username: &str) {
+        self.failures.remove(&Source::User(username.to_string()));
+    }
+
+    /// Forgets usernames and addresses without failures for longer than a window or the longest
+    /// lockout, which also resets their escalation.
+    pub fn forget_idle(&mut self) {
+        let now = Instant::now();
+        let idle_after = self.limits.failure_window.max(self.limits.max_lockout);
+        self.failures.retain(|_, failures| {
+            failures
+                .locked_until
+                .is_some_and(|locked_until| locked_until > now)
+                || now.duration_since(failures.last_failure) < idle_after
+        });
+    }
+}
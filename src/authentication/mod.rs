@@ -1,5 +1,12 @@
 pub mod authentication_actor;
 pub mod authentication_token_store_actor;
+pub mod login_limiter;
+pub mod public_paths;
+pub mod url_signer;
+
+use serde::Serialize;
+use serde_with::{TimestampMilliSeconds, serde_as};
+use std::time::SystemTime;
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct Token(pub String);
@@ -10,8 +17,54 @@ impl Token {
     }
 }
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+/// Identifies a session to its user without revealing the token behind it.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize)]
+pub struct SessionId(pub String);
+
+impl SessionId {
+    pub fn generate() -> Self {
+        Self(uuid::Uuid::new_v4().into())
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize)]
 pub struct Username(String);
 
+impl Username {
+    /// Whether this user may use the admin endpoints; an empty `admin_users` admits everyone.
+    pub fn is_admin(&self, admin_users: &[String]) -> bool {
+        admin_users.is_empty() || admin_users.contains(&self.0)
+    }
+}
+
+impl std::fmt::Display for Username {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 #[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
 struct Deadline(std::time::Instant);
+
+#[serde_as]
+#[derive(Clone, Debug, Serialize)]
+pub struct TokenInfo {
+    pub username: Username,
+    pub session_id: SessionId,
+    pub ttl_secs_remaining: u64,
+    /// When the token expires unless refreshed again, in milliseconds since the epoch.
+    #[serde_as(as = "TimestampMilliSeconds<i64>")]
+    pub expires_at: SystemTime,
+}
+
+/// The Argon2id hasher with the configured costs. Verifying still uses the costs recorded in each
+/// hash, so this matters for hashes produced here.
+pub fn argon2(params: argon2::Params) -> argon2::Argon2<'static> {
+    argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+}
+
+/// Whether `path` is `prefix` itself or lies below it.
+pub fn is_path_under(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
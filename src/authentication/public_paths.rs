@@ -0,0 +1,94 @@
+use crate::error::{Error, Result};
+use regex::RegexSet;
+
+/// Paths of the backend served without a token. Everything outside `/backend` and the data route
+/// is public regardless.
+pub const DEFAULT_PUBLIC_PATHS: &[&str] = &[
+    "/backend/login",
+    "/backend/refresh",
+    "/backend/frontend_hash",
+];
+
+/// Matches request paths against exact paths and globs, where `*` and `?` stay within one path
+/// segment and `**` spans segments.
+#[derive(Debug)]
+pub struct PublicPaths(RegexSet);
+
+impl PublicPaths {
+    pub fn new<'a>(patterns: impl IntoIterator<Item = &'a str>) -> Result<Self> {
+        let regexes: Vec<_> = patterns.into_iter().map(glob_to_regex).collect();
+        let regex_set = RegexSet::new(&regexes)
+            .map_err(|e| Error::ConfigError(format!("PUBLIC_PATHS is invalid: {}", e)))?;
+        Ok(Self(regex_set))
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        self.0.is_match(path)
+    }
+}
+
+pub fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.next_if_eq(&'*').is_some() => regex.push_str(".*"),
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_paths_match_only_themselves() {
+        let public_paths = PublicPaths::new(["/backend/login", "/backend/a.b"]).unwrap();
+        assert!(public_paths.matches("/backend/login"));
+        assert!(public_paths.matches("/backend/a.b"));
+        for path in [
+            "/backend/login/",
+            "/backend/logins",
+            "/backend/aXb",
+            "/x/backend/login",
+        ] {
+            assert!(!public_paths.matches(path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn globs_respect_path_segments() {
+        let public_paths =
+            PublicPaths::new(["/backend/share/*.jpg", "/backend/v?", "/backend/open/**"]).unwrap();
+        for path in [
+            "/backend/share/a.jpg",
+            "/backend/share/.jpg",
+            "/backend/v1",
+            "/backend/open/",
+            "/backend/open/a/b/c",
+        ] {
+            assert!(public_paths.matches(path), "{}", path);
+        }
+        for path in [
+            "/backend/share/a/b.jpg",
+            "/backend/share/a.png",
+            "/backend/v",
+            "/backend/v12",
+            "/backend/v/",
+            "/backend/open",
+        ] {
+            assert!(!public_paths.matches(path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn no_patterns_match_nothing() {
+        let public_paths = PublicPaths::new([]).unwrap();
+        assert!(!public_paths.matches("/backend/login"));
+    }
+}
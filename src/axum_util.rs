@@ -1,7 +1,112 @@
 use axum::{
-    http::StatusCode,
-    response::{Html, IntoResponse},
+    BoxError, Json, Router,
+    body::Body,
+    error_handling::HandleErrorLayer,
+    extract::{ConnectInfo, State},
+    http::{Request, StatusCode, header},
+    middleware::Next,
+    response::{Html, IntoResponse, Response},
 };
+use ipnet::IpNet;
+use serde::Serialize;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tower::{ServiceBuilder, limit::GlobalConcurrencyLimitLayer};
+
+/// An error answered with a JSON body, `{ "error": <code>, "message": <text> }`, whose code stays
+/// stable so clients can tell the cases apart.
+#[derive(Debug)]
+pub enum ApiError {
+    InvalidCredentials,
+    TooManyLoginAttempts {
+        retry_after: Duration,
+    },
+    Unauthorized,
+    Forbidden,
+    CsrfTokenMismatch,
+    BadRequest,
+    /// Carries why the extensions were rejected.
+    InvalidFileExtensions(String),
+    UnknownSession,
+    ServiceBusy,
+    ServiceUnavailable,
+    ServiceRestarting,
+}
+
+#[derive(Serialize)]
+struct ApiErrorBody<'a> {
+    error: &'static str,
+    message: &'a str,
+}
+
+impl ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidCredentials | ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::TooManyLoginAttempts { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Forbidden | ApiError::CsrfTokenMismatch => StatusCode::FORBIDDEN,
+            ApiError::BadRequest | ApiError::InvalidFileExtensions(_) => StatusCode::BAD_REQUEST,
+            ApiError::UnknownSession => StatusCode::NOT_FOUND,
+            ApiError::ServiceBusy | ApiError::ServiceUnavailable | ApiError::ServiceRestarting => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::InvalidCredentials => "invalid_credentials",
+            ApiError::TooManyLoginAttempts { .. } => "too_many_login_attempts",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::Forbidden => "forbidden",
+            ApiError::CsrfTokenMismatch => "csrf_token_mismatch",
+            ApiError::BadRequest => "bad_request",
+            ApiError::InvalidFileExtensions(_) => "invalid_file_extensions",
+            ApiError::UnknownSession => "unknown_session",
+            ApiError::ServiceBusy => "service_busy",
+            ApiError::ServiceUnavailable => "service_unavailable",
+            ApiError::ServiceRestarting => "service_restarting",
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            ApiError::InvalidCredentials => "Invalid credentials",
+            ApiError::TooManyLoginAttempts { .. } => "Too many failed logins",
+            ApiError::Unauthorized => "Unauthorized",
+            ApiError::Forbidden => "Forbidden",
+            ApiError::CsrfTokenMismatch => "X-CSRF-Token does not match the csrf cookie",
+            ApiError::BadRequest => "Bad request",
+            ApiError::InvalidFileExtensions(message) => message,
+            ApiError::UnknownSession => "Unknown session",
+            ApiError::ServiceBusy => "Service busy",
+            ApiError::ServiceUnavailable => "Service unavailable",
+            ApiError::ServiceRestarting => "Service restarting",
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = Json(ApiErrorBody {
+            error: self.code(),
+            message: self.message(),
+        });
+        match &self {
+            ApiError::TooManyLoginAttempts { retry_after } => {
+                // rounded up so a client waiting this long is let through
+                let retry_after_secs =
+                    retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                (
+                    self.status_code(),
+                    [(header::RETRY_AFTER, retry_after_secs.to_string())],
+                    body,
+                )
+                    .into_response()
+            }
+            _ => (self.status_code(), body).into_response(),
+        }
+    }
+}
 
 pub async fn not_found() -> impl IntoResponse {
     tracing::debug!("Not found");
@@ -24,6 +129,137 @@ pub async fn not_found() -> impl IntoResponse {
     )
 }
 
-pub async fn empty_response() -> impl IntoResponse {
-    "".into_response()
+pub async fn handle_overload(err: BoxError) -> impl IntoResponse {
+    if err.is::<tower::load_shed::error::Overloaded>() {
+        tracing::warn!("Request shed due to overload");
+        (StatusCode::SERVICE_UNAVAILABLE, "Service overloaded")
+    } else {
+        tracing::error!("Unhandled middleware error: {}", err);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+    }
+}
+
+/// Answers requests beyond `max_concurrent_requests` in flight with 503 instead of queueing them.
+/// The limit is shared by all routes; zero disables it.
+pub fn shed_load(router: Router, max_concurrent_requests: usize) -> Router {
+    let max_concurrent_requests = if max_concurrent_requests == 0 {
+        tokio::sync::Semaphore::MAX_PERMITS
+    } else {
+        max_concurrent_requests
+    };
+    router.layer(
+        ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(handle_overload))
+            .load_shed()
+            .layer(GlobalConcurrencyLimitLayer::new(max_concurrent_requests)),
+    )
+}
+
+/// Answers requests for dotfiles or anything below a dot-directory with 404, so files like a
+/// `.env` placed in the served directory are never exposed.
+pub async fn reject_hidden_paths(req: Request<Body>, next: Next) -> Response {
+    let is_hidden = percent_encoding::percent_decode_str(req.uri().path())
+        .decode_utf8_lossy()
+        .split('/')
+        .any(|segment| segment.starts_with('.'));
+    if is_hidden {
+        not_found().await.into_response()
+    } else {
+        next.run(req).await
+    }
+}
+
+/// Answers requests from addresses outside the allowlist with 403, before anything else runs.
+pub async fn restrict_to_allowlist(
+    State(ip_allowlist): State<Arc<Vec<IpNet>>>,
+    ConnectInfo(remote_address): ConnectInfo<SocketAddr>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    // IPv4 clients of a dual-stack listener show up as IPv4-mapped IPv6 addresses
+    let ip = remote_address.ip().to_canonical();
+    if ip_allowlist.iter().any(|net| net.contains(&ip)) {
+        next.run(req).await
+    } else {
+        tracing::warn!("Rejecting request from {} outside the IP allowlist", ip);
+        (StatusCode::FORBIDDEN, "Forbidden").into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use tokio::sync::Notify;
+    use tower::ServiceExt;
+
+    fn get_request(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn requests_beyond_the_limit_get_503() {
+        let entered = Arc::new(Notify::new());
+        let release = Arc::new(Notify::new());
+        let app = Router::new()
+            .route(
+                "/slow",
+                get({
+                    let entered = entered.clone();
+                    let release = release.clone();
+                    async move || {
+                        entered.notify_one();
+                        release.notified().await;
+                    }
+                }),
+            )
+            .route("/fast", get(async || "fast"));
+        let app = shed_load(app, 1);
+
+        let slow = tokio::spawn(app.clone().oneshot(get_request("/slow")));
+        entered.notified().await;
+
+        // the limit covers every route, not just the one in flight
+        let shed = app.clone().oneshot(get_request("/fast")).await.unwrap();
+        assert_eq!(shed.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        release.notify_one();
+        assert_eq!(slow.await.unwrap().unwrap().status(), StatusCode::OK);
+        let admitted = app.oneshot(get_request("/fast")).await.unwrap();
+        assert_eq!(admitted.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn lockouts_answer_429_with_retry_after_rounded_up() {
+        let response = ApiError::TooManyLoginAttempts {
+            retry_after: Duration::from_millis(1500),
+        }
+        .into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "2");
+    }
+
+    #[tokio::test]
+    async fn allowlist_admits_only_listed_networks() {
+        let ip_allowlist: Vec<IpNet> =
+            vec!["10.0.0.0/8".parse().unwrap(), "::1/128".parse().unwrap()];
+        let app = Router::new().route("/", get(async || "ok")).layer(
+            axum::middleware::from_fn_with_state(Arc::new(ip_allowlist), restrict_to_allowlist),
+        );
+        for (remote_address, expected) in [
+            ("10.1.2.3:1234", StatusCode::OK),
+            // how IPv4 clients of a dual-stack listener show up
+            ("[::ffff:10.1.2.3]:1234", StatusCode::OK),
+            ("[::1]:1234", StatusCode::OK),
+            ("11.0.0.1:1234", StatusCode::FORBIDDEN),
+            ("[::2]:1234", StatusCode::FORBIDDEN),
+        ] {
+            let mut request = get_request("/");
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(remote_address.parse::<SocketAddr>().unwrap()));
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), expected, "{}", remote_address);
+        }
+    }
 }
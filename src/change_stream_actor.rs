@@ -0,0 +1,171 @@
+use crate::{
+    error::Result,
+    file_change_data::{FileChangeData, SequencedChange},
+};
+use serde::Serialize;
+use std::{os::unix::fs::FileTypeExt, path::PathBuf, sync::Arc};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{UnixListener, UnixStream},
+    sync::{broadcast, mpsc},
+};
+use tracing::instrument;
+
+const READER_QUEUE_LENGTH: usize = 16;
+
+#[derive(Serialize)]
+struct ChangeLine<'a> {
+    seq: u64,
+    #[serde(flatten)]
+    change: &'a FileChangeData,
+}
+
+/// Streams every change as a line of JSON to all readers connected to a Unix socket.
+#[derive(Debug)]
+pub struct ChangeStreamActor {
+    socket_path: PathBuf,
+    listener: UnixListener,
+    reader_senders: Vec<mpsc::Sender<Arc<str>>>,
+}
+
+impl ChangeStreamActor {
+    pub fn new(socket_path: PathBuf) -> Result<Self> {
+        // a socket left behind by an unclean shutdown would make bind fail
+        if std::fs::symlink_metadata(&socket_path)
+            .is_ok_and(|metadata| metadata.file_type().is_socket())
+        {
+            std::fs::remove_file(&socket_path)?;
+        }
+        let listener = UnixListener::bind(&socket_path)?;
+        tracing::info!("Streaming changes on {:?}", &socket_path);
+
+        Ok(Self {
+            socket_path,
+            listener,
+            reader_senders: Vec::new(),
+        })
+    }
+
+    fn add_reader(&mut self, stream: UnixStream) {
+        let (sender, receiver) = mpsc::channel(READER_QUEUE_LENGTH);
+        tokio::task::spawn(Self::write_lines(stream, receiver));
+        self.reader_senders.push(sender);
+        tracing::debug!(
+            readers = self.reader_senders.len(),
+            "change stream reader connected"
+        );
+    }
+
+    async fn write_lines(mut stream: UnixStream, mut receiver: mpsc::Receiver<Arc<str>>) {
+        while let Some(line) = receiver.recv().await {
+            if let Err(e) = stream.write_all(line.as_bytes()).await {
+                tracing::debug!("change stream reader disconnected: {}", e);
+                break;
+            }
+        }
+    }
+
+    fn handle_change(&mut self, SequencedChange { seq, change }: SequencedChange) {
+        let line: Arc<str> = match serde_json::to_string(&ChangeLine {
+            seq,
+            change: &change,
+        }) {
+            Ok(json) => format!("{}\n", json).into(),
+            Err(e) => {
+                tracing::error!("Failed to serialize change for change stream: {}", e);
+                return;
+            }
+        };
+
+        // readers that fell behind are dropped rather than stalling the others
+        self.reader_senders
+            .retain(|sender| match sender.try_send(line.clone()) {
+                Ok(_) => true,
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    tracing::warn!("Dropping change stream reader that is not keeping up");
+                    false
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => false,
+            });
+    }
+
+    #[instrument(level = "trace")]
+    pub async fn run(mut self, mut receiver: broadcast::Receiver<SequencedChange>) {
+        loop {
+            tokio::select! {
+                msg = receiver.recv() => match msg {
+                    Ok(change) => self.handle_change(change),
+                    // readers can tell from the gap in seq that changes were skipped
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!("Change stream lagged, skipped {} changes", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                accepted = self.listener.accept() => match accepted {
+                    Ok((stream, _)) => self.add_reader(stream),
+                    Err(e) => tracing::warn!("Failed to accept change stream reader: {}", e),
+                },
+            }
+        }
+
+        let _ = std::fs::remove_file(&self.socket_path)
+            .inspect_err(|e| tracing::warn!("Failed to remove change stream socket: {}", e));
+        tracing::info!(
+            readers = self.reader_senders.len(),
+            "change stream shut down"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_change_data::{FileEntry, FileMetadata};
+    use std::time::{Duration, UNIX_EPOCH};
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    #[tokio::test]
+    async fn changes_are_streamed_as_lines_and_the_socket_is_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("changes.sock");
+        // a socket left behind by an earlier run
+        drop(std::os::unix::net::UnixListener::bind(&socket_path).unwrap());
+        let mut actor = ChangeStreamActor::new(socket_path.clone()).unwrap();
+
+        let client = UnixStream::connect(&socket_path).await.unwrap();
+        let (stream, _) = actor.listener.accept().await.unwrap();
+        actor.add_reader(stream);
+        for seq in [1, 2] {
+            actor.handle_change(SequencedChange {
+                seq,
+                change: Arc::new(FileChangeData::new(
+                    Vec::new(),
+                    vec![FileEntry::new(
+                        PathBuf::from(format!("{}.jpg", seq)),
+                        UNIX_EPOCH + Duration::from_secs(seq),
+                        FileMetadata::default(),
+                    )],
+                    Vec::new(),
+                )),
+            });
+        }
+
+        let mut lines = BufReader::new(client).lines();
+        for seq in [1, 2] {
+            let line = lines.next_line().await.unwrap().unwrap();
+            let line: serde_json::Value = serde_json::from_str(&line).unwrap();
+            assert_eq!(line["seq"], seq);
+            assert_eq!(
+                line["added"],
+                serde_json::json!([[format!("{}.jpg", seq), seq * 1000]])
+            );
+        }
+
+        let (sender, receiver) = broadcast::channel(1);
+        drop(sender);
+        actor.run(receiver).await;
+        assert!(!socket_path.exists());
+        // the reader's task ends with the actor, which closes its stream
+        assert_eq!(lines.next_line().await.unwrap(), None);
+    }
+}
@@ -1,70 +1,1323 @@
-use crate::error::Result;
+use crate::{
+    authentication::{
+        login_limiter::LoginLimits, public_paths::glob_to_regex, url_signer::UrlSigner,
+    },
+    error::{Error, Result},
+    file_change_data::{SortOrder, TimestampFormat},
+    file_change_tracker_actor::WatchMode,
+    file_tracker_actor::PushMode,
+    health::HEARTBEAT_INTERVAL,
+    web_socket_actor::WebSocketOverflow,
+};
+use argon2::PasswordHash;
+use ipnet::IpNet;
+use regex::{Regex, RegexSet};
 use shellexpand::tilde;
 use std::{
+    collections::HashMap,
     env,
     path::{Path, PathBuf},
+    time::Duration,
 };
+use tower_http::CompressionLevel;
 
 #[derive(Clone, Debug)]
 pub struct Config {
-    pub auth_pass_argon2: String,
-    pub auth_user: String,
+    pub auth_mode: AuthMode,
+    /// Accepted argon2 hashes by username; empty when clients log in with certificates alone.
+    pub auth_users: HashMap<String, Vec<String>>,
+    /// Users allowed on the admin endpoints; empty allows every user.
+    pub admin_users: Vec<String>,
+    pub argon2_params: argon2::Params,
     pub auth_token_cleanup_interval: std::time::Duration,
     pub auth_token_ttl: std::time::Duration,
+    pub auth_refresh_grace: std::time::Duration,
     pub auth_token_max_per_user: usize,
+    pub auth_token_max_users: usize,
+    pub login_limits: LoginLimits,
     pub file_extensions: Vec<String>,
+    pub detect_moves: bool,
+    pub title_regex: Option<Regex>,
+    /// Names of files and directories left out of scanning, on top of hidden ones.
+    pub ignore_patterns: RegexSet,
+    /// Levels of directories scanned, 1 being the files directly in `serve_dir`; zero scans all.
+    pub max_depth: usize,
+    /// Whether symlinked directories are descended into; symlinked files are tracked either way.
+    pub follow_symlinks: bool,
+    pub emit_file_owner: bool,
+    pub extract_dimensions: bool,
+    /// Whether newly found images are decoded for a blurhash placeholder.
+    pub emit_blurhash: bool,
+    pub settle_delay: std::time::Duration,
     pub rescrape_interval: std::time::Duration,
+    pub watch_mode: WatchMode,
+    pub backend: Backend,
     pub serve_dir: PathBuf,
-    pub listen_address: String,
+    pub actor_query_timeout: std::time::Duration,
+    pub health_max_heartbeat_age: std::time::Duration,
+    pub ws_inbound_frame_limit: u32,
+    pub ws_inbound_frame_window: std::time::Duration,
+    pub ws_full_resync_interval: std::time::Duration,
+    pub ws_max_changes_per_message: usize,
+    pub ws_max_lifetime: std::time::Duration,
+    pub ws_reconnect_delay: std::time::Duration,
+    pub ws_ping_interval: std::time::Duration,
+    pub ws_pong_timeout: std::time::Duration,
+    pub ws_flush_interval: std::time::Duration,
+    pub ws_overflow: WebSocketOverflow,
+    pub data_route_prefix: String,
+    pub public_data_prefixes: Vec<String>,
+    pub public_paths: Vec<String>,
+    pub url_signer: Option<UrlSigner>,
+    pub listen_target: ListenTarget,
+    pub listen_backlog: u32,
+    pub tls: Option<TlsConfig>,
+    pub max_concurrent_requests: usize,
+    pub ip_allowlist: Option<Vec<IpNet>>,
+    pub debug_endpoints: bool,
+    pub metrics_public: bool,
+    pub change_stream_socket: Option<PathBuf>,
+    pub validate_content: bool,
+    pub compression_level: CompressionLevel,
+    pub compression_algorithms: CompressionAlgorithms,
+    pub ws_push_mode: PushMode,
+    pub timestamp_format: TimestampFormat,
+    pub sort_order: SortOrder,
+    pub summary_interval: std::time::Duration,
+}
+
+/// How users prove who they are when logging in.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum AuthMode {
+    /// A username and password from the configured users.
+    #[default]
+    Password,
+    /// A TLS client certificate signed by `TLS_CLIENT_CA_PATH`, whose subject CN is the username.
+    Mtls,
+}
+
+/// Where the served files live.
+#[derive(Clone, Debug)]
+pub enum Backend {
+    Fs,
+    #[cfg(feature = "s3")]
+    S3(S3Config),
+}
+
+/// Bucket settings for `BACKEND=s3`. Credentials, region and endpoint are read by the S3 client
+/// from the usual `AWS_*` variables.
+#[cfg(feature = "s3")]
+#[derive(Clone, Debug)]
+pub struct S3Config {
+    pub bucket: String,
+    pub prefix: String,
+    pub presign_ttl: Duration,
+}
+
+/// What the server accepts connections on.
+#[derive(Clone, Debug)]
+pub enum ListenTarget {
+    /// A `host:port` pair, resolved at startup.
+    Tcp(String),
+    /// The path of a unix domain socket, given as `unix:/path/to/socket.sock`.
+    Unix(PathBuf),
+}
+
+fn parse_listen_target(raw: &str) -> Result<ListenTarget> {
+    match raw.strip_prefix("unix:") {
+        Some("") => Err(Error::ConfigError(
+            "LISTEN_ADDRESS unix: requires a socket path".to_string(),
+        )),
+        Some(path) => Ok(ListenTarget::Unix(
+            Path::new(&tilde(path).to_string()).to_path_buf(),
+        )),
+        None => Ok(ListenTarget::Tcp(raw.to_string())),
+    }
+}
+
+/// Certificate chain and private key for serving HTTPS, both PEM encoded.
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    /// CA certificates client certificates are verified against, only set with `AUTH_MODE=mtls`.
+    pub client_ca_path: Option<PathBuf>,
+}
+
+fn parse_tls() -> Result<Option<TlsConfig>> {
+    let expand = |raw: String| Path::new(&tilde(&raw).to_string()).to_path_buf();
+    match (
+        env::var("TLS_CERT_PATH").ok().map(expand),
+        env::var("TLS_KEY_PATH").ok().map(expand),
+    ) {
+        (Some(cert_path), Some(key_path)) => Ok(Some(TlsConfig {
+            cert_path,
+            key_path,
+            client_ca_path: env::var("TLS_CLIENT_CA_PATH").ok().map(expand),
+        })),
+        (None, None) => Ok(None),
+        _ => Err(Error::ConfigError(
+            "TLS_CERT_PATH and TLS_KEY_PATH must be set together".to_string(),
+        )),
+    }
+}
+
+fn parse_backend() -> Result<Backend> {
+    match env::var("BACKEND").unwrap_or("fs".to_string()).as_str() {
+        "fs" => Ok(Backend::Fs),
+        #[cfg(feature = "s3")]
+        "s3" => {
+            let bucket = env::var("S3_BUCKET").map_err(|_| {
+                Error::ConfigError("S3_BUCKET is required for BACKEND=s3".to_string())
+            })?;
+            let prefix = env::var("S3_PREFIX")
+                .unwrap_or_default()
+                .trim_matches('/')
+                .to_string();
+            let presign_ttl = duration_from_env(
+                "S3_PRESIGN_TTL",
+                "S3_PRESIGN_TTL_SECS",
+                Duration::from_secs,
+                "5m",
+            )?;
+            Ok(Backend::S3(S3Config {
+                bucket,
+                prefix,
+                presign_ttl,
+            }))
+        }
+        #[cfg(not(feature = "s3"))]
+        "s3" => Err(Error::ConfigError(
+            "BACKEND=s3 requires a build with the s3 feature".to_string(),
+        )),
+        other => Err(Error::ConfigError(format!(
+            "BACKEND must be fs or s3, got \"{}\"",
+            other
+        ))),
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CompressionAlgorithms {
+    pub br: bool,
+    pub deflate: bool,
+    pub gzip: bool,
+    pub zstd: bool,
+}
+
+fn parse_compression_level(raw: &str) -> Result<CompressionLevel> {
+    match raw.trim() {
+        "fastest" => Ok(CompressionLevel::Fastest),
+        "best" => Ok(CompressionLevel::Best),
+        "default" => Ok(CompressionLevel::Default),
+        level => level
+            .parse::<i32>()
+            .map(CompressionLevel::Precise)
+            .map_err(|_| {
+                Error::ConfigError(format!(
+                    "COMPRESSION_LEVEL must be fastest, best, default or a number, got \"{}\"",
+                    raw
+                ))
+            }),
+    }
+}
+
+fn parse_compression_algorithms(raw: &str) -> Result<CompressionAlgorithms> {
+    let mut algorithms = CompressionAlgorithms {
+        br: false,
+        deflate: false,
+        gzip: false,
+        zstd: false,
+    };
+    for algorithm in raw.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        match algorithm {
+            "br" => algorithms.br = true,
+            "deflate" => algorithms.deflate = true,
+            "gzip" => algorithms.gzip = true,
+            "zstd" => algorithms.zstd = true,
+            _ => {
+                return Err(Error::ConfigError(format!(
+                    "unknown compression algorithm \"{}\" in COMPRESSION_ALGORITHMS; expected br, deflate, gzip or zstd",
+                    algorithm
+                )));
+            }
+        }
+    }
+    Ok(algorithms)
+}
+
+/// Reads a human-readable duration such as `500ms`, `30s` or `1h` from `name`, falling back to
+/// the legacy numeric `legacy_name` (interpreted with `legacy_unit`) and then to `default`.
+fn duration_from_env(
+    name: &str,
+    legacy_name: &str,
+    legacy_unit: fn(u64) -> Duration,
+    default: &str,
+) -> Result<Duration> {
+    if let Ok(raw) = env::var(name) {
+        return humantime::parse_duration(raw.trim()).map_err(|e| {
+            Error::ConfigError(format!(
+                "{} is not a valid duration (\"{}\"): {}; use e.g. 500ms, 30s or 1h",
+                name, raw, e
+            ))
+        });
+    }
+    if let Ok(raw) = env::var(legacy_name) {
+        return raw.trim().parse::<u64>().map(legacy_unit).map_err(|e| {
+            Error::ConfigError(format!(
+                "{} is not a valid number (\"{}\"): {}",
+                legacy_name, raw, e
+            ))
+        });
+    }
+    humantime::parse_duration(default).map_err(|e| Error::ConfigError(e.to_string()))
+}
+
+/// Trims surrounding whitespace off each extension, so `jpg, png` is accepted, and checks that
+/// the rest is alphanumeric.
+pub fn validate_file_extensions(file_extensions: Vec<String>) -> Result<Vec<String>> {
+    let file_extensions: Vec<String> = file_extensions
+        .into_iter()
+        .map(|e| e.trim().to_string())
+        .collect();
+    if file_extensions.is_empty() {
+        return Err(Error::ConfigError(
+            "at least one file extension is required".to_string(),
+        ));
+    }
+    if let Some(invalid) = file_extensions
+        .iter()
+        .find(|e| e.is_empty() || !e.chars().all(|c| c.is_ascii_alphanumeric()))
+    {
+        return Err(Error::ConfigError(format!(
+            "invalid file extension \"{}\", expected alphanumeric characters only",
+            invalid
+        )));
+    }
+    Ok(file_extensions)
+}
+
+/// Splits ':'-separated argon2 hashes, several of which allow rotating a password without a flag
+/// day.
+fn parse_password_hashes(raw: &str) -> std::result::Result<Vec<String>, String> {
+    raw.split(':')
+        .map(|hash| {
+            PasswordHash::new(hash).map_err(|e| {
+                format!(
+                    "is not a valid argon2 hash ({}); generate one with `image-watch hash-password`",
+                    e
+                )
+            })?;
+            Ok(hash.to_string())
+        })
+        .collect()
+}
+
+/// Reads `AUTH_USERS_FILE`, one `username:hash[:hash...]` per line. Blank lines and lines starting
+/// with '#' are skipped.
+fn parse_auth_users_file(path: &Path) -> Result<HashMap<String, Vec<String>>> {
+    let content = std::fs::read_to_string(path).map_err(|e| {
+        Error::AuthConfigError(format!(
+            "AUTH_USERS_FILE {} could not be read: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    let mut users = HashMap::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line_error = |message: String| {
+            Error::AuthConfigError(format!(
+                "AUTH_USERS_FILE {} line {}: {}",
+                path.display(),
+                index + 1,
+                message
+            ))
+        };
+        let Some((username, raw_hashes)) = line.split_once(':') else {
+            return Err(line_error("expected username:hash".to_string()));
+        };
+        if username.is_empty() {
+            return Err(line_error("username is empty".to_string()));
+        }
+        let hashes =
+            parse_password_hashes(raw_hashes).map_err(|e| line_error(format!("password {}", e)))?;
+        if users.insert(username.to_string(), hashes).is_some() {
+            return Err(line_error(format!("user \"{}\" is listed twice", username)));
+        }
+    }
+    if users.is_empty() {
+        return Err(Error::AuthConfigError(format!(
+            "AUTH_USERS_FILE {} lists no users",
+            path.display()
+        )));
+    }
+    Ok(users)
+}
+
+/// Parses `IP_ALLOWLIST`, a comma-separated list of networks in CIDR notation or single addresses.
+fn parse_ip_allowlist(raw: &str) -> Result<Vec<IpNet>> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|entry| {
+            entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<std::net::IpAddr>().map(IpNet::from))
+                .map_err(|_| {
+                    Error::ConfigError(format!(
+                        "IP_ALLOWLIST entry \"{}\" is neither a network nor an address",
+                        entry
+                    ))
+                })
+        })
+        .collect()
+}
+
+/// The Argon2 costs from `ARGON2_MEM_KIB`, `ARGON2_ITERATIONS` and `ARGON2_PARALLELISM`, on their
+/// own so `hash-password` can use them without the rest of the config.
+pub fn argon2_params_from_env() -> Result<argon2::Params> {
+    let argon2_mem_kib = env::var("ARGON2_MEM_KIB")
+        .unwrap_or(argon2::Params::DEFAULT_M_COST.to_string())
+        .parse::<u32>()?;
+    let argon2_iterations = env::var("ARGON2_ITERATIONS")
+        .unwrap_or(argon2::Params::DEFAULT_T_COST.to_string())
+        .parse::<u32>()?;
+    let argon2_parallelism = env::var("ARGON2_PARALLELISM")
+        .unwrap_or(argon2::Params::DEFAULT_P_COST.to_string())
+        .parse::<u32>()?;
+    argon2::Params::new(argon2_mem_kib, argon2_iterations, argon2_parallelism, None).map_err(|e| {
+        Error::AuthConfigError(format!(
+            "ARGON2_MEM_KIB, ARGON2_ITERATIONS and ARGON2_PARALLELISM are invalid: {}",
+            e
+        ))
+    })
+}
+
+/// Compiles `IGNORE_PATTERNS`, globs matched against each file and directory name on its own.
+fn parse_ignore_patterns(raw: &str) -> Result<RegexSet> {
+    let mut regexes = Vec::new();
+    for pattern in raw.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        if pattern.contains('/') {
+            return Err(Error::ConfigError(format!(
+                "invalid entry in IGNORE_PATTERNS, patterns match names without /: \"{}\"",
+                pattern
+            )));
+        }
+        regexes.push(glob_to_regex(pattern));
+    }
+    RegexSet::new(&regexes)
+        .map_err(|e| Error::ConfigError(format!("IGNORE_PATTERNS is invalid: {}", e)))
+}
+
+/// Compiles `TITLE_REGEX`, which must have a `title` group or at least one unnamed group.
+fn parse_title_regex(raw: &str) -> Result<Regex> {
+    let regex = Regex::new(raw)
+        .map_err(|e| Error::ConfigError(format!("TITLE_REGEX is invalid: {}", e)))?;
+    if regex.captures_len() < 2 {
+        return Err(Error::ConfigError(
+            "TITLE_REGEX needs a capture group for the title".to_string(),
+        ));
+    }
+    Ok(regex)
+}
+
+/// Reads `ADMIN_USERS`, a comma-separated list of usernames, each of which has to be able to log in.
+/// Any subject CN the client CA signs can log in with `AUTH_MODE=mtls`, so those are taken as is.
+fn parse_admin_users(
+    raw: &str,
+    auth_mode: AuthMode,
+    auth_users: &HashMap<String, Vec<String>>,
+) -> Result<Vec<String>> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|username| !username.is_empty())
+        .map(|username| {
+            if auth_mode == AuthMode::Mtls || auth_users.contains_key(username) {
+                Ok(username.to_string())
+            } else {
+                Err(Error::ConfigError(format!(
+                    "ADMIN_USERS {} is not a known user",
+                    username
+                )))
+            }
+        })
+        .collect()
 }
 
 impl Config {
     pub fn from_env() -> Result<Self> {
-        let auth_pass_argon2 = env::var("AUTH_PASS_ARGON2")?;
-        let auth_user = env::var("AUTH_USER")?;
+        let auth_mode = match env::var("AUTH_MODE")
+            .unwrap_or("password".to_string())
+            .as_str()
+        {
+            "password" => AuthMode::Password,
+            "mtls" => AuthMode::Mtls,
+            other => {
+                return Err(Error::AuthConfigError(format!(
+                    "AUTH_MODE must be password or mtls, got \"{}\"",
+                    other
+                )));
+            }
+        };
+
+        let auth_users = match env::var("AUTH_USERS_FILE") {
+            Ok(raw_path) => {
+                if env::var("AUTH_USER").is_ok() || env::var("AUTH_PASS_ARGON2").is_ok() {
+                    return Err(Error::AuthConfigError(
+                        "AUTH_USER and AUTH_PASS_ARGON2 cannot be combined with AUTH_USERS_FILE"
+                            .to_string(),
+                    ));
+                }
+                parse_auth_users_file(Path::new(&tilde(&raw_path).to_string()))?
+            }
+            // certificates take the place of passwords
+            Err(_) if auth_mode == AuthMode::Mtls => HashMap::new(),
+            Err(_) => {
+                let raw_auth_pass_argon2 = env::var("AUTH_PASS_ARGON2").map_err(|_| {
+                    Error::AuthConfigError(
+                        "AUTH_PASS_ARGON2 or AUTH_USERS_FILE is required; generate a hash with `image-watch hash-password`"
+                            .to_string(),
+                    )
+                })?;
+                let auth_pass_argon2 = parse_password_hashes(&raw_auth_pass_argon2)
+                    .map_err(|e| Error::AuthConfigError(format!("AUTH_PASS_ARGON2 {}", e)))?;
+                let auth_user = env::var("AUTH_USER").map_err(|_| {
+                    Error::AuthConfigError(
+                        "AUTH_USER is required; set it to the login username".to_string(),
+                    )
+                })?;
+                HashMap::from([(auth_user, auth_pass_argon2)])
+            }
+        };
+
+        let admin_users = match env::var("ADMIN_USERS") {
+            Ok(raw) => parse_admin_users(&raw, auth_mode, &auth_users)?,
+            Err(_) => Vec::new(),
+        };
+
+        let argon2_params = argon2_params_from_env()?;
 
         let raw_file_extensions = env::var("FILE_EXTENSIONS").unwrap_or("jpg,jpeg".to_string());
-        let file_extensions = raw_file_extensions
-            .split(',')
-            .map(|s| s.to_string())
-            .collect();
+        let file_extensions = validate_file_extensions(
+            raw_file_extensions
+                .split(',')
+                .map(|s| s.to_string())
+                .collect(),
+        )?;
+
+        let detect_moves = env::var("DETECT_MOVES").unwrap_or("false".to_string());
+        let detect_moves = detect_moves.parse::<bool>()?;
+
+        let title_regex = env::var("TITLE_REGEX")
+            .ok()
+            .map(|raw| parse_title_regex(&raw))
+            .transpose()?;
+
+        let ignore_patterns =
+            parse_ignore_patterns(&env::var("IGNORE_PATTERNS").unwrap_or_default())?;
+
+        let max_depth = env::var("MAX_DEPTH").unwrap_or("0".to_string());
+        let max_depth = max_depth.parse::<usize>()?;
+
+        let follow_symlinks = env::var("FOLLOW_SYMLINKS").unwrap_or("false".to_string());
+        let follow_symlinks = follow_symlinks.parse::<bool>()?;
+
+        let emit_file_owner = env::var("EMIT_FILE_OWNER").unwrap_or("false".to_string());
+        let emit_file_owner = emit_file_owner.parse::<bool>()?;
+
+        let extract_dimensions = env::var("EXTRACT_DIMENSIONS").unwrap_or("false".to_string());
+        let extract_dimensions = extract_dimensions.parse::<bool>()?;
+
+        let emit_blurhash = env::var("EMIT_BLURHASH").unwrap_or("false".to_string());
+        let emit_blurhash = emit_blurhash.parse::<bool>()?;
+
+        // zero reports new files as soon as they are found
+        let settle_delay = duration_from_env(
+            "SETTLE_DELAY",
+            "SETTLE_DELAY_MILLIS",
+            Duration::from_millis,
+            "0s",
+        )?;
 
-        let auth_token_cleanup_interval =
-            env::var("AUTH_TOKEN_CLEANUP_INTERVAL_MILLIS").unwrap_or("1000".to_string());
-        let auth_token_cleanup_interval =
-            std::time::Duration::from_millis(auth_token_cleanup_interval.parse::<u64>()?);
+        let auth_token_cleanup_interval = duration_from_env(
+            "AUTH_TOKEN_CLEANUP_INTERVAL",
+            "AUTH_TOKEN_CLEANUP_INTERVAL_MILLIS",
+            Duration::from_millis,
+            "1s",
+        )?;
 
-        let auth_token_ttl = env::var("AUTH_TOKEN_TTL_SECS").unwrap_or("3600".to_string());
-        let auth_token_ttl = std::time::Duration::from_secs(auth_token_ttl.parse::<u64>()?);
+        let auth_token_ttl = duration_from_env(
+            "AUTH_TOKEN_TTL",
+            "AUTH_TOKEN_TTL_SECS",
+            Duration::from_secs,
+            "1h",
+        )?;
+
+        let auth_refresh_grace = duration_from_env(
+            "AUTH_REFRESH_GRACE",
+            "AUTH_REFRESH_GRACE_SECS",
+            Duration::from_secs,
+            "0s",
+        )?;
 
         let auth_token_max_per_user =
             env::var("AUTH_TOKEN_MAX_PER_USER").unwrap_or("16".to_string());
         let auth_token_max_per_user = auth_token_max_per_user.parse::<usize>()?;
 
-        let raw_rescrape_interval =
-            env::var("RESCRAPE_INTERVAL_MILLIS").unwrap_or("1000".to_string());
-        let rescrape_interval =
-            std::time::Duration::from_millis(raw_rescrape_interval.parse::<u64>()?);
+        let auth_token_max_users = env::var("AUTH_TOKEN_MAX_USERS").unwrap_or("1024".to_string());
+        let auth_token_max_users = auth_token_max_users.parse::<usize>()?.max(1);
+
+        // zero disables either limit, e.g. the per-address one behind a proxy
+        let login_max_failures_per_user =
+            env::var("LOGIN_MAX_FAILURES_PER_USER").unwrap_or("5".to_string());
+        let login_max_failures_per_user = login_max_failures_per_user.parse::<u32>()?;
+        let login_max_failures_per_ip =
+            env::var("LOGIN_MAX_FAILURES_PER_IP").unwrap_or("20".to_string());
+        let login_max_failures_per_ip = login_max_failures_per_ip.parse::<u32>()?;
+        let login_failure_window = duration_from_env(
+            "LOGIN_FAILURE_WINDOW",
+            "LOGIN_FAILURE_WINDOW_SECS",
+            Duration::from_secs,
+            "15m",
+        )?;
+        let login_lockout = duration_from_env(
+            "LOGIN_LOCKOUT",
+            "LOGIN_LOCKOUT_SECS",
+            Duration::from_secs,
+            "1m",
+        )?;
+        let login_max_lockout = duration_from_env(
+            "LOGIN_MAX_LOCKOUT",
+            "LOGIN_MAX_LOCKOUT_SECS",
+            Duration::from_secs,
+            "1h",
+        )?;
+        if login_max_lockout < login_lockout {
+            return Err(Error::ConfigError(
+                "LOGIN_MAX_LOCKOUT must not be shorter than LOGIN_LOCKOUT".to_string(),
+            ));
+        }
+        let login_limits = LoginLimits {
+            max_failures_per_user: login_max_failures_per_user,
+            max_failures_per_ip: login_max_failures_per_ip,
+            failure_window: login_failure_window,
+            lockout: login_lockout,
+            max_lockout: login_max_lockout,
+        };
+
+        let rescrape_interval = duration_from_env(
+            "RESCRAPE_INTERVAL",
+            "RESCRAPE_INTERVAL_MILLIS",
+            Duration::from_millis,
+            "1s",
+        )?;
+
+        // with notify, the rescrape interval times the reconciling rescrape
+        let watch_reconcile_every = env::var("WATCH_RECONCILE_EVERY").unwrap_or("60".to_string());
+        let watch_reconcile_every = watch_reconcile_every.parse::<u32>()?.max(1);
+        let watch_mode = match env::var("WATCH_MODE")
+            .unwrap_or("poll".to_string())
+            .as_str()
+        {
+            "poll" => WatchMode::Poll,
+            "notify" => WatchMode::Notify {
+                reconcile_every: watch_reconcile_every,
+            },
+            other => {
+                return Err(Error::ConfigError(format!(
+                    "WATCH_MODE must be poll or notify, got \"{}\"",
+                    other
+                )));
+            }
+        };
+
+        let backend = parse_backend()?;
 
-        let raw_serve_dir = env::var("SERVE_DIR")?;
+        // the object store backend serves nothing from disk
+        let raw_serve_dir = match backend {
+            Backend::Fs => env::var("SERVE_DIR")?,
+            #[cfg(feature = "s3")]
+            Backend::S3(_) => env::var("SERVE_DIR").unwrap_or_default(),
+        };
         let serve_dir = Path::new(&tilde(&raw_serve_dir).to_string()).to_path_buf();
 
-        let listen_address = env::var("LISTEN_ADDRESS").unwrap_or("127.0.0.1:3000".to_string());
+        // zero waits for actors to respond indefinitely
+        let actor_query_timeout = duration_from_env(
+            "ACTOR_QUERY_TIMEOUT",
+            "ACTOR_QUERY_TIMEOUT_SECS",
+            Duration::from_secs,
+            "10s",
+        )?;
+
+        // actors that have not beaten their heartbeat for longer fail the health check
+        let health_max_heartbeat_age = duration_from_env(
+            "HEALTH_MAX_HEARTBEAT_AGE",
+            "HEALTH_MAX_HEARTBEAT_AGE_SECS",
+            Duration::from_secs,
+            "30s",
+        )?;
+        if health_max_heartbeat_age <= HEARTBEAT_INTERVAL {
+            return Err(Error::ConfigError(format!(
+                "HEALTH_MAX_HEARTBEAT_AGE must be longer than the heartbeat interval of {:?}",
+                HEARTBEAT_INTERVAL
+            )));
+        }
+
+        let ws_inbound_frame_limit = env::var("WS_INBOUND_FRAME_LIMIT").unwrap_or("64".to_string());
+        let ws_inbound_frame_limit = ws_inbound_frame_limit.parse::<u32>()?;
+
+        let ws_inbound_frame_window = duration_from_env(
+            "WS_INBOUND_FRAME_WINDOW",
+            "WS_INBOUND_FRAME_WINDOW_MILLIS",
+            Duration::from_millis,
+            "1s",
+        )?;
+
+        let ws_push_mode = match env::var("WS_PUSH_MODE")
+            .unwrap_or("incremental".to_string())
+            .as_str()
+        {
+            "incremental" => PushMode::Incremental,
+            "full" => PushMode::Full,
+            other => {
+                return Err(Error::ConfigError(format!(
+                    "WS_PUSH_MODE must be incremental or full, got \"{}\"",
+                    other
+                )));
+            }
+        };
+
+        let ws_overflow = match env::var("WS_OVERFLOW")
+            .unwrap_or("block".to_string())
+            .as_str()
+        {
+            "drop" => WebSocketOverflow::Drop,
+            "block" => WebSocketOverflow::Block,
+            "close" => WebSocketOverflow::Close,
+            other => {
+                return Err(Error::ConfigError(format!(
+                    "WS_OVERFLOW must be drop, block or close, got \"{}\"",
+                    other
+                )));
+            }
+        };
+
+        let timestamp_format = match env::var("TIMESTAMP_FORMAT")
+            .unwrap_or("epoch_ms".to_string())
+            .as_str()
+        {
+            "epoch_ms" => TimestampFormat::EpochMs,
+            "iso8601" => TimestampFormat::Iso8601,
+            other => {
+                return Err(Error::ConfigError(format!(
+                    "TIMESTAMP_FORMAT must be epoch_ms or iso8601, got \"{}\"",
+                    other
+                )));
+            }
+        };
+
+        let sort_order = match env::var("SORT_ORDER")
+            .unwrap_or("mtime_desc".to_string())
+            .as_str()
+        {
+            "mtime_desc" => SortOrder::MtimeDesc,
+            "mtime_asc" => SortOrder::MtimeAsc,
+            "name_asc" => SortOrder::NameAsc,
+            "name_desc" => SortOrder::NameDesc,
+            other => {
+                return Err(Error::ConfigError(format!(
+                    "SORT_ORDER must be mtime_desc, mtime_asc, name_asc or name_desc, got \"{}\"",
+                    other
+                )));
+            }
+        };
+
+        let ws_full_resync_interval = duration_from_env(
+            "WS_FULL_RESYNC_INTERVAL",
+            "WS_FULL_RESYNC_INTERVAL_SECS",
+            Duration::from_secs,
+            "0s",
+        )?;
+
+        // zero keeps connections open for as long as the client likes
+        let ws_max_lifetime = duration_from_env(
+            "WS_MAX_LIFETIME",
+            "WS_MAX_LIFETIME_SECS",
+            Duration::from_secs,
+            "0s",
+        )?;
+
+        // suggested to clients in the close reason when they may reconnect
+        let ws_reconnect_delay = duration_from_env(
+            "WS_RECONNECT_DELAY",
+            "WS_RECONNECT_DELAY_MILLIS",
+            Duration::from_millis,
+            "2s",
+        )?;
+
+        // zero sends no pings, leaving dead connections to the operating system
+        let ws_ping_interval = duration_from_env(
+            "WS_PING_INTERVAL",
+            "WS_PING_INTERVAL_SECS",
+            Duration::from_secs,
+            "30s",
+        )?;
+        let ws_pong_timeout = duration_from_env(
+            "WS_PONG_TIMEOUT",
+            "WS_PONG_TIMEOUT_SECS",
+            Duration::from_secs,
+            "10s",
+        )?;
+        if !ws_ping_interval.is_zero() && ws_pong_timeout.is_zero() {
+            return Err(Error::ConfigError(
+                "WS_PONG_TIMEOUT must be positive while WS_PING_INTERVAL is".to_string(),
+            ));
+        }
+
+        // zero sends every change as it comes instead of coalescing those within the interval
+        let ws_flush_interval = duration_from_env(
+            "WS_FLUSH_INTERVAL",
+            "WS_FLUSH_INTERVAL_MILLIS",
+            Duration::from_millis,
+            "0s",
+        )?;
+
+        // zero sends every change as a single message, however large
+        let ws_max_changes_per_message =
+            env::var("WS_MAX_CHANGES_PER_MESSAGE").unwrap_or("0".to_string());
+        let ws_max_changes_per_message = ws_max_changes_per_message.parse::<usize>()?;
+
+        let raw_data_route_prefix =
+            env::var("DATA_ROUTE_PREFIX").unwrap_or("/backend/data".to_string());
+        let data_route_prefix = raw_data_route_prefix.trim_end_matches('/').to_string();
+        if !data_route_prefix.starts_with('/') {
+            return Err(Error::ConfigError(format!(
+                "DATA_ROUTE_PREFIX must be an absolute path other than \"/\", got \"{}\"",
+                raw_data_route_prefix
+            )));
+        }
+
+        // sub-prefixes of the data route that are served without authentication
+        let raw_public_data_prefixes = env::var("PUBLIC_DATA_PREFIXES").unwrap_or_default();
+        let mut public_data_prefixes = Vec::new();
+        for raw_public_data_prefix in raw_public_data_prefixes
+            .split(',')
+            .map(|s| s.trim().trim_matches('/'))
+            .filter(|s| !s.is_empty())
+        {
+            if raw_public_data_prefix
+                .split('/')
+                .any(|segment| segment.is_empty() || segment == "." || segment == "..")
+            {
+                return Err(Error::ConfigError(format!(
+                    "invalid entry in PUBLIC_DATA_PREFIXES: \"{}\"",
+                    raw_public_data_prefix
+                )));
+            }
+            public_data_prefixes.push(format!("{}/{}", data_route_prefix, raw_public_data_prefix));
+        }
+
+        // exact paths or globs served without authentication on top of the defaults
+        let raw_public_paths = env::var("PUBLIC_PATHS").unwrap_or_default();
+        let mut public_paths = Vec::new();
+        for raw_public_path in raw_public_paths
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+        {
+            if !raw_public_path.starts_with('/') {
+                return Err(Error::ConfigError(format!(
+                    "invalid entry in PUBLIC_PATHS, paths start with /: \"{}\"",
+                    raw_public_path
+                )));
+            }
+            public_paths.push(raw_public_path.to_string());
+        }
+
+        let signed_url_ttl = duration_from_env(
+            "SIGNED_URL_TTL",
+            "SIGNED_URL_TTL_SECS",
+            Duration::from_secs,
+            "5m",
+        )?;
+        let url_signer = env::var("SIGNED_URL_SECRET")
+            .ok()
+            .map(|secret| UrlSigner::new(secret, signed_url_ttl));
+
+        let listen_target = parse_listen_target(
+            &env::var("LISTEN_ADDRESS").unwrap_or("127.0.0.1:3000".to_string()),
+        )?;
+
+        let listen_backlog = env::var("LISTEN_BACKLOG").unwrap_or("1024".to_string());
+        let listen_backlog = listen_backlog.parse::<u32>()?;
+
+        let tls = parse_tls()?;
+        // the proxy in front of a unix socket terminates TLS
+        if tls.is_some() && matches!(listen_target, ListenTarget::Unix(_)) {
+            return Err(Error::ConfigError(
+                "TLS_CERT_PATH and TLS_KEY_PATH are only supported with a TCP LISTEN_ADDRESS"
+                    .to_string(),
+            ));
+        }
+        let client_ca_path = tls.as_ref().and_then(|tls| tls.client_ca_path.as_ref());
+        match (auth_mode, client_ca_path) {
+            (AuthMode::Mtls, None) => {
+                return Err(Error::AuthConfigError(
+                    "AUTH_MODE=mtls requires TLS_CERT_PATH, TLS_KEY_PATH and TLS_CLIENT_CA_PATH"
+                        .to_string(),
+                ));
+            }
+            (AuthMode::Password, Some(_)) => {
+                return Err(Error::AuthConfigError(
+                    "TLS_CLIENT_CA_PATH is only used with AUTH_MODE=mtls".to_string(),
+                ));
+            }
+            _ => {}
+        }
+
+        let max_concurrent_requests =
+            env::var("MAX_CONCURRENT_REQUESTS").unwrap_or("1024".to_string());
+        let max_concurrent_requests = max_concurrent_requests.parse::<usize>()?;
+
+        // unset allows every address, an empty list none
+        let ip_allowlist = env::var("IP_ALLOWLIST")
+            .ok()
+            .map(|raw| parse_ip_allowlist(&raw))
+            .transpose()?;
+        // peers of a unix socket have no address to check
+        if ip_allowlist.is_some() && matches!(listen_target, ListenTarget::Unix(_)) {
+            return Err(Error::ConfigError(
+                "IP_ALLOWLIST is only supported with a TCP LISTEN_ADDRESS".to_string(),
+            ));
+        }
+
+        let debug_endpoints = env::var("DEBUG_ENDPOINTS").unwrap_or("false".to_string());
+        let debug_endpoints = debug_endpoints.parse::<bool>()?;
+
+        let metrics_public = env::var("METRICS_PUBLIC").unwrap_or("false".to_string());
+        let metrics_public = metrics_public.parse::<bool>()?;
+
+        let validate_content = env::var("VALIDATE_CONTENT").unwrap_or("false".to_string());
+        let validate_content = validate_content.parse::<bool>()?;
+        #[cfg(feature = "s3")]
+        if validate_content && matches!(backend, Backend::S3(_)) {
+            return Err(Error::ConfigError(
+                "VALIDATE_CONTENT is only supported with BACKEND=fs".to_string(),
+            ));
+        }
+        #[cfg(feature = "s3")]
+        if matches!(watch_mode, WatchMode::Notify { .. }) && matches!(backend, Backend::S3(_)) {
+            return Err(Error::ConfigError(
+                "WATCH_MODE=notify is only supported with BACKEND=fs".to_string(),
+            ));
+        }
+        // objects have no owner or permission bits
+        #[cfg(feature = "s3")]
+        if emit_file_owner && matches!(backend, Backend::S3(_)) {
+            return Err(Error::ConfigError(
+                "EMIT_FILE_OWNER is only supported with BACKEND=fs".to_string(),
+            ));
+        }
+        // probing would mean downloading the start of every object
+        #[cfg(feature = "s3")]
+        if extract_dimensions && matches!(backend, Backend::S3(_)) {
+            return Err(Error::ConfigError(
+                "EXTRACT_DIMENSIONS is only supported with BACKEND=fs".to_string(),
+            ));
+        }
+        // as would decoding them
+        #[cfg(feature = "s3")]
+        if emit_blurhash && matches!(backend, Backend::S3(_)) {
+            return Err(Error::ConfigError(
+                "EMIT_BLURHASH is only supported with BACKEND=fs".to_string(),
+            ));
+        }
+
+        let compression_level = parse_compression_level(
+            &env::var("COMPRESSION_LEVEL").unwrap_or("default".to_string()),
+        )?;
+
+        // an empty list disables response compression entirely
+        let compression_algorithms = parse_compression_algorithms(
+            &env::var("COMPRESSION_ALGORITHMS").unwrap_or("br,deflate,gzip,zstd".to_string()),
+        )?;
+
+        let change_stream_socket = env::var("CHANGE_STREAM_SOCKET")
+            .ok()
+            .map(|raw| Path::new(&tilde(&raw).to_string()).to_path_buf());
+
+        // zero disables the periodic activity summary
+        let summary_interval = duration_from_env(
+            "SUMMARY_INTERVAL",
+            "SUMMARY_INTERVAL_SECS",
+            Duration::from_secs,
+            "0s",
+        )?;
 
         let config = Self {
-            auth_pass_argon2,
-            auth_user,
+            auth_mode,
+            auth_users,
+            admin_users,
+            argon2_params,
             auth_token_cleanup_interval,
             auth_token_ttl,
+            auth_refresh_grace,
             auth_token_max_per_user,
+            auth_token_max_users,
+            login_limits,
             file_extensions,
+            detect_moves,
+            title_regex,
+            ignore_patterns,
+            max_depth,
+            follow_symlinks,
+            emit_file_owner,
+            extract_dimensions,
+            emit_blurhash,
+            settle_delay,
             rescrape_interval,
+            watch_mode,
+            backend,
             serve_dir,
-            listen_address,
+            actor_query_timeout,
+            health_max_heartbeat_age,
+            ws_inbound_frame_limit,
+            ws_inbound_frame_window,
+            ws_full_resync_interval,
+            ws_max_changes_per_message,
+            ws_max_lifetime,
+            ws_reconnect_delay,
+            ws_ping_interval,
+            ws_pong_timeout,
+            ws_flush_interval,
+            ws_overflow,
+            data_route_prefix,
+            public_data_prefixes,
+            public_paths,
+            url_signer,
+            listen_target,
+            listen_backlog,
+            tls,
+            max_concurrent_requests,
+            ip_allowlist,
+            debug_endpoints,
+            metrics_public,
+            change_stream_socket,
+            validate_content,
+            compression_level,
+            compression_algorithms,
+            ws_push_mode,
+            timestamp_format,
+            sort_order,
+            summary_interval,
         };
 
         tracing::debug!("Configuration extraction successful: {:?}", config);
 
         Ok(config)
     }
+
+    /// The defaults of `from_env` with cheap argon2 costs, for `users` and files in `serve_dir`,
+    /// rescraping every 10 ms.
+    #[cfg(test)]
+    pub fn for_test(users: HashMap<String, Vec<String>>, serve_dir: PathBuf) -> Self {
+        Self {
+            auth_mode: AuthMode::Password,
+            auth_users: users,
+            admin_users: Vec::new(),
+            argon2_params: argon2::Params::new(argon2::Params::MIN_M_COST, 1, 1, None).unwrap(),
+            auth_token_cleanup_interval: Duration::from_secs(1),
+            auth_token_ttl: Duration::from_secs(3600),
+            auth_refresh_grace: Duration::ZERO,
+            auth_token_max_per_user: 16,
+            auth_token_max_users: 1024,
+            login_limits: LoginLimits {
+                max_failures_per_user: 5,
+                max_failures_per_ip: 20,
+                failure_window: Duration::from_secs(15 * 60),
+                lockout: Duration::from_secs(60),
+                max_lockout: Duration::from_secs(3600),
+            },
+            file_extensions: vec!["jpg".to_string(), "jpeg".to_string()],
+            detect_moves: false,
+            title_regex: None,
+            ignore_patterns: RegexSet::empty(),
+            max_depth: 0,
+            follow_symlinks: false,
+            emit_file_owner: false,
+            extract_dimensions: false,
+            emit_blurhash: false,
+            settle_delay: Duration::ZERO,
+            rescrape_interval: Duration::from_millis(10),
+            watch_mode: WatchMode::Poll,
+            backend: Backend::Fs,
+            serve_dir,
+            actor_query_timeout: Duration::from_secs(10),
+            health_max_heartbeat_age: Duration::from_secs(30),
+            ws_inbound_frame_limit: 64,
+            ws_inbound_frame_window: Duration::from_secs(1),
+            ws_full_resync_interval: Duration::ZERO,
+            ws_max_changes_per_message: 0,
+            ws_max_lifetime: Duration::ZERO,
+            ws_reconnect_delay: Duration::from_secs(2),
+            ws_ping_interval: Duration::from_secs(30),
+            ws_pong_timeout: Duration::from_secs(10),
+            ws_flush_interval: Duration::ZERO,
+            ws_overflow: WebSocketOverflow::Block,
+            data_route_prefix: "/backend/data".to_string(),
+            public_data_prefixes: Vec::new(),
+            public_paths: Vec::new(),
+            url_signer: None,
+            listen_target: parse_listen_target("127.0.0.1:0").unwrap(),
+            listen_backlog: 1024,
+            tls: None,
+            max_concurrent_requests: 1024,
+            ip_allowlist: None,
+            debug_endpoints: false,
+            metrics_public: false,
+            change_stream_socket: None,
+            validate_content: false,
+            compression_level: CompressionLevel::Default,
+            compression_algorithms: parse_compression_algorithms("br,deflate,gzip,zstd").unwrap(),
+            ws_push_mode: PushMode::Incremental,
+            timestamp_format: TimestampFormat::EpochMs,
+            sort_order: SortOrder::MtimeDesc,
+            summary_interval: Duration::ZERO,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn admin_users_must_be_known() {
+        let auth_users = HashMap::from([
+            ("alice".to_string(), Vec::new()),
+            ("bob".to_string(), Vec::new()),
+        ]);
+        assert_eq!(
+            parse_admin_users(" alice ,", AuthMode::Password, &auth_users).unwrap(),
+            vec!["alice".to_string()]
+        );
+        assert!(
+            parse_admin_users("", AuthMode::Password, &auth_users)
+                .unwrap()
+                .is_empty()
+        );
+        assert!(parse_admin_users("alice,mallory", AuthMode::Password, &auth_users).is_err());
+        // any certified name may log in
+        assert_eq!(
+            parse_admin_users("alice,mallory", AuthMode::Mtls, &HashMap::new()).unwrap(),
+            vec!["alice".to_string(), "mallory".to_string()]
+        );
+    }
+
+    #[test]
+    fn title_regexes_need_a_capture_group() {
+        assert!(parse_title_regex(r"^(.+)\.jpg$").is_ok());
+        assert!(parse_title_regex(r"^(?<title>.+)\.jpg$").is_ok());
+        assert!(parse_title_regex(r"^.+\.jpg$").is_err());
+        assert!(parse_title_regex(r"^(.+\.jpg$").is_err());
+    }
+
+    #[test]
+    fn listen_addresses_are_host_ports_or_unix_sockets() {
+        assert!(matches!(
+            parse_listen_target("0.0.0.0:8080").unwrap(),
+            ListenTarget::Tcp(address) if address == "0.0.0.0:8080"
+        ));
+        assert!(matches!(
+            parse_listen_target("unix:/run/image-watch.sock").unwrap(),
+            ListenTarget::Unix(path) if path == Path::new("/run/image-watch.sock")
+        ));
+        assert!(parse_listen_target("unix:").is_err());
+    }
+
+    #[test]
+    fn tls_needs_both_the_certificate_and_the_key() {
+        // SAFETY: no other test touches these variables
+        unsafe {
+            env::remove_var("TLS_CLIENT_CA_PATH");
+            env::remove_var("TLS_KEY_PATH");
+            env::remove_var("TLS_CERT_PATH");
+        }
+        assert!(parse_tls().unwrap().is_none());
+
+        unsafe { env::set_var("TLS_CERT_PATH", "/etc/image-watch/cert.pem") };
+        assert!(matches!(parse_tls(), Err(Error::ConfigError(_))));
+
+        unsafe { env::set_var("TLS_KEY_PATH", "/etc/image-watch/key.pem") };
+        let tls = parse_tls().unwrap().unwrap();
+        assert_eq!(tls.cert_path, Path::new("/etc/image-watch/cert.pem"));
+        assert_eq!(tls.key_path, Path::new("/etc/image-watch/key.pem"));
+        assert_eq!(tls.client_ca_path, None);
+    }
+
+    #[test]
+    fn durations_accept_human_readable_and_legacy_forms() {
+        // each case has variables of its own, as tests run in parallel
+        let cases = [
+            (Some("1h"), None, Duration::from_secs(3600)),
+            (Some(" 1500ms "), None, Duration::from_millis(1500)),
+            (None, Some("250"), Duration::from_millis(250)),
+            // the new form wins over the legacy one
+            (Some("30s"), Some("250"), Duration::from_secs(30)),
+            (None, None, Duration::from_secs(5)),
+        ];
+        for (case, (value, legacy_value, expected)) in cases.into_iter().enumerate() {
+            let name = format!("IMAGE_WATCH_TEST_DURATION_{}", case);
+            let legacy_name = format!("IMAGE_WATCH_TEST_DURATION_{}_MILLIS", case);
+            // SAFETY: no other test touches these variables
+            unsafe {
+                if let Some(value) = value {
+                    env::set_var(&name, value);
+                }
+                if let Some(legacy_value) = legacy_value {
+                    env::set_var(&legacy_name, legacy_value);
+                }
+            }
+            let duration =
+                duration_from_env(&name, &legacy_name, Duration::from_millis, "5s").unwrap();
+            assert_eq!(duration, expected, "case {}", case);
+        }
+    }
+
+    #[test]
+    fn malformed_durations_are_rejected() {
+        // SAFETY: no other test touches these variables
+        unsafe {
+            env::set_var("IMAGE_WATCH_TEST_BAD_DURATION", "1 fortnight");
+            env::set_var("IMAGE_WATCH_TEST_BAD_DURATION_LEGACY_SECS", "1h");
+        }
+        assert!(
+            duration_from_env(
+                "IMAGE_WATCH_TEST_BAD_DURATION",
+                "IMAGE_WATCH_TEST_UNSET_SECS",
+                Duration::from_secs,
+                "5s"
+            )
+            .is_err()
+        );
+        assert!(
+            duration_from_env(
+                "IMAGE_WATCH_TEST_UNSET",
+                "IMAGE_WATCH_TEST_BAD_DURATION_LEGACY_SECS",
+                Duration::from_secs,
+                "5s"
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn users_files_list_each_user_once() {
+        let hash = "$argon2id$v=19$m=8,t=1,p=1$c2FsdHNhbHQ$mTb2VKn0fMDz9ls9D7i9Vg";
+        let dir = tempfile::tempdir().unwrap();
+        let parse = |content: &str| {
+            let path = dir.path().join("users");
+            std::fs::write(&path, content).unwrap();
+            parse_auth_users_file(&path)
+        };
+
+        let users = parse(&format!(
+            "# admins\nalice:{hash}\n\n  bob:{hash}:{hash}  \n",
+            hash = hash
+        ))
+        .unwrap();
+        assert_eq!(users.len(), 2);
+        assert_eq!(users["alice"], vec![hash.to_string()]);
+        assert_eq!(users["bob"], vec![hash.to_string(), hash.to_string()]);
+
+        for (content, message) in [
+            (
+                format!("alice:{}\nbob", hash),
+                "line 2: expected username:hash",
+            ),
+            (format!(":{}", hash), "line 1: username is empty"),
+            ("alice:not a hash".to_string(), "line 1: password"),
+            (
+                format!("alice:{hash}\n# again\nalice:{hash}", hash = hash),
+                "line 3: user \"alice\" is listed twice",
+            ),
+            ("# nobody\n".to_string(), "lists no users"),
+        ] {
+            match parse(&content) {
+                Err(Error::AuthConfigError(e)) => assert!(e.contains(message), "{}", e),
+                other => panic!("expected {:?} to fail, got {:?}", content, other),
+            }
+        }
+        assert!(matches!(
+            parse_auth_users_file(&dir.path().join("missing")),
+            Err(Error::AuthConfigError(_))
+        ));
+    }
+
+    #[test]
+    fn argon2_costs_default_to_the_crate_defaults() {
+        // SAFETY: no other test touches these variables
+        unsafe {
+            env::remove_var("ARGON2_MEM_KIB");
+            env::remove_var("ARGON2_ITERATIONS");
+            env::remove_var("ARGON2_PARALLELISM");
+        }
+        let params = argon2_params_from_env().unwrap();
+        assert_eq!(
+            (params.m_cost(), params.t_cost(), params.p_cost()),
+            (
+                argon2::Params::DEFAULT_M_COST,
+                argon2::Params::DEFAULT_T_COST,
+                argon2::Params::DEFAULT_P_COST
+            )
+        );
+
+        unsafe {
+            env::set_var("ARGON2_MEM_KIB", "65536");
+            env::set_var("ARGON2_ITERATIONS", "3");
+            env::set_var("ARGON2_PARALLELISM", "4");
+        }
+        let params = argon2_params_from_env().unwrap();
+        assert_eq!(
+            (params.m_cost(), params.t_cost(), params.p_cost()),
+            (65536, 3, 4)
+        );
+
+        // less memory than eight blocks per lane is rejected by argon2
+        unsafe { env::set_var("ARGON2_MEM_KIB", "16") };
+        assert!(matches!(
+            argon2_params_from_env(),
+            Err(Error::AuthConfigError(_))
+        ));
+        unsafe { env::set_var("ARGON2_MEM_KIB", "lots") };
+        assert!(argon2_params_from_env().is_err());
+    }
+
+    #[test]
+    fn password_hashes_are_split_on_colons() {
+        let old = "$argon2id$v=19$m=8,t=1,p=1$c2FsdHNhbHQ$mTb2VKn0fMDz9ls9D7i9Vg";
+        let new = "$argon2id$v=19$m=8,t=1,p=1$c2FsdHNhbHQy$Kz6+8QmU6aqJj3e2Z3zL7w";
+        assert_eq!(
+            parse_password_hashes(&format!("{}:{}", old, new)).unwrap(),
+            vec![old.to_string(), new.to_string()]
+        );
+        assert!(parse_password_hashes(&format!("{}:not a hash", old)).is_err());
+        assert!(parse_password_hashes(&format!("{}:", old)).is_err());
+    }
+
+    #[test]
+    fn file_extensions_are_trimmed() {
+        let extensions = |raw: &str| raw.split(',').map(str::to_string).collect();
+        assert_eq!(
+            validate_file_extensions(extensions("jpg, png ")).unwrap(),
+            vec!["jpg".to_string(), "png".to_string()]
+        );
+        assert!(validate_file_extensions(extensions("jpg,")).is_err());
+        assert!(validate_file_extensions(extensions("jpg,p.ng")).is_err());
+        assert!(validate_file_extensions(Vec::new()).is_err());
+    }
 }
@@ -0,0 +1,127 @@
+use axum::{
+    body::Body,
+    extract::State,
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use mime_guess::from_path;
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::task::spawn_blocking;
+
+const SNIFF_LENGTH: usize = 16;
+
+/// Checks the leading bytes of a file against the signature of the image type its extension
+/// claims. Types without a known signature never match.
+fn matches_signature(mime: &str, head: &[u8]) -> bool {
+    let is_ftyp = |brands: &[&[u8]]| {
+        head.get(4..8) == Some(b"ftyp")
+            && head.get(8..12).is_some_and(|brand| brands.contains(&brand))
+    };
+    match mime {
+        "image/jpeg" => head.starts_with(&[0xff, 0xd8, 0xff]),
+        "image/png" => head.starts_with(b"\x89PNG\r\n\x1a\n"),
+        "image/gif" => head.starts_with(b"GIF87a") || head.starts_with(b"GIF89a"),
+        "image/webp" => head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WEBP"),
+        "image/bmp" => head.starts_with(b"BM"),
+        "image/tiff" => head.starts_with(b"II*\0") || head.starts_with(b"MM\0*"),
+        "image/avif" => is_ftyp(&[b"avif", b"avis"]),
+        "image/heic" | "image/heif" => is_ftyp(&[b"heic", b"heix", b"mif1", b"msf1"]),
+        _ => false,
+    }
+}
+
+/// Resolves a request path below the data route to a file in the served directory, mirroring
+/// the rules of `ServeDir`. Returns `None` for paths it would reject anyway.
+fn resolve_path(serve_dir: &Path, request_path: &str) -> Option<PathBuf> {
+    let decoded = percent_encoding::percent_decode_str(request_path)
+        .decode_utf8()
+        .ok()?;
+    let mut path = serve_dir.to_path_buf();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return None,
+            segment => path.push(segment),
+        }
+    }
+    Some(path)
+}
+
+fn read_head(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(SNIFF_LENGTH);
+    std::fs::File::open(path)?
+        .take(SNIFF_LENGTH as u64)
+        .read_to_end(&mut head)?;
+    Ok(head)
+}
+
+/// Rejects data files whose content does not match the image type claimed by their extension,
+/// so e.g. an HTML file renamed to `.jpg` is never served as an image.
+pub async fn validate_content(
+    State(serve_dir): State<Arc<PathBuf>>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let Some(path) = resolve_path(&serve_dir, req.uri().path()) else {
+        return next.run(req).await;
+    };
+
+    let (path, head) = match spawn_blocking(move || {
+        let head = read_head(&path);
+        (path, head)
+    })
+    .await
+    {
+        Ok((path, Ok(head))) => (path, head),
+        // missing or unreadable files are left to the file service to report
+        _ => return next.run(req).await,
+    };
+
+    let mime = from_path(&path).first_or_octet_stream();
+    if matches_signature(mime.essence_str(), &head) {
+        next.run(req).await
+    } else {
+        tracing::warn!("Content of {:?} does not match its type {}", &path, mime);
+        (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Content does not match file type",
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signatures_follow_the_claimed_type() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        assert!(matches_signature("image/png", png));
+        assert!(!matches_signature("image/jpeg", png));
+        assert!(matches_signature(
+            "image/avif",
+            b"\0\0\0\x1cftypavif\0\0\0\0"
+        ));
+        assert!(!matches_signature("image/avif", b"\0\0\0\x1cftypmp42"));
+        // too short to hold the brand
+        assert!(!matches_signature("image/webp", b"RIFF\0\0\0\0WE"));
+        assert!(!matches_signature("text/html", b"<html>"));
+    }
+
+    #[test]
+    fn request_paths_resolve_like_serve_dir() {
+        let serve_dir = Path::new("/srv/images");
+        assert_eq!(
+            resolve_path(serve_dir, "/sub/./a%20b.jpg"),
+            Some(PathBuf::from("/srv/images/sub/a b.jpg"))
+        );
+        assert_eq!(resolve_path(serve_dir, "/sub/../../etc/passwd"), None);
+        assert_eq!(resolve_path(serve_dir, "/%2e%2e/etc/passwd"), None);
+    }
+}
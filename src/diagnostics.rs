@@ -0,0 +1,26 @@
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+#[derive(Debug, Serialize)]
+pub struct QueueDepth {
+    len: usize,
+    capacity: usize,
+}
+
+/// Queued messages of an actor's channel, or `None` if the actor is gone.
+pub fn queue_depth<T>(sender: &mpsc::WeakSender<T>) -> Option<QueueDepth> {
+    let sender = sender.upgrade()?;
+    Some(QueueDepth {
+        len: sender.max_capacity() - sender.capacity(),
+        capacity: sender.max_capacity(),
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueueDepths {
+    pub authentication: Option<QueueDepth>,
+    pub authentication_token_store: Option<QueueDepth>,
+    pub file_tracker: Option<QueueDepth>,
+    pub file_change_tracker: Option<QueueDepth>,
+    pub web_sockets: usize,
+}
@@ -0,0 +1,149 @@
+use crate::file_tracker_actor::{FileTrackerActor, FileTrackerActorEvent};
+use axum::{
+    body::{Body, Bytes},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use futures_util::stream;
+use serde::Deserialize;
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+use tokio::{sync::mpsc, task::spawn_blocking};
+use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const CHUNKS_IN_FLIGHT: usize = 4;
+
+#[derive(Debug, Deserialize)]
+pub struct DownloadQuery {
+    prefix: Option<String>,
+}
+
+/// Forwards written bytes in fixed-size chunks to the response body, so memory stays bounded
+/// by the number of chunks in flight rather than the size of the archive.
+struct ChunkWriter {
+    sender: mpsc::Sender<io::Result<Bytes>>,
+    buffer: Vec<u8>,
+}
+
+impl ChunkWriter {
+    fn send_buffer(&mut self) -> io::Result<()> {
+        let chunk = Bytes::from(std::mem::replace(
+            &mut self.buffer,
+            Vec::with_capacity(CHUNK_SIZE),
+        ));
+        self.sender
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client went away"))
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.send_buffer()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.send_buffer()?;
+        }
+        Ok(())
+    }
+}
+
+fn write_archive(serve_dir: &Path, paths: Vec<PathBuf>, writer: ChunkWriter) -> io::Result<()> {
+    let mut zip = ZipWriter::new_stream(writer);
+
+    for path in paths {
+        // files removed since the baseline was taken are left out of the archive
+        let mut file = match File::open(serve_dir.join(&path)) {
+            Ok(file) => file,
+            Err(e) => {
+                tracing::debug!("Skipping {:?} in download: {}", &path, e);
+                continue;
+            }
+        };
+        let large_file = file
+            .metadata()
+            .is_ok_and(|metadata| metadata.len() > u32::MAX as u64);
+        // images are compressed already, deflating them again only costs time
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .large_file(large_file);
+        zip.start_file(path.to_string_lossy(), options)?;
+        if let Err(e) = io::copy(&mut file, &mut zip) {
+            if e.kind() == io::ErrorKind::BrokenPipe {
+                return Err(e);
+            }
+            tracing::warn!("Failed to read {:?} for download: {}", &path, e);
+        }
+    }
+
+    zip.finish()?.into_inner().flush()
+}
+
+#[tracing::instrument(level = "trace")]
+pub async fn serve_download_all(
+    file_tracker_actor_sender: mpsc::WeakSender<FileTrackerActorEvent>,
+    serve_dir: PathBuf,
+    DownloadQuery { prefix }: DownloadQuery,
+) -> Response {
+    let Some(file_tracker_actor_sender) = file_tracker_actor_sender.upgrade() else {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Service restarting").into_response();
+    };
+
+    let baseline = match FileTrackerActor::request_baseline(&file_tracker_actor_sender).await {
+        Ok(baseline) => baseline,
+        Err(e) => {
+            tracing::error!("Failed to request baseline: {}", e);
+            return (StatusCode::SERVICE_UNAVAILABLE, "Service restarting").into_response();
+        }
+    };
+
+    let prefix = prefix
+        .as_deref()
+        .map(|prefix| PathBuf::from(prefix.trim_matches('/')));
+    let paths: Vec<_> = baseline
+        .0
+        .into_iter()
+        .map(|entry| entry.path)
+        .filter(|path| {
+            prefix
+                .as_ref()
+                .is_none_or(|prefix| path.starts_with(prefix))
+        })
+        .collect();
+
+    let (sender, receiver) = mpsc::channel(CHUNKS_IN_FLIGHT);
+    let writer = ChunkWriter {
+        sender: sender.clone(),
+        buffer: Vec::with_capacity(CHUNK_SIZE),
+    };
+    spawn_blocking(move || {
+        if let Err(e) = write_archive(&serve_dir, paths, writer) {
+            tracing::warn!("Download archive aborted: {}", e);
+            let _ = sender.blocking_send(Err(e));
+        }
+    });
+
+    let body_stream = stream::unfold(receiver, async |mut receiver| {
+        let chunk = receiver.recv().await?;
+        Some((chunk, receiver))
+    });
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/zip")
+        .header(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"images.zip\"",
+        )
+        .body(Body::from_stream(body_stream))
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+}
@@ -1,13 +1,28 @@
 use thiserror::Error;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Axum error: {0}")]
     AxumError(#[from] axum::Error),
+    #[error("Config error: {0}")]
+    ConfigError(String),
+    #[error("Authentication config error: {0}")]
+    AuthConfigError(String),
+    #[error("Service busy")]
+    ServiceBusy,
+    #[error("Actor did not respond in time")]
+    ResponseTimeout,
+    #[error("notify::Error: {0}")]
+    NotifyError(#[from] notify::Error),
+    #[error("metrics_exporter_prometheus::BuildError: {0}")]
+    MetricsBuildError(#[from] metrics_exporter_prometheus::BuildError),
     #[error("DotEnvy error: {0}")]
     DotEnvyError(#[from] dotenvy::Error),
     #[error("SerdeJson error: {0}")]
     SerdeJsonError(#[from] serde_json::Error),
+    #[error("rmp_serde::encode::Error: {0}")]
+    RmpSerdeEncodeError(#[from] rmp_serde::encode::Error),
     #[error("std::var::EnvError: {0}")]
     StdVarEnvError(#[from] std::env::VarError),
     #[error("std::io::Error: {0}")]
@@ -24,6 +39,9 @@ pub enum Error {
     TokioJoinError(#[from] tokio::task::JoinError),
     #[error("tokio::sync::oneshot::error::RecvError: {0}")]
     TokioSyncOneshotReceiveError(#[from] tokio::sync::oneshot::error::RecvError),
+    #[cfg(feature = "s3")]
+    #[error("object_store::Error: {0}")]
+    ObjectStoreError(#[from] object_store::Error),
 }
 
 impl<T> From<std::sync::PoisonError<T>> for Error {
@@ -1,42 +1,601 @@
-use serde::Serialize;
-use serde_with::{TimestampMilliSeconds, serde_as};
-use std::{path::PathBuf, time::SystemTime};
+use chrono::{DateTime, Local, SecondsFormat};
+use serde::{Deserialize, Serialize, ser::SerializeSeq};
+use serde_with::{TimestampMilliSeconds, ser::SerializeAsWrap, serde_as};
+use std::{
+    cmp::Ordering,
+    collections::HashSet,
+    fs::Metadata,
+    hash::{DefaultHasher, Hash, Hasher},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+    time::SystemTime,
+};
+
+/// How the modification times of tracked files are sent to clients.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum TimestampFormat {
+    /// Milliseconds since the Unix epoch.
+    #[default]
+    EpochMs,
+    /// An ISO 8601 string in the local time zone, as set by `TZ`.
+    Iso8601,
+}
+
+/// The order of the tracked files, in the baseline as in each change.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum SortOrder {
+    /// Newest first.
+    #[default]
+    MtimeDesc,
+    MtimeAsc,
+    NameAsc,
+    NameDesc,
+}
+
+impl SortOrder {
+    pub fn compare(self, a: &FileEntry, b: &FileEntry) -> Ordering {
+        match self {
+            SortOrder::MtimeDesc => b.mtime.cmp(&a.mtime),
+            SortOrder::MtimeAsc => a.mtime.cmp(&b.mtime),
+            SortOrder::NameAsc => a.path.cmp(&b.path),
+            SortOrder::NameDesc => b.path.cmp(&a.path),
+        }
+    }
+
+    /// Whether a file moves within the order when it is renamed.
+    pub fn by_name(self) -> bool {
+        matches!(self, SortOrder::NameAsc | SortOrder::NameDesc)
+    }
+}
+
+static TIMESTAMP_FORMAT: OnceLock<TimestampFormat> = OnceLock::new();
+
+/// Sets the format of all file entries serialized from now on.
+pub fn set_timestamp_format(timestamp_format: TimestampFormat) {
+    let _ = TIMESTAMP_FORMAT.set(timestamp_format);
+}
+
+/// The name the order is configured by in `SORT_ORDER`.
+impl std::fmt::Display for SortOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SortOrder::MtimeDesc => "mtime_desc",
+            SortOrder::MtimeAsc => "mtime_asc",
+            SortOrder::NameAsc => "name_asc",
+            SortOrder::NameDesc => "name_desc",
+        })
+    }
+}
+
+/// Ownership and permission bits of a file, as reported by the file system.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct FileOwner {
+    pub uid: u32,
+    pub gid: u32,
+    pub mode: u32,
+}
+
+impl FileOwner {
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        Self {
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            // the file type bits say nothing new about a tracked file
+            mode: metadata.mode() & 0o7777,
+        }
+    }
+}
+
+/// Pixel size of an image, as read from its header.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct ImageDimensions {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ImageDimensions {
+    /// Reads only as much of the file as it takes to find the dimensions.
+    pub fn probe(path: &Path) -> Option<Self> {
+        let dimensions = image::ImageReader::open(path)
+            .and_then(|reader| reader.with_guessed_format())
+            .map_err(image::ImageError::from)
+            .and_then(|reader| reader.into_dimensions());
+        match dimensions {
+            Ok((width, height)) => Some(Self { width, height }),
+            Err(e) => {
+                tracing::debug!("Failed to read dimensions of {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+}
+
+/// Horizontal and vertical components of a blurhash, as the blurhash authors suggest.
+const BLURHASH_COMPONENTS: (u32, u32) = (4, 3);
+/// Longest side of the thumbnail a blurhash is encoded from, far more than its components need.
+const BLURHASH_THUMBNAIL_SIZE: u32 = 32;
+
+/// Encodes a placeholder for an image, decoding all of it but encoding only a thumbnail.
+pub fn compute_blurhash(path: &Path) -> Option<String> {
+    let image = image::ImageReader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(image::ImageError::from)
+        .and_then(|reader| reader.decode());
+    let image = match image {
+        Ok(image) => image,
+        Err(e) => {
+            tracing::debug!("Failed to decode {:?} for a blurhash: {}", path, e);
+            return None;
+        }
+    };
+    match blurhash::encode_image(
+        BLURHASH_COMPONENTS.0,
+        BLURHASH_COMPONENTS.1,
+        &image
+            .thumbnail(BLURHASH_THUMBNAIL_SIZE, BLURHASH_THUMBNAIL_SIZE)
+            .to_rgba8(),
+    ) {
+        Ok(blurhash) => Some(blurhash),
+        Err(e) => {
+            tracing::debug!("Failed to encode a blurhash of {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Information derived from a file beyond its path and modification time.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct FileMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Size in bytes, left out only for entries that came without one, as emitted changes may.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default, flatten, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<FileOwner>,
+    /// Left out unless dimensions are extracted, and null for files whose header could not be
+    /// read.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "serde_with::rust::double_option"
+    )]
+    pub dimensions: Option<Option<ImageDimensions>>,
+    /// Left out unless blurhashes are emitted or for files that could not be decoded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blurhash: Option<String>,
+}
+
+impl FileMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.size.is_none()
+            && self.owner.is_none()
+            && self.dimensions.is_none()
+            && self.blurhash.is_none()
+    }
+}
 
 #[serde_as]
-#[derive(Clone, Debug, Default, Serialize)]
-pub struct FileAddData(
-    #[serde_as(as = "Vec<(_, TimestampMilliSeconds<i64>)>")] pub Vec<(PathBuf, SystemTime)>,
-);
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FileEntryRepr {
+    WithMetadata(
+        PathBuf,
+        #[serde_as(as = "TimestampMilliSeconds<i64>")] SystemTime,
+        FileMetadata,
+    ),
+    Plain(
+        PathBuf,
+        #[serde_as(as = "TimestampMilliSeconds<i64>")] SystemTime,
+    ),
+}
+
+/// A tracked file, sent as `[path, mtime]` or `[path, mtime, metadata]` when there is any
+/// metadata, so clients reading only the first two elements keep working. Entries are always
+/// read with the mtime in epoch milliseconds, whatever `TimestampFormat` they are sent in.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(from = "FileEntryRepr")]
+pub struct FileEntry {
+    pub path: PathBuf,
+    pub mtime: SystemTime,
+    pub metadata: FileMetadata,
+}
+
+impl FileEntry {
+    pub fn new(path: PathBuf, mtime: SystemTime, metadata: FileMetadata) -> Self {
+        Self {
+            path,
+            mtime,
+            metadata,
+        }
+    }
+}
+
+impl From<FileEntryRepr> for FileEntry {
+    fn from(repr: FileEntryRepr) -> Self {
+        match repr {
+            FileEntryRepr::WithMetadata(path, mtime, metadata) => Self::new(path, mtime, metadata),
+            FileEntryRepr::Plain(path, mtime) => Self::new(path, mtime, FileMetadata::default()),
+        }
+    }
+}
+
+impl Serialize for FileEntry {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let with_metadata = !self.metadata.is_empty();
+        let mut seq = serializer.serialize_seq(Some(if with_metadata { 3 } else { 2 }))?;
+        seq.serialize_element(&self.path)?;
+        match TIMESTAMP_FORMAT.get().copied().unwrap_or_default() {
+            TimestampFormat::EpochMs => seq
+                .serialize_element(&SerializeAsWrap::<_, TimestampMilliSeconds<i64>>::new(
+                    &self.mtime,
+                ))?,
+            TimestampFormat::Iso8601 => seq.serialize_element(
+                &DateTime::<Local>::from(self.mtime).to_rfc3339_opts(SecondsFormat::Millis, false),
+            )?,
+        }
+        if with_metadata {
+            seq.serialize_element(&self.metadata)?;
+        }
+        seq.end()
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct FileAddData(pub Vec<FileEntry>);
 
 impl FileAddData {
     pub fn new() -> Self {
         Self(Vec::new())
     }
+
+    /// Identifies the set of paths and timestamps regardless of their order, so a reconnecting
+    /// client can tell whether the list it holds is still current.
+    pub fn fingerprint(&self) -> String {
+        let sum = self.0.iter().fold(0u64, |sum, entry| {
+            let mut hasher = DefaultHasher::new();
+            entry.path.hash(&mut hasher);
+            entry.mtime.hash(&mut hasher);
+            sum.wrapping_add(hasher.finish())
+        });
+        format!("{:016x}", sum)
+    }
 }
 
 #[serde_as]
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct FileRemoveData(pub Vec<PathBuf>);
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct FileMoveData(pub Vec<(PathBuf, PathBuf)>);
+
+impl FileMoveData {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Files that stayed in place but were rewritten, with their new timestamp.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct FileModifyData(pub Vec<FileEntry>);
+
+impl FileModifyData {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Where a page of the baseline a client asked for sits within the files it is sent.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SnapshotPage {
+    pub offset: usize,
+    /// Number of files the client is sent in all, across pages.
+    pub total: usize,
+    /// Whether files remain past this page.
+    pub more: bool,
+    /// Changes applied to the baseline so far; a client seeing it change between pages may have
+    /// missed files that moved across a page boundary and starts over.
+    pub seq: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct FileChangeData {
     pub removed: FileRemoveData,
     pub added: FileAddData,
+    #[serde(default, skip_serializing_if = "FileMoveData::is_empty")]
+    pub moved: FileMoveData,
+    #[serde(default, skip_serializing_if = "FileModifyData::is_empty")]
+    pub modified: FileModifyData,
+    /// Marks `added` as the complete current set, replacing whatever the client held before.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub full: bool,
+    /// Number of tracked files once the change is applied.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<usize>,
+    /// Fingerprint of all tracked files once the change is applied, for clients holding them all.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+    /// Set on pages of the baseline, which carry their files in `added`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<SnapshotPage>,
 }
 
 impl FileChangeData {
-    pub fn new(removed: Vec<PathBuf>, added: Vec<(PathBuf, SystemTime)>) -> Self {
+    pub fn new(
+        removed: Vec<PathBuf>,
+        added: Vec<FileEntry>,
+        moved: Vec<(PathBuf, PathBuf)>,
+    ) -> Self {
         Self {
             removed: FileRemoveData(removed),
             added: FileAddData(added),
+            moved: FileMoveData(moved),
+            modified: FileModifyData::default(),
+            full: false,
+            total: None,
+            fingerprint: None,
+            snapshot: None,
+        }
+    }
+
+    /// A change that replaces the client's state with `baseline`.
+    pub fn full(baseline: FileAddData) -> Self {
+        Self {
+            removed: FileRemoveData(Vec::new()),
+            total: Some(baseline.0.len()),
+            fingerprint: Some(baseline.fingerprint()),
+            added: baseline,
+            moved: FileMoveData::default(),
+            modified: FileModifyData::default(),
+            full: true,
+            snapshot: None,
         }
     }
 
     pub fn is_empty(&self) -> bool {
-        self.removed.0.is_empty() && self.added.0.is_empty()
+        self.removed.0.is_empty()
+            && self.added.0.is_empty()
+            && self.moved.is_empty()
+            && self.modified.is_empty()
     }
 
     pub fn is_not_empty(&self) -> bool {
         !self.is_empty()
     }
+
+    fn len(&self) -> usize {
+        self.removed.0.len() + self.added.0.len() + self.moved.0.len() + self.modified.0.len()
+    }
+
+    /// Splits the change into consecutive changes of at most `max_len` entries each, zero
+    /// meaning no limit. Removals go first, then moves, modifications and additions, and only
+    /// the first part of a full change replaces the client's state.
+    pub fn split(self, max_len: usize) -> Vec<FileChangeData> {
+        if max_len == 0 || self.len() <= max_len {
+            return vec![self];
+        }
+
+        let mut parts = Vec::new();
+        for removed in self.removed.0.chunks(max_len) {
+            parts.push(Self::new(removed.to_vec(), Vec::new(), Vec::new()));
+        }
+        for moved in self.moved.0.chunks(max_len) {
+            parts.push(Self::new(Vec::new(), Vec::new(), moved.to_vec()));
+        }
+        for modified in self.modified.0.chunks(max_len) {
+            let mut part = Self::new(Vec::new(), Vec::new(), Vec::new());
+            part.modified = FileModifyData(modified.to_vec());
+            parts.push(part);
+        }
+        for added in self.added.0.chunks(max_len) {
+            parts.push(Self::new(Vec::new(), added.to_vec(), Vec::new()));
+        }
+        if let Some(first) = parts.first_mut() {
+            first.full = self.full;
+        }
+        for part in &mut parts {
+            part.total = self.total;
+        }
+        // the state only matches the fingerprint once every part is applied
+        if let Some(last) = parts.last_mut() {
+            last.fingerprint = self.fingerprint;
+        }
+        parts
+    }
+
+    /// Whether `later` can be folded into this change with `merge`. Moves apply before everything
+    /// else in a change, and a full change replaces the state, so neither merges.
+    pub fn can_merge(&self, later: &FileChangeData) -> bool {
+        !self.full && !later.full && self.moved.is_empty() && later.moved.is_empty()
+    }
+
+    /// Folds `later` into this change, so that applying the result has the effect of applying
+    /// both in order. A file added and removed again in between nets to nothing.
+    pub fn merge(&mut self, later: FileChangeData) {
+        debug_assert!(self.can_merge(&later));
+        let added_paths: HashSet<_> = self
+            .added
+            .0
+            .iter()
+            .map(|entry| entry.path.clone())
+            .collect();
+        let modified_paths: HashSet<_> = self
+            .modified
+            .0
+            .iter()
+            .map(|entry| entry.path.clone())
+            .collect();
+        let superseded_paths: HashSet<_> = later
+            .removed
+            .0
+            .iter()
+            .chain(later.added.0.iter().map(|entry| &entry.path))
+            .chain(later.modified.0.iter().map(|entry| &entry.path))
+            .collect();
+        self.added
+            .0
+            .retain(|entry| !superseded_paths.contains(&entry.path));
+        self.modified
+            .0
+            .retain(|entry| !superseded_paths.contains(&entry.path));
+
+        // a file the client was not told about needs no removal; one it replaced is removed already
+        let removed_paths: HashSet<_> = self.removed.0.iter().cloned().collect();
+        self.removed.0.extend(
+            later
+                .removed
+                .0
+                .into_iter()
+                .filter(|path| !added_paths.contains(path) && !removed_paths.contains(path)),
+        );
+        // a file stays new to the client however often it changes in between
+        for entry in later.modified.0 {
+            if added_paths.contains(&entry.path) {
+                self.added.0.push(entry);
+            } else {
+                self.modified.0.push(entry);
+            }
+        }
+        for entry in later.added.0 {
+            if modified_paths.contains(&entry.path) {
+                self.modified.0.push(entry);
+            } else {
+                self.added.0.push(entry);
+            }
+        }
+
+        self.total = later.total;
+        self.fingerprint = later.fingerprint;
+    }
+
+    /// Keeps only entries whose path `matches`. A move out of the subset becomes a removal; a
+    /// move into it is dropped, as its timestamp is not known here, and the file shows up with
+    /// the next baseline.
+    pub fn retain_paths(&mut self, matches: impl Fn(&Path) -> bool) {
+        // both describe all tracked files, which would mislead a client seeing only some
+        self.total = None;
+        self.fingerprint = None;
+        self.removed.0.retain(|path| matches(path));
+        self.added.0.retain(|entry| matches(&entry.path));
+        self.modified.0.retain(|entry| matches(&entry.path));
+        let moved = std::mem::take(&mut self.moved.0);
+        for (from, to) in moved {
+            match (matches(&from), matches(&to)) {
+                (true, true) => self.moved.0.push((from, to)),
+                (true, false) => self.removed.0.push(from),
+                (false, _) => {}
+            }
+        }
+    }
+}
+
+/// A change as applied to the baseline, numbered in the order changes were applied.
+#[derive(Clone, Debug)]
+pub struct SequencedChange {
+    pub seq: u64,
+    pub change: Arc<FileChangeData>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn entry(path: &str, mtime_secs: u64) -> FileEntry {
+        FileEntry::new(
+            PathBuf::from(path),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(mtime_secs),
+            FileMetadata::default(),
+        )
+    }
+
+    fn paths(entries: &[FileEntry]) -> Vec<&Path> {
+        entries.iter().map(|entry| entry.path.as_path()).collect()
+    }
+
+    #[test]
+    fn large_changes_split_into_parts_applied_in_order() {
+        let mut change = FileChangeData::new(
+            vec![PathBuf::from("r1.jpg"), PathBuf::from("r2.jpg")],
+            vec![entry("a1.jpg", 3), entry("a2.jpg", 2), entry("a3.jpg", 1)],
+            vec![(PathBuf::from("from.jpg"), PathBuf::from("to.jpg"))],
+        );
+        change.full = true;
+        change.total = Some(4);
+        change.fingerprint = Some("fingerprint".to_string());
+
+        let parts = change.clone().split(2);
+        let shapes: Vec<_> = parts
+            .iter()
+            .map(|part| (part.removed.0.len(), part.moved.0.len(), part.added.0.len()))
+            .collect();
+        assert_eq!(shapes, [(2, 0, 0), (0, 1, 0), (0, 0, 2), (0, 0, 1)]);
+        assert_eq!(paths(&parts[3].added.0), [Path::new("a3.jpg")]);
+        assert_eq!(
+            parts.iter().map(|part| part.full).collect::<Vec<_>>(),
+            [true, false, false, false]
+        );
+        assert!(parts.iter().all(|part| part.total == Some(4)));
+        assert_eq!(
+            parts
+                .iter()
+                .map(|part| part.fingerprint.as_deref())
+                .collect::<Vec<_>>(),
+            [None, None, None, Some("fingerprint")]
+        );
+
+        assert_eq!(change.clone().split(0).len(), 1);
+        assert_eq!(change.split(6).len(), 1);
+    }
+
+    #[test]
+    fn adding_then_removing_a_file_merges_to_nothing() {
+        let mut change = FileChangeData::new(Vec::new(), vec![entry("a.jpg", 1)], Vec::new());
+        let later = FileChangeData::new(vec![PathBuf::from("a.jpg")], Vec::new(), Vec::new());
+
+        assert!(change.can_merge(&later));
+        change.merge(later);
+        assert!(change.is_empty(), "{:?}", change);
+    }
+
+    #[test]
+    fn removing_then_adding_a_file_keeps_both() {
+        let mut change = FileChangeData::new(vec![PathBuf::from("a.jpg")], Vec::new(), Vec::new());
+        change.merge(FileChangeData::new(
+            Vec::new(),
+            vec![entry("a.jpg", 2)],
+            Vec::new(),
+        ));
+
+        assert_eq!(change.removed.0, [PathBuf::from("a.jpg")]);
+        assert_eq!(paths(&change.added.0), [Path::new("a.jpg")]);
+    }
+
+    #[test]
+    fn moves_and_full_changes_do_not_merge() {
+        let plain = FileChangeData::new(Vec::new(), vec![entry("a.jpg", 1)], Vec::new());
+        let moved = FileChangeData::new(
+            Vec::new(),
+            Vec::new(),
+            vec![(PathBuf::from("a.jpg"), PathBuf::from("b.jpg"))],
+        );
+        let full = FileChangeData::full(FileAddData(vec![entry("a.jpg", 1)]));
+
+        assert!(!plain.can_merge(&moved));
+        assert!(!moved.can_merge(&plain));
+        assert!(!plain.can_merge(&full));
+        assert!(!full.can_merge(&plain));
+    }
+
+    #[test]
+    fn sizes_are_serialized_with_the_entry() {
+        let mut sized = entry("a.jpg", 1);
+        sized.metadata.size = Some(12345);
+        assert_eq!(
+            serde_json::to_value(FileAddData(vec![sized, entry("b.jpg", 2)])).unwrap(),
+            serde_json::json!([["a.jpg", 1000, { "size": 12345 }], ["b.jpg", 2000]])
+        );
+    }
 }
@@ -1,118 +1,1363 @@
-use crate::file_tracker_actor::{FileTrackerActor, FileTrackerActorEvent};
-use std::cmp::Reverse;
-use std::{collections::HashSet, mem::take, path::PathBuf, time::Duration};
+#[cfg(feature = "s3")]
+use crate::object_store_source::{ListedObject, ObjectStoreSource};
+use crate::{
+    config::validate_file_extensions,
+    error::Result,
+    file_change_data::{
+        FileChangeData, FileEntry, FileMetadata, FileModifyData, FileOwner, ImageDimensions,
+        SortOrder, compute_blurhash,
+    },
+    file_tracker_actor::FileTrackerActorEvent,
+    health::Heartbeat,
+    metrics, tokio_util,
+};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use regex::{Regex, RegexSet};
+use std::os::unix::fs::MetadataExt;
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    fs::Metadata,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+#[cfg(feature = "s3")]
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::Arc,
+};
 use tokio::{
-    sync::mpsc,
+    sync::{mpsc, oneshot},
     task::spawn_blocking,
-    time::{Interval, MissedTickBehavior},
+    time::{Instant, Interval, MissedTickBehavior},
 };
 use tracing::instrument;
 
+/// How long a single attempt waits for room in the file tracker's queue.
+const SEND_CHANGE_TIMEOUT: Duration = Duration::from_secs(5);
+const SEND_CHANGE_ATTEMPTS: u32 = 3;
+const SEND_CHANGE_INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+/// How long changed paths are collected after a filesystem event before they are scanned.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(100);
+const WATCH_QUEUE_LENGTH: usize = 1024;
+
+type WatchEvent = notify::Result<notify::Event>;
+
+/// How changes to the served directory are noticed.
+#[derive(Clone, Copy, Debug)]
+pub enum WatchMode {
+    /// Every rescrape interval walks the whole directory.
+    Poll,
+    /// Filesystem events trigger scans of just the changed paths. The whole directory is walked
+    /// every `reconcile_every` rescrape intervals only, to catch events that were missed.
+    Notify { reconcile_every: u32 },
+}
+
+/// Identifies file contents across renames: a renamed file keeps its inode, size and mtime.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct FileIdentity {
+    dev: u64,
+    ino: u64,
+    len: u64,
+    modified: SystemTime,
+}
+
+impl FileIdentity {
+    fn from_metadata(metadata: &Metadata) -> Option<Self> {
+        Some(Self {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            len: metadata.len(),
+            modified: metadata.modified().ok()?,
+        })
+    }
+
+    /// Objects have no inode, the entity tag stands in for it so copies of the same content
+    /// can still be recognized as moves.
+    #[cfg(feature = "s3")]
+    fn from_object(object: &ListedObject) -> Self {
+        let mut hasher = DefaultHasher::new();
+        object.e_tag.hash(&mut hasher);
+        Self {
+            dev: 0,
+            ino: hasher.finish(),
+            len: object.size,
+            modified: object.modified,
+        }
+    }
+}
+
+/// Where the tracked files are listed from.
+#[derive(Debug)]
+pub enum FileSource {
+    Directory(PathBuf),
+    #[cfg(feature = "s3")]
+    ObjectStore(Arc<ObjectStoreSource>),
+}
+
+/// The path under which a file is served, as clients see it.
+fn served_path(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Keeps one deterministic winner per served path, warning once about each new collision.
+fn resolve_served_path_collisions(
+    found: HashSet<PathBuf>,
+    known_collisions: &mut HashSet<String>,
+) -> HashSet<PathBuf> {
+    let mut by_served_path: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    for path in found {
+        by_served_path
+            .entry(served_path(&path))
+            .or_default()
+            .push(path);
+    }
+
+    known_collisions.retain(|served_path| {
+        by_served_path
+            .get(served_path)
+            .is_some_and(|paths| paths.len() > 1)
+    });
+
+    by_served_path
+        .into_iter()
+        .map(|(served_path, mut paths)| {
+            paths.sort();
+            if paths.len() > 1 && known_collisions.insert(served_path.clone()) {
+                tracing::warn!(
+                    "Files {:?} collide on served path {:?}, serving {:?}",
+                    paths,
+                    served_path,
+                    paths[0]
+                );
+            }
+            paths.swap_remove(0)
+        })
+        .collect()
+}
+
+/// Extracts a title from the file name: the `title` group if the pattern has one, otherwise
+/// the first group. Files whose name does not match get no title.
+fn extract_title(title_regex: &Regex, path: &Path) -> Option<String> {
+    let file_name = path.file_name()?.to_str()?;
+    let captures = title_regex.captures(file_name)?;
+    captures
+        .name("title")
+        .or_else(|| captures.get(1))
+        .map(|title| title.as_str().to_string())
+}
+
+/// Whether a file or directory is left out of scanning: always when hidden, such as a .env next
+/// to the images, otherwise when its name matches one of `ignore_patterns`.
+fn is_ignored_name(name: &OsStr, ignore_patterns: &RegexSet) -> bool {
+    name.as_encoded_bytes().starts_with(b".") || ignore_patterns.is_match(&name.to_string_lossy())
+}
+
+/// Whether the path is in or below a file or directory left out of scanning.
+fn is_ignored(path: &Path, ignore_patterns: &RegexSet) -> bool {
+    path.components()
+        .any(|component| is_ignored_name(component.as_os_str(), ignore_patterns))
+}
+
+/// A recursive watch on the served directory.
+#[derive(Debug)]
+struct DirectoryWatch {
+    /// Keeps the watch alive while its events arrive on `event_receiver`.
+    _watcher: RecommendedWatcher,
+    root: PathBuf,
+    event_receiver: mpsc::Receiver<WatchEvent>,
+}
+
+async fn receive_watch_event(watch: &mut Option<DirectoryWatch>) -> Option<WatchEvent> {
+    match watch {
+        Some(watch) => watch.event_receiver.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn sleep_until_if_some(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[derive(Debug)]
+pub enum FileChangeTrackerActorEvent {
+    SetFileExtensions {
+        file_extensions: Vec<String>,
+        response_sender: oneshot::Sender<()>,
+    },
+}
+
 #[derive(Debug)]
 pub struct FileChangeTrackerActor {
     file_tracker_actor_sender: mpsc::Sender<FileTrackerActorEvent>,
     rescrape_timer: Interval,
-    path_prefix: PathBuf,
+    source: FileSource,
     file_extensions: HashSet<String>,
-    known_files: HashSet<PathBuf>,
+    detect_moves: bool,
+    title_regex: Option<Regex>,
+    ignore_patterns: RegexSet,
+    /// Levels of directories scanned, zero scans all.
+    max_depth: usize,
+    follow_symlinks: bool,
+    emit_file_owner: bool,
+    extract_dimensions: bool,
+    emit_blurhash: bool,
+    /// Blurhashes by file contents, so each is computed once for as long as the file is known.
+    blurhashes: HashMap<FileIdentity, Option<String>>,
+    sort_order: SortOrder,
+    known_files: HashMap<PathBuf, FileIdentity>,
+    known_collisions: HashSet<String>,
+    /// How long a new file must stay unchanged before it is reported; zero reports it at once.
+    settle_delay: Duration,
+    /// New files waiting to settle, with the identity they have had since the given instant.
+    pending_files: HashMap<PathBuf, (FileIdentity, Instant)>,
+    watch: Option<DirectoryWatch>,
+    heartbeat: Heartbeat,
+    heartbeat_timer: Interval,
+    /// Paths that changed since the last scan; the empty path stands for the whole directory.
+    dirty_paths: HashSet<PathBuf>,
+    dirty_scan_deadline: Option<Instant>,
 }
 
 impl FileChangeTrackerActor {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         file_tracker_actor_sender: mpsc::Sender<FileTrackerActorEvent>,
         rescrape_interval: Duration,
-        path_prefix: PathBuf,
+        source: FileSource,
         file_extensions: Vec<String>,
-    ) -> Self {
+        detect_moves: bool,
+        title_regex: Option<Regex>,
+        ignore_patterns: RegexSet,
+        max_depth: usize,
+        follow_symlinks: bool,
+        emit_file_owner: bool,
+        extract_dimensions: bool,
+        emit_blurhash: bool,
+        sort_order: SortOrder,
+        settle_delay: Duration,
+        watch_mode: WatchMode,
+    ) -> Result<Self> {
         let file_extensions = file_extensions.into_iter().collect();
-        let mut rescrape_timer = tokio::time::interval(rescrape_interval);
+        let mut rescrape_timer = tokio::time::interval(match watch_mode {
+            WatchMode::Poll => rescrape_interval,
+            WatchMode::Notify { reconcile_every } => rescrape_interval * reconcile_every,
+        });
         // continue with intended interval even if the timer is missed
         rescrape_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
-        let known_files = HashSet::new();
+        let known_files = HashMap::new();
+        let known_collisions = HashSet::new();
 
-        Self {
+        let watch = match (watch_mode, &source) {
+            (WatchMode::Notify { .. }, FileSource::Directory(path_prefix)) => {
+                let (sender, receiver) = mpsc::channel(WATCH_QUEUE_LENGTH);
+                // the watcher calls back on a thread of its own, outside the runtime; while the
+                // queue is full, events pile up in the kernel until it asks for a rescan
+                let mut watcher = notify::recommended_watcher(move |event| {
+                    let _ = sender.blocking_send(event);
+                })?;
+                watcher.watch(path_prefix, RecursiveMode::Recursive)?;
+                tracing::info!("Watching {:?} for changes", path_prefix);
+                Some(DirectoryWatch {
+                    _watcher: watcher,
+                    root: path_prefix.clone(),
+                    event_receiver: receiver,
+                })
+            }
+            _ => None,
+        };
+
+        Ok(Self {
             file_tracker_actor_sender,
             rescrape_timer,
-            path_prefix,
+            source,
             file_extensions,
+            detect_moves,
+            title_regex,
+            ignore_patterns,
+            max_depth,
+            follow_symlinks,
+            emit_file_owner,
+            extract_dimensions,
+            emit_blurhash,
+            blurhashes: HashMap::new(),
+            sort_order,
             known_files,
+            known_collisions,
+            settle_delay,
+            pending_files: HashMap::new(),
+            watch,
+            heartbeat: Heartbeat::default(),
+            heartbeat_timer: Heartbeat::timer(),
+            dirty_paths: HashSet::new(),
+            dirty_scan_deadline: None,
+        })
+    }
+
+    /// Shared heartbeat, beaten from the run loop for the health check.
+    pub fn heartbeat(&self) -> Heartbeat {
+        self.heartbeat.clone()
+    }
+
+    fn mark_dirty(&mut self, path: PathBuf) {
+        self.dirty_paths.insert(path);
+        self.scan_dirty_by(Instant::now() + WATCH_DEBOUNCE);
+    }
+
+    /// Moves the next scan of the dirty paths forward to `deadline` unless it is due earlier.
+    fn scan_dirty_by(&mut self, deadline: Instant) {
+        self.dirty_scan_deadline = Some(
+            self.dirty_scan_deadline
+                .map_or(deadline, |scheduled| scheduled.min(deadline)),
+        );
+    }
+
+    fn handle_watch_event(&mut self, event: WatchEvent) {
+        let Some(watch) = &self.watch else {
+            return;
+        };
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                tracing::warn!("Watching for changes failed, scanning everything: {}", e);
+                self.mark_dirty(PathBuf::new());
+                return;
+            }
+        };
+        if event.need_rescan() {
+            tracing::warn!("Missed changes to watched files, scanning everything");
+            self.mark_dirty(PathBuf::new());
+            return;
+        }
+        if event.kind.is_access() {
+            return;
+        }
+        let paths: Vec<_> = event
+            .paths
+            .iter()
+            .filter_map(|path| path.strip_prefix(&watch.root).ok())
+            .filter(|path| !is_ignored(path, &self.ignore_patterns))
+            .map(Path::to_path_buf)
+            .collect();
+        for path in paths {
+            self.mark_dirty(path);
         }
     }
 
+    /// Hands a change to the file tracker, retrying with backoff while its queue stays full.
+    /// Returns whether the change was delivered.
+    async fn deliver_change(&self, change: FileChangeData) -> bool {
+        let mut backoff = SEND_CHANGE_INITIAL_BACKOFF;
+        for attempt in 1..=SEND_CHANGE_ATTEMPTS {
+            // reserving first keeps the change if the attempt times out
+            match tokio::time::timeout(
+                SEND_CHANGE_TIMEOUT,
+                self.file_tracker_actor_sender.reserve(),
+            )
+            .await
+            {
+                Ok(Ok(permit)) => {
+                    permit.send(FileTrackerActorEvent::Change(change));
+                    return true;
+                }
+                Ok(Err(e)) => {
+                    tracing::error!("File tracker is gone, dropping change: {}", e);
+                    return false;
+                }
+                Err(_) => tracing::warn!(
+                    attempt,
+                    "File tracker did not accept change within {:?}",
+                    SEND_CHANGE_TIMEOUT
+                ),
+            }
+            if attempt < SEND_CHANGE_ATTEMPTS {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+        }
+        false
+    }
+
+    async fn rescrape(&mut self) -> Result<()> {
+        let start = Instant::now();
+        let result = self.scan(None).await;
+        metrics::record_rescrape(start.elapsed());
+        result
+    }
+
+    /// Brings the known files up to date. With `dirty_paths`, only files at or below those paths
+    /// are looked at and all others are taken to be unchanged.
     #[instrument(level = "trace")]
-    async fn rescrape(&mut self) -> crate::error::Result<()> {
-        let known_files = take(&mut self.known_files);
-        let path_prefix = self.path_prefix.clone();
+    async fn scan(&mut self, dirty_paths: Option<HashSet<PathBuf>>) -> Result<()> {
+        // the blocking task works on copies so a failed scan leaves the known state untouched
+        // objects come with their metadata, so they are listed up front instead of looked up
+        // one by one like files
+        let (path_prefix, objects): (_, Option<HashMap<PathBuf, FileIdentity>>) = match &self.source
+        {
+            FileSource::Directory(path_prefix) => (path_prefix.clone(), None),
+            #[cfg(feature = "s3")]
+            FileSource::ObjectStore(source) => match source.list().await {
+                Ok(objects) => {
+                    let objects = objects
+                        .into_iter()
+                        .map(|object| {
+                            let identity = FileIdentity::from_object(&object);
+                            (object.path, identity)
+                        })
+                        .collect();
+                    (PathBuf::new(), Some(objects))
+                }
+                Err(e) => {
+                    tracing::error!("Listing objects failed, skipping this cycle: {}", e);
+                    return Ok(());
+                }
+            },
+        };
+        let known_files = self.known_files.clone();
         let file_extensions = self.file_extensions.clone();
+        let detect_moves = self.detect_moves;
+        let title_regex = self.title_regex.clone();
+        let ignore_patterns = self.ignore_patterns.clone();
+        let max_depth = self.max_depth;
+        let follow_symlinks = self.follow_symlinks;
+        let emit_file_owner = self.emit_file_owner;
+        let extract_dimensions = self.extract_dimensions;
+        let emit_blurhash = self.emit_blurhash;
+        let mut blurhashes = self.blurhashes.clone();
+        let sort_order = self.sort_order;
+        let mut known_collisions = self.known_collisions.clone();
+        let settle_delay = self.settle_delay;
+        let mut pending_files = self.pending_files.clone();
 
-        let (found, file_change_data) = spawn_blocking(move || {
-            let found: HashSet<_> = walkdir::WalkDir::new(&path_prefix)
-                .into_iter()
-                .filter_map(|e| e.ok())
-                .filter(|e| e.path().is_file())
-                .filter_map(|e| {
-                    e.path()
-                        .strip_prefix(&path_prefix)
-                        .map(|p| p.to_path_buf())
-                        .ok()
+        let scan = spawn_blocking(move || {
+            let has_tracked_extension = |path: &Path| {
+                path.extension()
+                    .and_then(|extension| extension.to_str())
+                    .is_some_and(|extension| file_extensions.contains(extension))
+            };
+
+            // whether a path lies deeper than scanned, counting the files in the root as level 1
+            let too_deep = |path: &Path| max_depth != 0 && path.components().count() > max_depth;
+
+            // walks `root`, itself `depth` levels below the served directory
+            let walk = |root: &Path, depth: usize| {
+                let mut walk_dir = walkdir::WalkDir::new(root).follow_links(follow_symlinks);
+                if max_depth != 0 {
+                    walk_dir = walk_dir.max_depth(max_depth.saturating_sub(depth));
+                }
+                walk_dir
+                    .into_iter()
+                    // ignored directories are not descended into
+                    .filter_entry(|e| {
+                        e.depth() == 0 || !is_ignored_name(e.file_name(), &ignore_patterns)
+                    })
+                    .filter_map(|e| e.ok())
+                    .filter(|e| e.path().is_file())
+                    .filter_map(|e| {
+                        e.path()
+                            .strip_prefix(&path_prefix)
+                            .map(|p| p.to_path_buf())
+                            .ok()
+                    })
+                    .filter(|path| has_tracked_extension(path))
+                    .collect::<Vec<_>>()
+            };
+
+            let in_scope = |path: &Path| {
+                dirty_paths.as_ref().is_none_or(|dirty_paths| {
+                    path.ancestors().any(|path| dirty_paths.contains(path))
                 })
-                .filter_map(|e| {
-                    let extension = e.extension()?.to_str()?;
-                    if file_extensions.contains(extension) {
-                        Some(e)
-                    } else {
-                        None
+            };
+
+            let found: HashSet<_> = match (&objects, &dirty_paths) {
+                (Some(objects), _) => objects
+                    .keys()
+                    .filter(|path| !is_ignored(path, &ignore_patterns) && !too_deep(path))
+                    .filter(|path| has_tracked_extension(path))
+                    .cloned()
+                    .collect(),
+                (None, None) => walk(&path_prefix, 0).into_iter().collect(),
+                (None, Some(dirty_paths)) => {
+                    let mut found: HashSet<_> = known_files
+                        .keys()
+                        .filter(|path| !in_scope(path))
+                        .filter(|path| has_tracked_extension(path))
+                        .cloned()
+                        .collect();
+                    for dirty_path in dirty_paths.iter().filter(|path| !too_deep(path)) {
+                        let depth = dirty_path.components().count();
+                        found.extend(walk(&path_prefix.join(dirty_path), depth));
                     }
+                    found
+                }
+            };
+
+            let found = resolve_served_path_collisions(found, &mut known_collisions);
+
+            // paths are moved between the collections rather than cloned, as directories with
+            // many files make those copies the bulk of the work
+            let mut new_known_files = known_files;
+            let mut removed: Vec<_> = new_known_files
+                .extract_if(|path, _| !found.contains(path))
+                .collect();
+
+            let identify = |path: &Path| {
+                let (identity, owner) = match &objects {
+                    Some(objects) => (objects.get(path).copied(), None),
+                    None => match path_prefix.join(path).metadata() {
+                        Ok(metadata) => (
+                            FileIdentity::from_metadata(&metadata),
+                            emit_file_owner.then(|| FileOwner::from_metadata(&metadata)),
+                        ),
+                        Err(e) => {
+                            tracing::debug!("Skipping unreadable file {:?}: {}", path, e);
+                            return None;
+                        }
+                    },
+                };
+                let Some(identity) = identity else {
+                    tracing::debug!("Skipping file without modification time {:?}", path);
+                    return None;
+                };
+                // empty files are most likely still being written
+                if identity.len == 0 {
+                    tracing::debug!("Skipping empty file {:?}", path);
+                    return None;
+                }
+                Some((identity, owner))
+            };
+
+            // files that stayed are looked at again, a new timestamp means they were rewritten;
+            // skipped ones keep their known identity and are compared again on the next scan
+            let modified: Vec<_> = new_known_files
+                .iter_mut()
+                .filter(|(path, _)| in_scope(path))
+                .filter_map(|(path, known_identity)| {
+                    let (identity, owner) = identify(path)?;
+                    let previous = std::mem::replace(known_identity, identity);
+                    (identity.modified != previous.modified)
+                        .then(|| (path.clone(), identity, owner))
                 })
                 .collect();
 
-            let file_change_data = crate::file_change_data::FileChangeData::new(
-                known_files.difference(&found).cloned().collect(),
-                {
-                    let mut tmp: Vec<_> = found
-                        .difference(&known_files)
-                        .cloned()
-                        .filter_map(|path| {
-                            path_prefix
-                                .join(path.clone())
-                                .metadata()
-                                .ok()
-                                .and_then(|metadata| metadata.modified().ok())
-                                .map(|timestamp| (path, timestamp))
-                        })
-                        .collect();
-                    tmp.sort_by_key(|(_, time)| Reverse(*time));
-                    tmp
-                },
-            );
+            let mut added: Vec<_> = found
+                .into_iter()
+                .filter(|path| !new_known_files.contains_key(path))
+                .filter_map(|path| {
+                    // skipped files stay out of known_files and are retried on the next scan
+                    let (identity, owner) = identify(&path)?;
+                    Some((path, identity, owner))
+                })
+                .collect();
+
+            let title = |path: &Path| {
+                title_regex
+                    .as_ref()
+                    .and_then(|title_regex| extract_title(title_regex, path))
+            };
 
-            (found, file_change_data)
+            let mut moved = Vec::new();
+            if detect_moves && !removed.is_empty() && !added.is_empty() {
+                let mut added_by_identity: HashMap<_, Vec<_>> = HashMap::new();
+                for (path, identity, owner) in added.drain(..) {
+                    added_by_identity
+                        .entry(identity)
+                        .or_default()
+                        .push((path, owner));
+                }
+                removed.retain(|(from, identity)| {
+                    let Some(paths) = added_by_identity.get_mut(identity) else {
+                        return true;
+                    };
+                    // a rename that changes the title is sent as removal and addition, as
+                    // clients keep the metadata of moved files
+                    match paths.iter().position(|(to, _)| title(to) == title(from)) {
+                        Some(index) => {
+                            let (to, _) = paths.swap_remove(index);
+                            new_known_files.insert(to.clone(), *identity);
+                            moved.push((from.clone(), to));
+                            false
+                        }
+                        None => true,
+                    }
+                });
+                added = added_by_identity
+                    .into_iter()
+                    .flat_map(|(identity, paths)| {
+                        paths
+                            .into_iter()
+                            .map(move |(path, owner)| (path, identity, owner))
+                    })
+                    .collect();
+            }
+
+            // moved files were settled under their old path, new ones wait until they stop
+            // changing, as a file that is still being written would be reported truncated
+            if !settle_delay.is_zero() {
+                let now = Instant::now();
+                let mut settling = HashMap::new();
+                added.retain(|(path, identity, _)| {
+                    // files last written long enough ago, such as all of them at startup, need
+                    // no second look
+                    if SystemTime::now()
+                        .duration_since(identity.modified)
+                        .is_ok_and(|age| age >= settle_delay)
+                    {
+                        pending_files.remove(path);
+                        return true;
+                    }
+                    match pending_files.remove(path) {
+                        Some((seen, since)) if seen == *identity => {
+                            let settled = now.duration_since(since) >= settle_delay;
+                            if !settled {
+                                settling.insert(path.clone(), (seen, since));
+                            }
+                            settled
+                        }
+                        _ => {
+                            settling.insert(path.clone(), (*identity, now));
+                            false
+                        }
+                    }
+                });
+                // files left in scope vanished before they settled
+                pending_files.retain(|path, _| !in_scope(path));
+                pending_files.extend(settling);
+            }
+
+            let removed: Vec<_> = removed.into_iter().map(|(path, _)| path).collect();
+            for (path, identity, _) in &added {
+                new_known_files.insert(path.clone(), *identity);
+            }
+
+            // modified files have new contents and so are decoded again, moved ones keep theirs
+            let mut to_entries = |files: Vec<(PathBuf, FileIdentity, Option<FileOwner>)>| {
+                let mut entries: Vec<_> = files
+                    .into_iter()
+                    .map(|(path, identity, owner)| {
+                        let metadata = FileMetadata {
+                            title: title(&path),
+                            size: Some(identity.len),
+                            owner,
+                            dimensions: extract_dimensions
+                                .then(|| ImageDimensions::probe(&path_prefix.join(&path))),
+                            blurhash: emit_blurhash
+                                .then(|| {
+                                    blurhashes
+                                        .entry(identity)
+                                        .or_insert_with(|| {
+                                            compute_blurhash(&path_prefix.join(&path))
+                                        })
+                                        .clone()
+                                })
+                                .flatten(),
+                        };
+                        FileEntry::new(path, identity.modified, metadata)
+                    })
+                    .collect();
+                entries.sort_by(|a, b| sort_order.compare(a, b));
+                entries
+            };
+
+            let mut file_change_data = FileChangeData::new(removed, to_entries(added), moved);
+            file_change_data.modified = FileModifyData(to_entries(modified));
+
+            if !blurhashes.is_empty() {
+                let known_identities: HashSet<_> = new_known_files.values().collect();
+                blurhashes.retain(|identity, _| known_identities.contains(identity));
+            }
+
+            (
+                new_known_files,
+                known_collisions,
+                pending_files,
+                blurhashes,
+                file_change_data,
+            )
         })
-        .await?;
+        .await;
+
+        let (known_files, known_collisions, pending_files, blurhashes, file_change_data) =
+            match scan {
+                Ok(scan) => scan,
+                Err(e) => {
+                    tracing::error!("Rescrape task failed, skipping this cycle: {}", e);
+                    return Ok(());
+                }
+            };
+        // kept even if the change is not delivered, so the next scan does not decode again
+        self.blurhashes = blurhashes;
 
         if file_change_data.is_not_empty() {
             tracing::debug!("file change data: {:?}", &file_change_data);
-            FileTrackerActor::send_change(&self.file_tracker_actor_sender, file_change_data)
-                .await?;
+            // keeping the previous state makes the next scan find the same change again
+            if !self.deliver_change(file_change_data).await {
+                tracing::error!("Change not delivered, retrying with the next scan");
+                return Ok(());
+            }
         }
 
-        self.known_files = found;
+        self.known_files = known_files;
+        self.known_collisions = known_collisions;
+        self.pending_files = pending_files;
+
+        // a watched file that stopped changing raises no more events, so settling files are
+        // looked at again once the first of them is due
+        if self.watch.is_some()
+            && let Some(due) = self
+                .pending_files
+                .values()
+                .map(|(_, since)| *since + self.settle_delay)
+                .min()
+        {
+            self.dirty_paths.extend(self.pending_files.keys().cloned());
+            self.scan_dirty_by(due);
+        }
 
         Ok(())
     }
 
     #[instrument(level = "trace")]
-    pub async fn run(mut self, mut receiver: mpsc::Receiver<()>) {
+    pub async fn run(mut self, mut receiver: mpsc::Receiver<FileChangeTrackerActorEvent>) {
+        // the first tick is due at once; scanning before the first heartbeat has the health check
+        // report a long initial scan as starting up rather than wedged
+        self.rescrape_timer.tick().await;
+        self.rescrape().await.expect("Expected rescrape to succeed");
+
         loop {
             tokio::select! {
                 msg = receiver.recv() => match msg {
-                    Some(_) => {},
+                    Some(FileChangeTrackerActorEvent::SetFileExtensions { file_extensions, response_sender }) => {
+                        tracing::info!("file extensions changed to {:?}", &file_extensions);
+                        self.file_extensions = file_extensions.into_iter().collect();
+                        // watched files are otherwise only looked at again once they change
+                        if self.watch.is_some() {
+                            self.mark_dirty(PathBuf::new());
+                        }
+                        let _ = response_sender.send(()).inspect_err(|e| {
+                            tracing::error!("Error responding to FileChangeTrackerActorEvent::SetFileExtensions: {:?}", e)
+                        });
+                    },
                     None => break,
                 },
                 _ = self.rescrape_timer.tick() => {
                     self.rescrape().await.expect("Expected rescrape to succeed");
                 }
+                event = receive_watch_event(&mut self.watch) => match event {
+                    Some(event) => self.handle_watch_event(event),
+                    None => {
+                        tracing::error!("Watching for changes stopped, relying on rescrapes");
+                        self.watch = None;
+                    }
+                },
+                _ = self.heartbeat_timer.tick() => self.heartbeat.beat(),
+                _ = sleep_until_if_some(self.dirty_scan_deadline) => {
+                    self.dirty_scan_deadline = None;
+                    let dirty_paths = std::mem::take(&mut self.dirty_paths);
+                    self.scan(Some(dirty_paths)).await.expect("Expected scan to succeed");
+                }
+            }
+        }
+    }
+
+    pub async fn set_file_extensions(
+        sender: &mpsc::Sender<FileChangeTrackerActorEvent>,
+        file_extensions: Vec<String>,
+    ) -> Result<()> {
+        let file_extensions = validate_file_extensions(file_extensions)?;
+        let (response_sender, response_receiver) = oneshot::channel();
+        sender
+            .send(FileChangeTrackerActorEvent::SetFileExtensions {
+                file_extensions,
+                response_sender,
+            })
+            .await?;
+        tokio_util::receive_response(response_receiver).await
+    }
+}
+
+#[cfg(test)]
+impl FileChangeTrackerActor {
+    /// A tracker polling `serve_dir` for jpg files, which only scans when a test calls
+    /// `rescrape_now`.
+    pub(crate) fn for_test(
+        file_tracker_actor_sender: mpsc::Sender<FileTrackerActorEvent>,
+        serve_dir: PathBuf,
+    ) -> Self {
+        Self::for_test_with_watch_mode(file_tracker_actor_sender, serve_dir, WatchMode::Poll)
+    }
+
+    pub(crate) fn for_test_with_watch_mode(
+        file_tracker_actor_sender: mpsc::Sender<FileTrackerActorEvent>,
+        serve_dir: PathBuf,
+        watch_mode: WatchMode,
+    ) -> Self {
+        let mut tracker = Self::new(
+            file_tracker_actor_sender,
+            Duration::from_secs(3600),
+            FileSource::Directory(serve_dir),
+            vec!["jpg".to_string()],
+            false,
+            None,
+            RegexSet::empty(),
+            0,
+            false,
+            false,
+            false,
+            false,
+            SortOrder::default(),
+            Duration::ZERO,
+            watch_mode,
+        )
+        .expect("Expected a tracker to be created");
+        tracker.rescrape_timer.reset();
+        tracker
+    }
+
+    pub(crate) async fn rescrape_now(&mut self) -> Result<()> {
+        self.rescrape().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::ffi::OsStrExt;
+
+    fn write_file(root: &Path, path: &str) {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, b"image").unwrap();
+    }
+
+    /// Rescrapes and returns the paths the tracker reported as added, in order.
+    async fn rescrape_added(
+        tracker: &mut FileChangeTrackerActor,
+        receiver: &mut mpsc::Receiver<FileTrackerActorEvent>,
+    ) -> Vec<PathBuf> {
+        tracker.rescrape_now().await.unwrap();
+        let mut added = Vec::new();
+        while let Ok(FileTrackerActorEvent::Change(change)) = receiver.try_recv() {
+            added.extend(change.added.0.into_iter().map(|entry| entry.path));
+        }
+        added
+    }
+
+    #[test]
+    fn served_path_collisions_keep_one_file_and_warn_once() {
+        let (logs, _guard) = crate::test_util::capture_logs();
+        // names that are not valid UTF-8 are served lossily, so both become "a/\u{fffd}.jpg"
+        let colliding = [b"a/\xfe.jpg", b"a/\xff.jpg"]
+            .map(|name| PathBuf::from(std::ffi::OsStr::from_bytes(name)));
+        let found = |paths: &[PathBuf]| -> HashSet<PathBuf> {
+            paths
+                .iter()
+                .cloned()
+                .chain([PathBuf::from("c.jpg")])
+                .collect()
+        };
+        let mut known_collisions = HashSet::new();
+
+        let first = resolve_served_path_collisions(found(&colliding), &mut known_collisions);
+        let mut reversed = colliding.clone();
+        reversed.reverse();
+        let second = resolve_served_path_collisions(found(&reversed), &mut known_collisions);
+        assert_eq!(first, second);
+        assert_eq!(first.len(), 2);
+        assert!(first.contains(Path::new("c.jpg")));
+        let warnings = || {
+            logs.lines()
+                .into_iter()
+                .filter(|line| line.contains("collide on served path"))
+                .count()
+        };
+        assert_eq!(warnings(), 1);
+
+        // once resolved, a collision coming back is reported again
+        resolve_served_path_collisions(found(&colliding[..1]), &mut known_collisions);
+        assert!(known_collisions.is_empty());
+        resolve_served_path_collisions(found(&colliding), &mut known_collisions);
+        assert_eq!(warnings(), 2);
+    }
+
+    #[test]
+    fn titles_come_from_the_title_group_or_the_first_group() {
+        let named = Regex::new(r"^(\d+)-(?<title>.+)\.jpg$").unwrap();
+        let unnamed = Regex::new(r"^(.+?)_\d+\.jpg$").unwrap();
+        assert_eq!(
+            extract_title(&named, Path::new("sub/01-Sunset.jpg")),
+            Some("Sunset".to_string())
+        );
+        assert_eq!(
+            extract_title(&unnamed, Path::new("Sunset_01.jpg")),
+            Some("Sunset".to_string())
+        );
+        assert_eq!(extract_title(&unnamed, Path::new("Sunset.jpg")), None);
+    }
+
+    #[tokio::test]
+    async fn renames_changing_the_title_are_not_moves() {
+        let root = tempfile::tempdir().unwrap();
+        let (sender, mut receiver) = mpsc::channel(8);
+        let mut tracker = FileChangeTrackerActor::for_test(sender, root.path().to_path_buf());
+        tracker.detect_moves = true;
+        tracker.title_regex = Some(Regex::new(r"^(.+?)_\d+\.jpg$").unwrap());
+        let mut rescrape = async || {
+            tracker.rescrape_now().await.unwrap();
+            let Ok(FileTrackerActorEvent::Change(change)) = receiver.try_recv() else {
+                panic!("expected a change");
+            };
+            change
+        };
+
+        write_file(root.path(), "Sunset_1.jpg");
+        let change = rescrape().await;
+        assert_eq!(change.added.0[0].metadata.title, Some("Sunset".to_string()));
+
+        std::fs::rename(
+            root.path().join("Sunset_1.jpg"),
+            root.path().join("Sunset_2.jpg"),
+        )
+        .unwrap();
+        let change = rescrape().await;
+        assert_eq!(
+            change.moved.0,
+            vec![(PathBuf::from("Sunset_1.jpg"), PathBuf::from("Sunset_2.jpg"))]
+        );
+
+        std::fs::rename(
+            root.path().join("Sunset_2.jpg"),
+            root.path().join("Dawn_2.jpg"),
+        )
+        .unwrap();
+        let change = rescrape().await;
+        assert!(change.moved.0.is_empty());
+        assert_eq!(change.removed.0, vec![PathBuf::from("Sunset_2.jpg")]);
+        assert_eq!(change.added.0[0].metadata.title, Some("Dawn".to_string()));
+    }
+
+    #[tokio::test]
+    async fn rescrape_reports_new_files_once() {
+        let root = tempfile::tempdir().unwrap();
+        let (sender, mut receiver) = mpsc::channel(8);
+        let mut tracker = FileChangeTrackerActor::for_test(sender, root.path().to_path_buf());
+
+        write_file(root.path(), "a.jpg");
+        write_file(root.path(), "notes.txt");
+        assert_eq!(
+            rescrape_added(&mut tracker, &mut receiver).await,
+            vec![PathBuf::from("a.jpg")]
+        );
+
+        write_file(root.path(), "sub/b.jpg");
+        assert_eq!(
+            rescrape_added(&mut tracker, &mut receiver).await,
+            vec![PathBuf::from("sub/b.jpg")]
+        );
+        assert!(rescrape_added(&mut tracker, &mut receiver).await.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn undelivered_changes_are_sent_again_with_the_next_rescrape() {
+        let root = tempfile::tempdir().unwrap();
+        let (sender, mut receiver) = mpsc::channel(1);
+        let mut tracker = FileChangeTrackerActor::for_test(sender.clone(), root.path().into());
+        sender
+            .send(FileTrackerActorEvent::Change(FileChangeData::new(
+                vec![],
+                vec![],
+                vec![],
+            )))
+            .await
+            .unwrap();
+
+        write_file(root.path(), "a.jpg");
+        let started = Instant::now();
+        tracker.rescrape_now().await.unwrap();
+        // three attempts of 5s with 250ms and 500ms of backoff in between
+        assert_eq!(started.elapsed(), Duration::from_millis(15_750));
+        assert!(receiver.try_recv().is_ok());
+        assert!(receiver.try_recv().is_err());
+
+        assert_eq!(
+            rescrape_added(&mut tracker, &mut receiver).await,
+            vec![PathBuf::from("a.jpg")]
+        );
+        assert!(rescrape_added(&mut tracker, &mut receiver).await.is_empty());
+
+        drop(receiver);
+        write_file(root.path(), "b.jpg");
+        let started = Instant::now();
+        tracker.rescrape_now().await.unwrap();
+        assert_eq!(started.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn file_owners_are_emitted_only_when_enabled() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        let root = tempfile::tempdir().unwrap();
+        let (sender, mut receiver) = mpsc::channel(8);
+        let mut tracker = FileChangeTrackerActor::for_test(sender, root.path().to_path_buf());
+        let mut rescrape = async |emit_file_owner| {
+            tracker.emit_file_owner = emit_file_owner;
+            tracker.rescrape_now().await.unwrap();
+            let Ok(FileTrackerActorEvent::Change(change)) = receiver.try_recv() else {
+                panic!("expected a change");
+            };
+            serde_json::to_value(&change.added.0[0]).unwrap()
+        };
+
+        write_file(root.path(), "a.jpg");
+        assert_eq!(rescrape(false).await[2], serde_json::json!({"size": 5}));
+
+        write_file(root.path(), "b.jpg");
+        let path = root.path().join("b.jpg");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        let metadata = path.metadata().unwrap();
+        assert_eq!(
+            rescrape(true).await[2],
+            serde_json::json!({
+                "size": 5,
+                "uid": metadata.uid(),
+                "gid": metadata.gid(),
+                "mode": 0o640
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn watched_changes_are_reported_without_a_rescrape() {
+        let root = tempfile::tempdir().unwrap();
+        write_file(root.path(), "a.jpg");
+        let (sender, mut receiver) = mpsc::channel(8);
+        let mut tracker = FileChangeTrackerActor::for_test_with_watch_mode(
+            sender,
+            root.path().to_path_buf(),
+            WatchMode::Notify {
+                reconcile_every: 60,
+            },
+        );
+        // run scans at once, when the timer first ticks
+        tracker.rescrape_timer = tokio::time::interval(Duration::from_secs(3600));
+        let (_event_sender, event_receiver) = mpsc::channel(1);
+        let join_handle = tokio::spawn(tracker.run(event_receiver));
+        let mut next_change =
+            async || match tokio::time::timeout(Duration::from_secs(5), receiver.recv()).await {
+                Ok(Some(FileTrackerActorEvent::Change(change))) => change,
+                other => panic!("expected a change, got {:?}", other),
+            };
+        let added = |change: &FileChangeData| -> Vec<PathBuf> {
+            change
+                .added
+                .0
+                .iter()
+                .map(|entry| entry.path.clone())
+                .collect()
+        };
+
+        // later scans only follow events, the next rescrape is an hour away
+        assert_eq!(added(&next_change().await), vec![PathBuf::from("a.jpg")]);
+
+        write_file(root.path(), ".hidden.jpg");
+        write_file(root.path(), "sub/b.jpg");
+        assert_eq!(
+            added(&next_change().await),
+            vec![PathBuf::from("sub/b.jpg")]
+        );
+
+        std::fs::remove_file(root.path().join("a.jpg")).unwrap();
+        assert_eq!(next_change().await.removed.0, vec![PathBuf::from("a.jpg")]);
+
+        join_handle.abort();
+    }
+
+    #[tokio::test]
+    async fn files_rewritten_in_place_are_reported_as_modified() {
+        let root = tempfile::tempdir().unwrap();
+        let (sender, mut receiver) = mpsc::channel(8);
+        let mut tracker = FileChangeTrackerActor::for_test(sender, root.path().to_path_buf());
+        let path = root.path().join("a.jpg");
+        let set_mtime = |secs| {
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+                .unwrap();
+        };
+        let mut rescrape = async || {
+            tracker.rescrape_now().await.unwrap();
+            match receiver.try_recv() {
+                Ok(FileTrackerActorEvent::Change(change)) => Some(change),
+                _ => None,
             }
+        };
+
+        write_file(root.path(), "a.jpg");
+        set_mtime(2_000_000_000);
+        assert!(rescrape().await.is_some());
+        assert!(rescrape().await.is_none());
+
+        // restored timestamps are a new version too, not only later ones
+        for secs in [2_000_000_100, 1_000_000_000] {
+            set_mtime(secs);
+            let change = rescrape().await.expect("expected a change");
+            assert!(change.added.0.is_empty());
+            assert!(change.removed.0.is_empty());
+            assert_eq!(change.modified.0.len(), 1);
+            assert_eq!(change.modified.0[0].path, PathBuf::from("a.jpg"));
+            assert_eq!(
+                change.modified.0[0].mtime,
+                SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+            );
+        }
+        assert!(rescrape().await.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn new_files_are_held_back_until_they_stop_changing() {
+        let root = tempfile::tempdir().unwrap();
+        let (sender, mut receiver) = mpsc::channel(8);
+        let mut tracker = FileChangeTrackerActor::for_test(sender, root.path().to_path_buf());
+        tracker.settle_delay = Duration::from_secs(2);
+        let append = |path: &str| {
+            use std::io::Write;
+            let mut file = std::fs::File::options()
+                .append(true)
+                .open(root.path().join(path))
+                .unwrap();
+            file.write_all(b" more").unwrap();
+        };
+
+        write_file(root.path(), "old.jpg");
+        std::fs::File::options()
+            .write(true)
+            .open(root.path().join("old.jpg"))
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(60))
+            .unwrap();
+        write_file(root.path(), "growing.jpg");
+        write_file(root.path(), "vanishing.jpg");
+        // files written long ago count as settled
+        assert_eq!(
+            rescrape_added(&mut tracker, &mut receiver).await,
+            vec![PathBuf::from("old.jpg")]
+        );
+
+        tokio::time::advance(Duration::from_millis(1500)).await;
+        append("growing.jpg");
+        std::fs::remove_file(root.path().join("vanishing.jpg")).unwrap();
+        assert!(rescrape_added(&mut tracker, &mut receiver).await.is_empty());
+
+        // two seconds after the first sighting, but not after the last change
+        tokio::time::advance(Duration::from_millis(1000)).await;
+        assert!(rescrape_added(&mut tracker, &mut receiver).await.is_empty());
+
+        tokio::time::advance(Duration::from_millis(1000)).await;
+        assert_eq!(
+            rescrape_added(&mut tracker, &mut receiver).await,
+            vec![PathBuf::from("growing.jpg")]
+        );
+        assert!(tracker.pending_files.is_empty());
+    }
+
+    #[tokio::test]
+    async fn empty_and_unreadable_files_are_retried_on_the_next_rescrape() {
+        let root = tempfile::tempdir().unwrap();
+        let (sender, mut receiver) = mpsc::channel(8);
+        let mut tracker = FileChangeTrackerActor::for_test(sender, root.path().to_path_buf());
+
+        std::fs::write(root.path().join("a.jpg"), b"").unwrap();
+        std::os::unix::fs::symlink("missing", root.path().join("b.jpg")).unwrap();
+        assert!(rescrape_added(&mut tracker, &mut receiver).await.is_empty());
+        assert!(rescrape_added(&mut tracker, &mut receiver).await.is_empty());
+
+        write_file(root.path(), "a.jpg");
+        write_file(root.path(), "missing");
+        let mut added = rescrape_added(&mut tracker, &mut receiver).await;
+        added.sort();
+        assert_eq!(added, vec![PathBuf::from("a.jpg"), PathBuf::from("b.jpg")]);
+    }
+
+    #[tokio::test]
+    async fn hidden_temporary_and_ignored_files_are_skipped() {
+        let root = tempfile::tempdir().unwrap();
+        for path in [
+            "a.jpg",
+            ".hidden.jpg",
+            "b.jpg.tmp",
+            "c_draft.jpg",
+            "x.part.jpg",
+            // only skipped as their hidden directory is not descended into
+            ".thumbs/d.jpg",
+            "sub/.cache/deeper/g.jpg",
+            "cache/e.jpg",
+            "sub/f.jpg",
+        ] {
+            write_file(root.path(), path);
+        }
+
+        // without ignore patterns, only hidden and temporary files are skipped
+        let (sender, mut receiver) = mpsc::channel(8);
+        let mut tracker = FileChangeTrackerActor::for_test(sender, root.path().to_path_buf());
+        let mut added = rescrape_added(&mut tracker, &mut receiver).await;
+        added.sort();
+        assert_eq!(
+            added,
+            [
+                "a.jpg",
+                "c_draft.jpg",
+                "cache/e.jpg",
+                "sub/f.jpg",
+                "x.part.jpg"
+            ]
+            .into_iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+        );
+
+        let (sender, mut receiver) = mpsc::channel(8);
+        let mut tracker = FileChangeTrackerActor::for_test(sender, root.path().to_path_buf());
+        tracker.ignore_patterns = RegexSet::new([
+            crate::authentication::public_paths::glob_to_regex("*_draft.jpg"),
+            crate::authentication::public_paths::glob_to_regex("*.part.jpg"),
+            crate::authentication::public_paths::glob_to_regex("cache"),
+        ])
+        .unwrap();
+        let mut added = rescrape_added(&mut tracker, &mut receiver).await;
+        added.sort();
+        assert_eq!(
+            added,
+            vec![PathBuf::from("a.jpg"), PathBuf::from("sub/f.jpg")]
+        );
+    }
+
+    #[tokio::test]
+    async fn scanning_stops_at_the_maximum_depth() {
+        let root = tempfile::tempdir().unwrap();
+        for path in ["a.jpg", "one/b.jpg", "one/two/c.jpg", "one/two/three/d.jpg"] {
+            write_file(root.path(), path);
         }
+
+        for (max_depth, expected) in [
+            (1, vec!["a.jpg"]),
+            (2, vec!["a.jpg", "one/b.jpg"]),
+            (
+                0,
+                vec!["a.jpg", "one/b.jpg", "one/two/c.jpg", "one/two/three/d.jpg"],
+            ),
+        ] {
+            let (sender, mut receiver) = mpsc::channel(8);
+            let mut tracker = FileChangeTrackerActor::for_test(sender, root.path().to_path_buf());
+            tracker.max_depth = max_depth;
+            let mut added = rescrape_added(&mut tracker, &mut receiver).await;
+            added.sort();
+            assert_eq!(
+                added,
+                expected.into_iter().map(PathBuf::from).collect::<Vec<_>>(),
+                "MAX_DEPTH={}",
+                max_depth
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn dimensions_are_read_from_added_and_modified_files() {
+        let root = tempfile::tempdir().unwrap();
+        let (sender, mut receiver) = mpsc::channel(8);
+        let mut tracker = FileChangeTrackerActor::for_test(sender, root.path().to_path_buf());
+        let save = |name: &str, width, height, secs| {
+            let path = root.path().join(name);
+            image::RgbImage::new(width, height)
+                .save_with_format(&path, image::ImageFormat::Png)
+                .unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+                .unwrap();
+        };
+        let mut rescrape = async |extract_dimensions| {
+            tracker.extract_dimensions = extract_dimensions;
+            tracker.rescrape_now().await.unwrap();
+            let Ok(FileTrackerActorEvent::Change(change)) = receiver.try_recv() else {
+                panic!("expected a change");
+            };
+            change
+                .added
+                .0
+                .iter()
+                .chain(&change.modified.0)
+                .map(|entry| {
+                    let metadata = serde_json::to_value(&entry.metadata).unwrap();
+                    (entry.path.clone(), metadata.get("dimensions").cloned())
+                })
+                .collect::<HashMap<_, _>>()
+        };
+
+        save("off.jpg", 1, 1, 1);
+        assert_eq!(rescrape(false).await[Path::new("off.jpg")], None);
+
+        save("a.jpg", 8, 6, 1);
+        write_file(root.path(), "broken.jpg");
+        let dimensions = rescrape(true).await;
+        assert_eq!(
+            dimensions[Path::new("a.jpg")],
+            Some(serde_json::json!({"width": 8, "height": 6}))
+        );
+        assert_eq!(
+            dimensions[Path::new("broken.jpg")],
+            Some(serde_json::Value::Null)
+        );
+
+        save("a.jpg", 4, 4, 2);
+        assert_eq!(
+            rescrape(true).await,
+            HashMap::from([(
+                PathBuf::from("a.jpg"),
+                Some(serde_json::json!({"width": 4, "height": 4}))
+            )])
+        );
+    }
+
+    #[tokio::test]
+    async fn blurhashes_are_computed_once_per_known_file() {
+        let root = tempfile::tempdir().unwrap();
+        let (sender, mut receiver) = mpsc::channel(8);
+        let mut tracker = FileChangeTrackerActor::for_test(sender, root.path().to_path_buf());
+        tracker.emit_blurhash = true;
+
+        image::RgbImage::from_pixel(8, 6, image::Rgb([200, 40, 40]))
+            .save_with_format(root.path().join("a.jpg"), image::ImageFormat::Png)
+            .unwrap();
+        write_file(root.path(), "broken.jpg");
+        tracker.rescrape_now().await.unwrap();
+        let Ok(FileTrackerActorEvent::Change(change)) = receiver.try_recv() else {
+            panic!("Expected the new files to be reported");
+        };
+        let blurhash = |name: &str| {
+            change
+                .added
+                .0
+                .iter()
+                .find(|entry| entry.path == Path::new(name))
+                .map(|entry| entry.metadata.blurhash.clone())
+                .unwrap()
+        };
+        assert!(blurhash("a.jpg").is_some_and(|blurhash| !blurhash.is_empty()));
+        assert_eq!(blurhash("broken.jpg"), None);
+        // failures are remembered as well, so neither file is decoded again
+        assert_eq!(tracker.blurhashes.len(), 2);
+
+        std::fs::remove_file(root.path().join("a.jpg")).unwrap();
+        tracker.rescrape_now().await.unwrap();
+        assert_eq!(tracker.blurhashes.len(), 1);
     }
 }
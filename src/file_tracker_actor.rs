@@ -1,107 +1,310 @@
 use crate::authentication::{
     Token, authentication_token_store_actor::AuthenticationTokenStoreActorEvent,
 };
-use crate::web_socket_actor::WebSocketActorEvent;
+use crate::web_socket_actor::{
+    CHANGE_QUEUE_CAPACITY, ChangeBuffer, WebSocketActorEvent, WebSocketConnectionInfo,
+    WebSocketFilter, WebSocketHandshake, WebSocketSettings,
+};
 use crate::{
     error::Result,
-    file_change_data::{FileAddData, FileChangeData, FileRemoveData},
+    file_change_data::{
+        FileAddData, FileChangeData, FileModifyData, FileMoveData, FileRemoveData, SequencedChange,
+        SnapshotPage, SortOrder,
+    },
+    health::Heartbeat,
+    metrics, tokio_util,
     web_socket_actor::WebSocketActor,
 };
 use axum::extract::ws::WebSocket;
-use std::mem::take;
-use tokio::{sync::mpsc, task::spawn_blocking};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+use tokio::{
+    sync::{broadcast, mpsc, oneshot},
+    task::spawn_blocking,
+    time::Interval,
+};
 use tracing::instrument;
 
+const CHANGE_BROADCAST_CAPACITY: usize = 64;
+/// How long web socket actors may take to close on shutdown before they are aborted.
+const WEB_SOCKET_SHUTDOWN_GRACE_PERIOD: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// What web socket clients receive when files change.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum PushMode {
+    /// Only the difference to the previous state.
+    #[default]
+    Incremental,
+    /// The complete current set, for clients that keep no state of their own.
+    Full,
+}
+
 #[derive(Debug)]
 struct WebSocketActorSenderAndJoinHandle {
     sender: mpsc::Sender<WebSocketActorEvent>,
+    change_buffer: Arc<ChangeBuffer>,
     join_handle: tokio::task::JoinHandle<()>,
+    token: Token,
 }
 
 impl WebSocketActorSenderAndJoinHandle {
     fn extract_join_handle(self) -> tokio::task::JoinHandle<()> {
         self.join_handle
     }
+
+    /// A failed send means the actor has dropped its receiver and is finishing on its own, so
+    /// its task is detached rather than joined to keep the tracker from ever waiting on it.
+    fn detach(self) {
+        drop(self.join_handle);
+    }
+}
+
+/// Identifies a state of the baseline. The instance id keeps revisions of different runs apart,
+/// as the sequence number starts over on every start.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Revision {
+    pub instance: uuid::Uuid,
+    pub seq: u64,
+}
+
+impl std::fmt::Display for Revision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.instance.simple(), self.seq)
+    }
 }
 
 #[derive(Debug)]
 pub enum FileTrackerActorEvent {
     Change(FileChangeData),
-    AddWebSocket(WebSocket, Token),
+    Emit(FileChangeData),
+    AddWebSocket {
+        // boxed, as the socket alone would make every event several hundred bytes
+        ws: Box<WebSocket>,
+        token: Token,
+        connection_info: WebSocketConnectionInfo,
+        handshake: WebSocketHandshake,
+        file_tracker_actor_sender: mpsc::WeakSender<FileTrackerActorEvent>,
+    },
+    RequestBaseline {
+        response_sender: oneshot::Sender<FileAddData>,
+    },
+    /// Up to `limit` of the files matching `filter`, skipping the first `offset` of them.
+    GetBaselineSlice {
+        offset: usize,
+        limit: usize,
+        filter: WebSocketFilter,
+        response_sender: oneshot::Sender<FileChangeData>,
+    },
+    RequestRevision {
+        response_sender: oneshot::Sender<Revision>,
+    },
+    RevokeWebSockets {
+        token: Token,
+    },
 }
 
 #[derive(Debug)]
 pub struct FileTrackerActor {
     baseline: FileAddData,
+    baseline_fingerprint: String,
     web_socket_actor_senders_and_join_handles: Vec<WebSocketActorSenderAndJoinHandle>,
+    web_socket_count: Arc<AtomicUsize>,
+    tracked_file_count: Arc<AtomicUsize>,
+    broadcast_count: Arc<AtomicUsize>,
     authentication_token_store_actor_sender: mpsc::Sender<AuthenticationTokenStoreActorEvent>,
-    token_refresh_interval: std::time::Duration,
+    web_socket_settings: WebSocketSettings,
+    push_mode: PushMode,
+    sort_order: SortOrder,
+    change_publisher: broadcast::Sender<SequencedChange>,
+    change_seq: u64,
+    instance: uuid::Uuid,
+    heartbeat: Heartbeat,
+    heartbeat_timer: Interval,
 }
 
 impl FileTrackerActor {
     pub fn new(
         authentication_token_store_actor_sender: mpsc::Sender<AuthenticationTokenStoreActorEvent>,
-        token_refresh_interval: std::time::Duration,
+        web_socket_settings: WebSocketSettings,
+        push_mode: PushMode,
+        sort_order: SortOrder,
     ) -> Self {
         let baseline = FileAddData::new();
+        let baseline_fingerprint = baseline.fingerprint();
         let web_socket_actor_senders_and_join_handles = Vec::new();
 
         Self {
             baseline,
+            baseline_fingerprint,
             web_socket_actor_senders_and_join_handles,
+            web_socket_count: Arc::new(AtomicUsize::new(0)),
+            tracked_file_count: Arc::new(AtomicUsize::new(0)),
+            broadcast_count: Arc::new(AtomicUsize::new(0)),
             authentication_token_store_actor_sender,
-            token_refresh_interval,
+            web_socket_settings,
+            push_mode,
+            sort_order,
+            change_publisher: broadcast::Sender::new(CHANGE_BROADCAST_CAPACITY),
+            change_seq: 0,
+            instance: uuid::Uuid::new_v4(),
+            heartbeat: Heartbeat::default(),
+            heartbeat_timer: Heartbeat::timer(),
         }
     }
 
-    #[instrument(level = "trace")]
-    async fn handle_change(&mut self, change: FileChangeData) {
-        tracing::info!("known files changed: {:?}", &change);
+    /// Subscribes to every change applied to the baseline, independent of the web socket fan-out.
+    pub fn subscribe_changes(&self) -> broadcast::Receiver<SequencedChange> {
+        self.change_publisher.subscribe()
+    }
 
-        {
-            let mut survivors = Vec::new();
+    /// Shared counter of live web socket actors, readable without querying the actor.
+    pub fn web_socket_count(&self) -> Arc<AtomicUsize> {
+        self.web_socket_count.clone()
+    }
 
-            for sender_and_join_handle in self.web_socket_actor_senders_and_join_handles.drain(..) {
-                let result =
-                    WebSocketActor::send_change(&sender_and_join_handle.sender, change.clone())
-                        .await;
-                match result {
-                    Ok(_) => {
-                        survivors.push(sender_and_join_handle);
-                    }
-                    Err(_) => {
-                        sender_and_join_handle
-                            .extract_join_handle()
-                            .await
-                            .expect("Expected handle to be joinable");
-                    }
+    /// Shared size of the baseline, readable without querying the actor.
+    pub fn tracked_file_count(&self) -> Arc<AtomicUsize> {
+        self.tracked_file_count.clone()
+    }
+
+    /// Shared running total of changes broadcast to the web sockets.
+    pub fn broadcast_count(&self) -> Arc<AtomicUsize> {
+        self.broadcast_count.clone()
+    }
+
+    /// Shared heartbeat, beaten from the run loop for the health check.
+    pub fn heartbeat(&self) -> Heartbeat {
+        self.heartbeat.clone()
+    }
+
+    fn update_web_socket_count(&self) {
+        let count = self.web_socket_actor_senders_and_join_handles.len();
+        self.web_socket_count.store(count, Ordering::Relaxed);
+        metrics::set_web_sockets(count);
+    }
+
+    async fn broadcast_change(&mut self, change: &FileChangeData) {
+        self.broadcast_count.fetch_add(1, Ordering::Relaxed);
+        let mut survivors = Vec::new();
+
+        for sender_and_join_handle in self.web_socket_actor_senders_and_join_handles.drain(..) {
+            let result = WebSocketActor::send_change(
+                &sender_and_join_handle.sender,
+                &sender_and_join_handle.change_buffer,
+                change.clone(),
+            )
+            .await;
+            match result {
+                Ok(_) => {
+                    survivors.push(sender_and_join_handle);
+                }
+                Err(_) => {
+                    sender_and_join_handle.detach();
                 }
             }
+        }
+
+        self.web_socket_actor_senders_and_join_handles = survivors;
+        self.update_web_socket_count();
+    }
 
-            self.web_socket_actor_senders_and_join_handles = survivors;
+    /// Drops removals of paths the baseline does not hold, as an applied debug emit may carry.
+    fn retain_tracked_removals(&self, change: &mut FileChangeData) {
+        if change.removed.0.is_empty() {
+            return;
         }
+        let tracked: HashSet<_> = self.baseline.0.iter().map(|entry| &entry.path).collect();
+        change.removed.0.retain(|path| tracked.contains(path));
+    }
+
+    #[instrument(level = "trace")]
+    async fn handle_change(&mut self, mut change: FileChangeData) {
+        self.retain_tracked_removals(&mut change);
+        tracing::info!("known files changed: {:?}", &change);
+        metrics::record_change(change.added.0.len(), change.removed.0.len());
 
-        let baseline = take(&mut self.baseline);
+        // merge a copy so the current baseline survives a failed blocking task
+        let baseline = self.baseline.clone();
+        let change = Arc::new(change);
+        let merged_change = change.clone();
+        let sort_order = self.sort_order;
 
         let new_baseline = spawn_blocking(move || {
-            let FileChangeData { removed, added } = &change;
+            let FileChangeData {
+                removed,
+                added,
+                moved,
+                modified,
+                ..
+            } = &*merged_change;
 
-            let mut new_baseline =
-                Vec::with_capacity(baseline.0.len() + added.0.len() - removed.0.len());
+            // moved files keep their timestamp, so renaming them in place preserves an order by
+            // timestamp, and one by name is restored afterwards
+            let baseline = if moved.is_empty() {
+                baseline
+            } else {
+                let moved: HashMap<_, _> = moved.0.iter().cloned().collect();
+                let mut baseline: Vec<_> = baseline
+                    .0
+                    .into_iter()
+                    .map(|mut entry| {
+                        if let Some(to) = moved.get(&entry.path) {
+                            entry.path = to.clone();
+                        }
+                        entry
+                    })
+                    .collect();
+                if sort_order.by_name() {
+                    baseline.sort_by(|a, b| sort_order.compare(a, b));
+                }
+                FileAddData(baseline)
+            };
+
+            // a rewritten file is taken out and merged back in at its new place
+            let (baseline, added) = if modified.is_empty() {
+                (baseline, Cow::Borrowed(&added.0))
+            } else {
+                let modified_paths: HashSet<_> =
+                    modified.0.iter().map(|entry| &entry.path).collect();
+                let baseline = FileAddData(
+                    baseline
+                        .0
+                        .into_iter()
+                        .filter(|entry| !modified_paths.contains(&entry.path))
+                        .collect(),
+                );
+                let mut added = added.0.clone();
+                added.extend(modified.0.iter().cloned());
+                added.sort_by(|a, b| sort_order.compare(a, b));
+                (baseline, Cow::Owned(added))
+            };
+
+            let mut new_baseline = Vec::with_capacity(
+                (baseline.0.len() + added.len()).saturating_sub(removed.0.len()),
+            );
 
             let (mut idx_baseline, mut index_added) = (0usize, 0usize);
-            let (baseline_len, added_len) = (baseline.0.len(), added.0.len());
+            let (baseline_len, added_len) = (baseline.0.len(), added.len());
             while idx_baseline < baseline_len || index_added < added_len {
-                if idx_baseline < baseline_len && removed.0.contains(&baseline.0[idx_baseline].0) {
+                if idx_baseline < baseline_len && removed.0.contains(&baseline.0[idx_baseline].path)
+                {
                     idx_baseline += 1;
                 } else if index_added < added_len {
                     if idx_baseline < baseline_len
-                        && baseline.0[idx_baseline].1 > added.0[index_added].1
+                        && sort_order
+                            .compare(&baseline.0[idx_baseline], &added[index_added])
+                            .is_lt()
                     {
                         new_baseline.push(baseline.0[idx_baseline].clone());
                         idx_baseline += 1;
                     } else {
-                        new_baseline.push(added.0[index_added].clone());
+                        new_baseline.push(added[index_added].clone());
                         index_added += 1;
                     }
                 } else {
@@ -109,70 +312,279 @@ impl FileTrackerActor {
                     idx_baseline += 1;
                 }
             }
-            new_baseline
+            let new_baseline = FileAddData(new_baseline);
+            let fingerprint = new_baseline.fingerprint();
+            (new_baseline, fingerprint)
         })
-        .await
-        .expect("Expected task to complete");
+        .await;
+
+        let (new_baseline, fingerprint) = match new_baseline {
+            Ok(new_baseline) => new_baseline,
+            Err(e) => {
+                tracing::error!("Baseline merge task failed, keeping old baseline: {}", e);
+                return;
+            }
+        };
 
         tracing::debug!("new baseline: {:?}", &new_baseline);
 
-        self.baseline = FileAddData(new_baseline);
+        self.baseline = new_baseline;
+        self.baseline_fingerprint = fingerprint;
+        self.tracked_file_count
+            .store(self.baseline.0.len(), Ordering::Relaxed);
+
+        // the merge task is done with its reference, so this does not copy the change
+        let mut change = Arc::unwrap_or_clone(change);
+        // changes go out after the merge so the total they carry is the merged one
+        change.total = Some(self.baseline.0.len());
+        change.fingerprint = Some(self.baseline_fingerprint.clone());
+        match self.push_mode {
+            PushMode::Incremental => self.broadcast_change(&change).await,
+            PushMode::Full => {
+                self.broadcast_change(&FileChangeData::full(self.baseline.clone()))
+                    .await
+            }
+        }
+
+        self.change_seq += 1;
+        // having no subscribers is not an error
+        let _ = self.change_publisher.send(SequencedChange {
+            seq: self.change_seq,
+            change: Arc::new(change),
+        });
     }
 
-    #[instrument(level = "trace")]
-    pub async fn run(mut self, mut receiver: mpsc::Receiver<FileTrackerActorEvent>) {
-        while let Some(msg) = receiver.recv().await {
-            match msg {
-                FileTrackerActorEvent::Change(change) => {
-                    self.handle_change(change).await;
-                }
-                FileTrackerActorEvent::AddWebSocket(ws, token) => {
-                    let (sender, receiver) = mpsc::channel::<_>(8);
-                    let ws_actor = WebSocketActor::new(
-                        ws,
-                        self.authentication_token_store_actor_sender.clone(),
-                        self.token_refresh_interval,
-                        token,
-                    );
-                    let join_handle = tokio::task::spawn(ws_actor.run(receiver));
-                    let sender_and_join_handle = WebSocketActorSenderAndJoinHandle {
-                        sender,
+    fn initial_change(&self, handshake: &WebSocketHandshake) -> FileChangeData {
+        let holds_baseline = handshake.since().is_none() && handshake.filter().is_empty();
+        if holds_baseline && handshake.fingerprint() == Some(self.baseline_fingerprint.as_str()) {
+            // nothing changed while the client was away, an empty change tells it so
+            let mut change = FileChangeData::new(Vec::new(), Vec::new(), Vec::new());
+            change.total = Some(self.baseline.0.len());
+            change.fingerprint = Some(self.baseline_fingerprint.clone());
+            return change;
+        }
+
+        if let PushMode::Full = self.push_mode {
+            return FileChangeData::full(self.baseline.clone());
+        }
+
+        if handshake.paged() {
+            // the client asks for the baseline page by page
+            let mut change = FileChangeData::new(Vec::new(), Vec::new(), Vec::new());
+            change.total = holds_baseline.then_some(self.baseline.0.len());
+            return change;
+        }
+
+        let added = match handshake.since() {
+            Some(since) => FileAddData(
+                self.baseline
+                    .0
+                    .iter()
+                    .filter(|entry| entry.mtime >= since)
+                    .cloned()
+                    .collect(),
+            ),
+            None => self.baseline.clone(),
+        };
+
+        FileChangeData {
+            removed: FileRemoveData(Vec::new()),
+            added,
+            moved: FileMoveData::default(),
+            modified: FileModifyData::default(),
+            full: false,
+            total: Some(self.baseline.0.len()),
+            // a client that only got newer files holds less than the fingerprint covers
+            fingerprint: holds_baseline.then(|| self.baseline_fingerprint.clone()),
+            snapshot: None,
+        }
+    }
+
+    /// A page of the baseline as seen through `filter`. The page at offset zero replaces what
+    /// the client held, and the last one carries the fingerprint if the client gets all files.
+    fn baseline_slice(
+        &self,
+        offset: usize,
+        limit: usize,
+        filter: &WebSocketFilter,
+    ) -> FileChangeData {
+        let matching: Vec<_> = self
+            .baseline
+            .0
+            .iter()
+            .filter(|entry| filter.matches(&entry.path))
+            .collect();
+        let total = matching.len();
+        let added = matching
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect();
+        let more = offset.saturating_add(limit) < total;
+
+        let mut change = FileChangeData::new(Vec::new(), added, Vec::new());
+        change.full = offset == 0;
+        if filter.is_empty() {
+            change.total = Some(total);
+            change.fingerprint = (!more).then(|| self.baseline_fingerprint.clone());
+        }
+        change.snapshot = Some(SnapshotPage {
+            offset,
+            total,
+            more,
+            seq: self.change_seq,
+        });
+        change
+    }
+
+    async fn handle_event(&mut self, msg: FileTrackerActorEvent) {
+        match msg {
+            FileTrackerActorEvent::Change(change) => {
+                self.handle_change(change).await;
+            }
+            FileTrackerActorEvent::Emit(mut change) => {
+                tracing::info!("emitting synthetic change: {:?}", &change);
+                // synthetic changes leave the baseline alone
+                change.total = Some(self.baseline.0.len());
+                // clients applying it no longer hold what the baseline fingerprint covers
+                change.fingerprint = None;
+                self.broadcast_change(&change).await;
+            }
+            FileTrackerActorEvent::AddWebSocket {
+                ws,
+                token,
+                connection_info,
+                handshake,
+                file_tracker_actor_sender,
+            } => {
+                connection_info.audit_accepted();
+                let (sender, receiver) = mpsc::channel::<_>(CHANGE_QUEUE_CAPACITY);
+                let change_buffer = Arc::new(ChangeBuffer::new(self.web_socket_settings.overflow));
+                let ws_actor = WebSocketActor::new(
+                    *ws,
+                    self.authentication_token_store_actor_sender.clone(),
+                    file_tracker_actor_sender,
+                    &self.web_socket_settings,
+                    token.clone(),
+                    connection_info,
+                    handshake.filter(),
+                    handshake.encoding(),
+                    change_buffer.clone(),
+                );
+                let join_handle = tokio::task::spawn(ws_actor.run(receiver));
+                // track the socket before sending the baseline so no change can slip past it
+                self.web_socket_actor_senders_and_join_handles.push(
+                    WebSocketActorSenderAndJoinHandle {
+                        sender: sender.clone(),
+                        change_buffer: change_buffer.clone(),
                         join_handle,
-                    };
-                    let result = WebSocketActor::send_change(
-                        &sender_and_join_handle.sender,
-                        FileChangeData {
-                            removed: FileRemoveData(Vec::new()),
-                            added: self.baseline.clone(),
-                        },
-                    )
-                    .await;
-                    match result {
-                        Ok(_) => {
-                            self.web_socket_actor_senders_and_join_handles
-                                .push(sender_and_join_handle);
-                        }
-                        Err(_) => {
-                            sender_and_join_handle
-                                .extract_join_handle()
-                                .await
-                                .expect("Expected handle to be joinable");
-                        }
-                    }
+                        token,
+                    },
+                );
+                let result = WebSocketActor::send_change(
+                    &sender,
+                    &change_buffer,
+                    self.initial_change(&handshake),
+                )
+                .await;
+                if result.is_err()
+                    && let Some(sender_and_join_handle) =
+                        self.web_socket_actor_senders_and_join_handles.pop()
+                {
+                    sender_and_join_handle.detach();
+                }
+                self.update_web_socket_count();
+            }
+            FileTrackerActorEvent::RequestBaseline { response_sender } => {
+                let _ = response_sender
+                    .send(self.baseline.clone())
+                    .inspect_err(|_| {
+                        tracing::error!(
+                            "Error responding to FileTrackerActorEvent::RequestBaseline"
+                        )
+                    });
+            }
+            FileTrackerActorEvent::GetBaselineSlice {
+                offset,
+                limit,
+                filter,
+                response_sender,
+            } => {
+                let _ = response_sender
+                    .send(self.baseline_slice(offset, limit, &filter))
+                    .inspect_err(|_| {
+                        tracing::error!(
+                            "Error responding to FileTrackerActorEvent::GetBaselineSlice"
+                        )
+                    });
+            }
+            FileTrackerActorEvent::RequestRevision { response_sender } => {
+                let revision = Revision {
+                    instance: self.instance,
+                    seq: self.change_seq,
+                };
+                let _ = response_sender.send(revision).inspect_err(|_| {
+                    tracing::error!("Error responding to FileTrackerActorEvent::RequestRevision")
+                });
+            }
+            FileTrackerActorEvent::RevokeWebSockets { token } => {
+                // the actors close on their own and are dropped with the next change
+                for sender_and_join_handle in self
+                    .web_socket_actor_senders_and_join_handles
+                    .iter()
+                    .filter(|sender_and_join_handle| sender_and_join_handle.token == token)
+                {
+                    let _ = WebSocketActor::revoke(&sender_and_join_handle.sender).await;
                 }
             }
         }
+    }
+
+    #[instrument(level = "trace")]
+    pub async fn run(mut self, mut receiver: mpsc::Receiver<FileTrackerActorEvent>) {
+        loop {
+            tokio::select! {
+                msg = receiver.recv() => match msg {
+                    Some(msg) => self.handle_event(msg).await,
+                    None => break,
+                },
+                _ = self.heartbeat_timer.tick() => self.heartbeat.beat(),
+            }
+        }
 
         self.shutdown_web_socket_actor_handlers().await;
     }
 
-    async fn shutdown_web_socket_actor_handlers(mut self) {
-        for sender_and_join_handle in self.web_socket_actor_senders_and_join_handles.drain(..) {
-            let join_handle = sender_and_join_handle.extract_join_handle();
-            join_handle
-                .await
-                .expect("Expected web socket actor to be joinable");
+    /// Closes the web sockets, aborting those still open after the grace period, and returns how
+    /// many closed on their own and how many were aborted.
+    async fn shutdown_web_socket_actor_handlers(mut self) -> (usize, usize) {
+        // dropping all senders first lets the web socket actors close concurrently
+        let join_handles: Vec<_> = self
+            .web_socket_actor_senders_and_join_handles
+            .drain(..)
+            .map(WebSocketActorSenderAndJoinHandle::extract_join_handle)
+            .collect();
+        self.update_web_socket_count();
+
+        let deadline = tokio::time::Instant::now() + WEB_SOCKET_SHUTDOWN_GRACE_PERIOD;
+        let (mut drained, mut force_closed) = (0usize, 0usize);
+
+        for mut join_handle in join_handles {
+            match tokio::time::timeout_at(deadline, &mut join_handle).await {
+                Ok(result) => {
+                    result.expect("Expected web socket actor to be joinable");
+                    drained += 1;
+                }
+                Err(_) => {
+                    join_handle.abort();
+                    force_closed += 1;
+                }
+            }
         }
+
+        tracing::info!(drained, force_closed, "web socket actors shut down");
+        (drained, force_closed)
     }
 
     pub async fn send_change(
@@ -183,14 +595,523 @@ impl FileTrackerActor {
         Ok(())
     }
 
+    pub async fn emit_change(
+        sender: &mpsc::Sender<FileTrackerActorEvent>,
+        change: FileChangeData,
+    ) -> Result<()> {
+        sender.send(FileTrackerActorEvent::Emit(change)).await?;
+        Ok(())
+    }
+
+    pub async fn request_baseline(
+        sender: &mpsc::Sender<FileTrackerActorEvent>,
+    ) -> Result<FileAddData> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        sender
+            .send(FileTrackerActorEvent::RequestBaseline { response_sender })
+            .await?;
+        tokio_util::receive_response(response_receiver).await
+    }
+
+    pub async fn get_baseline_slice(
+        sender: &mpsc::Sender<FileTrackerActorEvent>,
+        offset: usize,
+        limit: usize,
+        filter: WebSocketFilter,
+    ) -> Result<FileChangeData> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        sender
+            .send(FileTrackerActorEvent::GetBaselineSlice {
+                offset,
+                limit,
+                filter,
+                response_sender,
+            })
+            .await?;
+        tokio_util::receive_response(response_receiver).await
+    }
+
+    pub async fn request_revision(
+        sender: &mpsc::Sender<FileTrackerActorEvent>,
+    ) -> Result<Revision> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        sender
+            .send(FileTrackerActorEvent::RequestRevision { response_sender })
+            .await?;
+        tokio_util::receive_response(response_receiver).await
+    }
+
+    /// Closes all web sockets opened with `token`.
+    pub async fn revoke_web_sockets(
+        sender: &mpsc::Sender<FileTrackerActorEvent>,
+        token: Token,
+    ) -> Result<()> {
+        sender
+            .send(FileTrackerActorEvent::RevokeWebSockets { token })
+            .await?;
+        Ok(())
+    }
+
     pub async fn add_web_socket(
         sender: &mpsc::Sender<FileTrackerActorEvent>,
         ws: WebSocket,
         token: Token,
+        connection_info: WebSocketConnectionInfo,
+        handshake: WebSocketHandshake,
     ) -> Result<()> {
         sender
-            .send(FileTrackerActorEvent::AddWebSocket(ws, token))
+            .send(FileTrackerActorEvent::AddWebSocket {
+                ws: Box::new(ws),
+                token,
+                connection_info,
+                handshake,
+                file_tracker_actor_sender: sender.downgrade(),
+            })
             .await?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+impl FileTrackerActor {
+    /// A tracker with the test web socket settings.
+    pub(crate) fn for_test(
+        authentication_token_store_actor_sender: mpsc::Sender<AuthenticationTokenStoreActorEvent>,
+    ) -> Self {
+        Self::new(
+            authentication_token_store_actor_sender,
+            WebSocketSettings::for_test(),
+            PushMode::default(),
+            SortOrder::default(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_change_data::{FileEntry, FileMetadata};
+    use std::{path::PathBuf, time::UNIX_EPOCH};
+
+    fn entry(path: &str, mtime_secs: u64) -> FileEntry {
+        FileEntry::new(
+            PathBuf::from(path),
+            UNIX_EPOCH + std::time::Duration::from_secs(mtime_secs),
+            FileMetadata::default(),
+        )
+    }
+
+    fn baseline_paths(tracker: &FileTrackerActor) -> Vec<PathBuf> {
+        tracker
+            .baseline
+            .0
+            .iter()
+            .map(|entry| entry.path.clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn changes_update_the_baseline() {
+        let (authentication_token_store_actor_sender, _authentication_token_store_actor_receiver) =
+            mpsc::channel(8);
+        let mut tracker = FileTrackerActor::for_test(authentication_token_store_actor_sender);
+
+        tracker
+            .handle_event(FileTrackerActorEvent::Change(FileChangeData::new(
+                Vec::new(),
+                // changes come sorted, newest first
+                vec![entry("b.jpg", 2), entry("a.jpg", 1)],
+                Vec::new(),
+            )))
+            .await;
+        assert_eq!(
+            baseline_paths(&tracker),
+            vec![PathBuf::from("b.jpg"), PathBuf::from("a.jpg")]
+        );
+
+        tracker
+            .handle_event(FileTrackerActorEvent::Change(FileChangeData::new(
+                Vec::new(),
+                vec![entry("c.jpg", 3)],
+                Vec::new(),
+            )))
+            .await;
+        assert_eq!(
+            baseline_paths(&tracker),
+            vec![
+                PathBuf::from("c.jpg"),
+                PathBuf::from("b.jpg"),
+                PathBuf::from("a.jpg")
+            ]
+        );
+
+        tracker
+            .handle_event(FileTrackerActorEvent::Change(FileChangeData::new(
+                vec![PathBuf::from("b.jpg")],
+                Vec::new(),
+                Vec::new(),
+            )))
+            .await;
+        assert_eq!(
+            baseline_paths(&tracker),
+            vec![PathBuf::from("c.jpg"), PathBuf::from("a.jpg")]
+        );
+        assert_eq!(tracker.change_seq, 3);
+    }
+
+    #[tokio::test]
+    async fn modified_files_move_to_their_new_timestamp() {
+        let (authentication_token_store_actor_sender, _authentication_token_store_actor_receiver) =
+            mpsc::channel(8);
+        let mut tracker = FileTrackerActor::for_test(authentication_token_store_actor_sender);
+        tracker
+            .handle_event(FileTrackerActorEvent::Change(FileChangeData::new(
+                Vec::new(),
+                vec![entry("c.jpg", 3), entry("b.jpg", 2), entry("a.jpg", 1)],
+                Vec::new(),
+            )))
+            .await;
+
+        for (modified, expected) in [
+            (entry("a.jpg", 4), ["a.jpg", "c.jpg", "b.jpg"]),
+            (entry("c.jpg", 0), ["a.jpg", "b.jpg", "c.jpg"]),
+        ] {
+            let mut change = FileChangeData::new(Vec::new(), Vec::new(), Vec::new());
+            change.modified = FileModifyData(vec![modified]);
+            tracker
+                .handle_event(FileTrackerActorEvent::Change(change))
+                .await;
+            assert_eq!(
+                baseline_paths(&tracker),
+                expected.map(PathBuf::from).to_vec()
+            );
+        }
+        assert_eq!(
+            tracker.baseline.0[0].mtime,
+            UNIX_EPOCH + std::time::Duration::from_secs(4)
+        );
+    }
+
+    #[tokio::test]
+    async fn initial_changes_leave_out_files_older_than_since() {
+        let (authentication_token_store_actor_sender, _authentication_token_store_actor_receiver) =
+            mpsc::channel(8);
+        let mut tracker = FileTrackerActor::for_test(authentication_token_store_actor_sender);
+        tracker
+            .handle_event(FileTrackerActorEvent::Change(FileChangeData::new(
+                Vec::new(),
+                vec![entry("c.jpg", 3), entry("b.jpg", 2), entry("a.jpg", 1)],
+                Vec::new(),
+            )))
+            .await;
+        let handshake = |query: &str| {
+            let uri = format!("/backend/ws{}", query).parse().unwrap();
+            axum::extract::Query::<WebSocketHandshake>::try_from_uri(&uri)
+                .unwrap()
+                .0
+        };
+        let added_paths = |change: &FileChangeData| {
+            change
+                .added
+                .0
+                .iter()
+                .map(|entry| entry.path.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let all = tracker.initial_change(&handshake(""));
+        assert_eq!(added_paths(&all), baseline_paths(&tracker));
+        assert_eq!(all.fingerprint, Some(tracker.baseline_fingerprint.clone()));
+
+        // the bound is in milliseconds and inclusive
+        let recent = tracker.initial_change(&handshake("?since=2000"));
+        assert_eq!(
+            added_paths(&recent),
+            vec![PathBuf::from("c.jpg"), PathBuf::from("b.jpg")]
+        );
+        assert_eq!(recent.total, Some(3));
+        assert_eq!(recent.fingerprint, None);
+
+        let none = tracker.initial_change(&handshake("?since=3001"));
+        assert!(none.added.0.is_empty());
+        assert!(none.removed.0.is_empty());
+    }
+
+    #[tokio::test]
+    async fn clients_with_a_current_fingerprint_skip_the_baseline() {
+        let (authentication_token_store_actor_sender, _authentication_token_store_actor_receiver) =
+            mpsc::channel(8);
+        let mut tracker = FileTrackerActor::for_test(authentication_token_store_actor_sender);
+        let change = |removed: Vec<&str>, added| {
+            FileTrackerActorEvent::Change(FileChangeData::new(
+                removed.into_iter().map(PathBuf::from).collect(),
+                added,
+                Vec::new(),
+            ))
+        };
+        let handshake = |query: String| {
+            let uri = format!("/backend/ws?{}", query).parse().unwrap();
+            axum::extract::Query::<WebSocketHandshake>::try_from_uri(&uri)
+                .unwrap()
+                .0
+        };
+        tracker
+            .handle_event(change(vec![], vec![entry("b.jpg", 2), entry("a.jpg", 1)]))
+            .await;
+        let fingerprint = tracker.baseline_fingerprint.clone();
+
+        let current = tracker.initial_change(&handshake(format!("fingerprint={}", fingerprint)));
+        assert!(current.added.0.is_empty());
+        assert_eq!(current.total, Some(2));
+        assert_eq!(current.fingerprint, Some(fingerprint.clone()));
+
+        // a filtered client holds less than the fingerprint covers
+        let filtered = tracker.initial_change(&handshake(format!(
+            "fingerprint={}&extensions=jpg",
+            fingerprint
+        )));
+        assert_eq!(filtered.added.0.len(), 2);
+        assert_eq!(filtered.fingerprint, None);
+
+        tracker
+            .handle_event(change(vec!["a.jpg"], vec![entry("c.jpg", 3)]))
+            .await;
+        let stale = tracker.initial_change(&handshake(format!("fingerprint={}", fingerprint)));
+        assert_eq!(stale.added.0.len(), 2);
+        assert_ne!(stale.fingerprint, Some(fingerprint.clone()));
+
+        // the fingerprint depends on the files, not on how the baseline got there
+        tracker
+            .handle_event(change(vec!["c.jpg"], vec![entry("a.jpg", 1)]))
+            .await;
+        assert_eq!(tracker.baseline_fingerprint, fingerprint);
+    }
+
+    #[tokio::test]
+    async fn slices_past_the_end_are_empty_final_pages() {
+        let (authentication_token_store_actor_sender, _authentication_token_store_actor_receiver) =
+            mpsc::channel(8);
+        let mut tracker = FileTrackerActor::for_test(authentication_token_store_actor_sender);
+        tracker
+            .handle_event(FileTrackerActorEvent::Change(FileChangeData::new(
+                Vec::new(),
+                vec![entry("c.jpg", 3), entry("b.jpg", 2), entry("a.jpg", 1)],
+                Vec::new(),
+            )))
+            .await;
+        let filter = WebSocketFilter::default();
+
+        let first = tracker.baseline_slice(0, 2, &filter);
+        let snapshot = first.snapshot.as_ref().unwrap();
+        assert_eq!(first.added.0.len(), 2);
+        assert!(first.full);
+        assert!(snapshot.more);
+        assert_eq!(first.fingerprint, None);
+
+        let last = tracker.baseline_slice(2, 2, &filter);
+        assert_eq!(last.added.0.len(), 1);
+        assert!(!last.full);
+        assert!(!last.snapshot.as_ref().unwrap().more);
+        assert_eq!(last.fingerprint, Some(tracker.baseline_fingerprint.clone()));
+
+        for offset in [3, 10, usize::MAX] {
+            let past_the_end = tracker.baseline_slice(offset, 2, &filter);
+            let snapshot = past_the_end.snapshot.as_ref().unwrap();
+            assert!(past_the_end.added.0.is_empty());
+            assert!(!snapshot.more);
+            assert_eq!(snapshot.total, 3);
+            assert_eq!(snapshot.offset, offset);
+        }
+    }
+
+    #[tokio::test]
+    async fn the_baseline_follows_the_sort_order() {
+        let cases = [
+            (
+                SortOrder::MtimeDesc,
+                ["d", "b", "a", "c"],
+                ["d", "b", "a", "e"],
+            ),
+            (
+                SortOrder::MtimeAsc,
+                ["c", "a", "b", "d"],
+                ["e", "a", "b", "d"],
+            ),
+            (
+                SortOrder::NameAsc,
+                ["a", "b", "c", "d"],
+                ["a", "b", "d", "e"],
+            ),
+            (
+                SortOrder::NameDesc,
+                ["d", "c", "b", "a"],
+                ["e", "d", "b", "a"],
+            ),
+        ];
+        for (sort_order, expected_added, expected_moved) in cases {
+            let (
+                authentication_token_store_actor_sender,
+                _authentication_token_store_actor_receiver,
+            ) = mpsc::channel(8);
+            let mut tracker = FileTrackerActor::for_test(authentication_token_store_actor_sender);
+            tracker.sort_order = sort_order;
+            let expected = |names: [&str; 4]| {
+                names
+                    .map(|name| PathBuf::from(format!("{}.jpg", name)))
+                    .to_vec()
+            };
+
+            // changes come sorted the way the tracker sorts its baseline
+            for mut added in [
+                vec![entry("a.jpg", 2), entry("c.jpg", 1)],
+                vec![entry("b.jpg", 3), entry("d.jpg", 4)],
+            ] {
+                added.sort_by(|a, b| sort_order.compare(a, b));
+                tracker
+                    .handle_event(FileTrackerActorEvent::Change(FileChangeData::new(
+                        Vec::new(),
+                        added,
+                        Vec::new(),
+                    )))
+                    .await;
+            }
+            assert_eq!(
+                baseline_paths(&tracker),
+                expected(expected_added),
+                "{:?}",
+                sort_order
+            );
+
+            tracker
+                .handle_event(FileTrackerActorEvent::Change(FileChangeData::new(
+                    Vec::new(),
+                    Vec::new(),
+                    vec![(PathBuf::from("c.jpg"), PathBuf::from("e.jpg"))],
+                )))
+                .await;
+            assert_eq!(
+                baseline_paths(&tracker),
+                expected(expected_moved),
+                "{:?}",
+                sort_order
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn changes_reach_live_web_sockets_past_finishing_ones() {
+        let (authentication_token_store_actor_sender, _authentication_token_store_actor_receiver) =
+            mpsc::channel(8);
+        let mut tracker = FileTrackerActor::for_test(authentication_token_store_actor_sender);
+        // dropped its receiver, but its task has not returned yet
+        let (finishing_sender, _) = mpsc::channel(1);
+        let finishing = tokio::task::spawn(std::future::pending());
+        let (live_sender, mut live_receiver) = mpsc::channel(1);
+        let live = tokio::task::spawn(async {});
+        for (sender, join_handle) in [(finishing_sender, finishing), (live_sender, live)] {
+            tracker.web_socket_actor_senders_and_join_handles.push(
+                WebSocketActorSenderAndJoinHandle {
+                    sender,
+                    change_buffer: Arc::new(ChangeBuffer::new(Default::default())),
+                    join_handle,
+                    token: Token::generate(),
+                },
+            );
+        }
+
+        tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            tracker.handle_event(FileTrackerActorEvent::Change(FileChangeData::new(
+                Vec::new(),
+                vec![entry("a.jpg", 1)],
+                Vec::new(),
+            ))),
+        )
+        .await
+        .expect("the tracker waited on a finishing web socket actor");
+        assert!(matches!(
+            live_receiver.try_recv(),
+            Ok(WebSocketActorEvent::Change(_))
+        ));
+        assert_eq!(tracker.web_socket_actor_senders_and_join_handles.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn full_push_mode_sends_the_complete_set_on_every_change() {
+        let (authentication_token_store_actor_sender, _authentication_token_store_actor_receiver) =
+            mpsc::channel(8);
+        let mut tracker = FileTrackerActor::for_test(authentication_token_store_actor_sender);
+        tracker.push_mode = PushMode::Full;
+        let (sender, mut receiver) = mpsc::channel(8);
+        tracker
+            .web_socket_actor_senders_and_join_handles
+            .push(WebSocketActorSenderAndJoinHandle {
+                sender,
+                change_buffer: Arc::new(ChangeBuffer::new(Default::default())),
+                join_handle: tokio::task::spawn(async {}),
+                token: Token::generate(),
+            });
+        let mut pushed = async |change: FileChangeData| {
+            tracker
+                .handle_event(FileTrackerActorEvent::Change(change))
+                .await;
+            let Some(WebSocketActorEvent::Change(pushed)) = receiver.recv().await else {
+                panic!("expected a change to be pushed");
+            };
+            assert!(pushed.full);
+            assert!(pushed.removed.0.is_empty());
+            pushed
+                .added
+                .0
+                .into_iter()
+                .map(|entry| entry.path)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            pushed(FileChangeData::new(
+                Vec::new(),
+                vec![entry("b.jpg", 2), entry("a.jpg", 1)],
+                Vec::new(),
+            ))
+            .await,
+            vec![PathBuf::from("b.jpg"), PathBuf::from("a.jpg")]
+        );
+        assert_eq!(
+            pushed(FileChangeData::new(
+                vec![PathBuf::from("b.jpg")],
+                vec![entry("c.jpg", 3)],
+                Vec::new(),
+            ))
+            .await,
+            vec![PathBuf::from("c.jpg"), PathBuf::from("a.jpg")]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn web_sockets_still_open_after_the_grace_period_are_aborted() {
+        let (authentication_token_store_actor_sender, _authentication_token_store_actor_receiver) =
+            mpsc::channel(8);
+        let mut tracker = FileTrackerActor::for_test(authentication_token_store_actor_sender);
+        // one closes once its channel does, the other never finishes
+        let (closing_sender, mut closing_receiver) = mpsc::channel(1);
+        let closing =
+            tokio::task::spawn(async move { while closing_receiver.recv().await.is_some() {} });
+        let (stuck_sender, _stuck_receiver) = mpsc::channel(1);
+        let stuck = tokio::task::spawn(std::future::pending());
+        for (sender, join_handle) in [(closing_sender, closing), (stuck_sender, stuck)] {
+            tracker.web_socket_actor_senders_and_join_handles.push(
+                WebSocketActorSenderAndJoinHandle {
+                    sender,
+                    change_buffer: Arc::new(ChangeBuffer::new(Default::default())),
+                    join_handle,
+                    token: Token::generate(),
+                },
+            );
+        }
+
+        let start = tokio::time::Instant::now();
+        assert_eq!(tracker.shutdown_web_socket_actor_handlers().await, (1, 1));
+        assert_eq!(start.elapsed(), WEB_SOCKET_SHUTDOWN_GRACE_PERIOD);
+    }
+}
@@ -1,47 +1,96 @@
 use axum::{
     body::Body,
     extract::Path,
-    http::header,
+    http::{HeaderMap, StatusCode, header},
     response::{IntoResponse, Response},
 };
 use mime_guess::from_path;
 use rust_embed::Embed;
 use std::hash::{Hash, Hasher};
+#[cfg(feature = "compress-frontend")]
+use std::io::Read;
 
 #[derive(Embed)]
-#[folder = "frontend/dist/"]
+#[cfg_attr(
+    all(not(feature = "no-frontend"), not(feature = "compress-frontend")),
+    folder = "frontend/dist/"
+)]
+#[cfg_attr(
+    all(feature = "no-frontend", not(feature = "compress-frontend")),
+    folder = "frontend/placeholder/"
+)]
+// gzipped by the build script from whichever of the above is built
+#[cfg_attr(feature = "compress-frontend", folder = "$OUT_DIR/frontend/")]
 struct Frontend;
 
 #[tracing::instrument(level = "trace")]
-pub async fn serve_frontend(
-    path: Option<Path<String>>,
-) -> Result<impl IntoResponse, impl IntoResponse> {
+pub async fn serve_frontend(headers: HeaderMap, path: Option<Path<String>>) -> Response {
     let path = path.unwrap_or(Path("".to_string()));
     let path = path.as_str();
 
     let default_path = "index.html";
 
-    let path_candidate = if path.is_empty() { default_path } else { &path };
+    let path_candidate = if path.is_empty() { default_path } else { path };
 
     let actual_path_and_content = Frontend::get(path_candidate)
         .map(|content| (path_candidate, content))
         .or_else(|| Frontend::get(default_path).map(|content| (default_path, content)));
 
-    if let Some((actual_path, content)) = actual_path_and_content {
-        let body = Body::from(content.data.into_owned());
-        let mime = from_path(actual_path).first_or_octet_stream();
-        tracing::debug!("Serving {} as {}", actual_path, path);
-        let response = Response::builder()
-            .header(header::CONTENT_TYPE, mime.as_ref())
-            .body(body);
-        match response {
-            Ok(response) => Ok(response),
-            Err(_) => Err(crate::axum_util::not_found().await),
-        }
-    } else {
+    let Some((actual_path, content)) = actual_path_and_content else {
         tracing::debug!("Failed to serve {}", path);
-        Err(crate::axum_util::not_found().await)
-    }
+        return crate::axum_util::not_found().await.into_response();
+    };
+
+    let mime = from_path(actual_path).first_or_octet_stream();
+    tracing::debug!("Serving {} as {}", actual_path, path);
+    let response = Response::builder().header(header::CONTENT_TYPE, mime.as_ref());
+
+    #[cfg(feature = "compress-frontend")]
+    let (response, body) = {
+        let response = response.header(header::VARY, header::ACCEPT_ENCODING.as_str());
+        if accepts_gzip(&headers) {
+            (
+                response.header(header::CONTENT_ENCODING, "gzip"),
+                Body::from(content.data.into_owned()),
+            )
+        } else {
+            let mut data = Vec::new();
+            if let Err(e) =
+                flate2::read::GzDecoder::new(content.data.as_ref()).read_to_end(&mut data)
+            {
+                tracing::error!("Failed to decompress {}: {}", actual_path, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+            (response, Body::from(data))
+        }
+    };
+    #[cfg(not(feature = "compress-frontend"))]
+    let body = Body::from(content.data.into_owned());
+
+    response
+        .body(body)
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+}
+
+/// Whether `Accept-Encoding` admits gzip, in which assets are stored with `compress-frontend`.
+#[cfg(feature = "compress-frontend")]
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut parameters = coding.split(';').map(str::trim);
+            let name = parameters.next().unwrap_or_default();
+            let refused = parameters.any(|parameter| {
+                parameter
+                    .strip_prefix("q=")
+                    .and_then(|quality| quality.parse::<f32>().ok())
+                    == Some(0.0)
+            });
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && !refused
+        })
 }
 
 pub fn frontend_hash() -> String {
@@ -61,3 +110,63 @@ pub fn frontend_hash() -> String {
     shas.hash(&mut hasher);
     format!("{:016x}", hasher.finish())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "compress-frontend")]
+    use axum::http::HeaderValue;
+
+    async fn body(response: Response) -> Vec<u8> {
+        axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+            .to_vec()
+    }
+
+    #[tokio::test]
+    async fn unknown_paths_fall_back_to_the_index() {
+        let index = serve_frontend(HeaderMap::new(), None).await;
+        assert_eq!(index.status(), StatusCode::OK);
+        assert_eq!(index.headers()[header::CONTENT_TYPE], "text/html");
+
+        let unknown = serve_frontend(HeaderMap::new(), Some(Path("images".to_string()))).await;
+        assert_eq!(body(unknown).await, body(index).await);
+    }
+
+    #[cfg(feature = "compress-frontend")]
+    #[tokio::test]
+    async fn assets_are_sent_gzipped_to_clients_accepting_it() {
+        let plain = serve_frontend(HeaderMap::new(), None).await;
+        assert_eq!(plain.headers().get(header::CONTENT_ENCODING), None);
+        let plain = body(plain).await;
+
+        let headers = HeaderMap::from_iter([(
+            header::ACCEPT_ENCODING,
+            HeaderValue::from_static("br, gzip;q=0.8"),
+        )]);
+        let gzipped = serve_frontend(headers, None).await;
+        assert_eq!(gzipped.headers()[header::CONTENT_ENCODING], "gzip");
+        let mut decompressed = Vec::new();
+        flate2::read::GzDecoder::new(body(gzipped).await.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, plain);
+    }
+
+    #[cfg(feature = "compress-frontend")]
+    #[test]
+    fn gzip_refused_with_zero_quality_is_not_accepted() {
+        let accepts = |value| {
+            accepts_gzip(&HeaderMap::from_iter([(
+                header::ACCEPT_ENCODING,
+                HeaderValue::from_static(value),
+            )]))
+        };
+        assert!(accepts("gzip, deflate"));
+        assert!(accepts("*"));
+        assert!(!accepts("gzip;q=0, br"));
+        assert!(!accepts("identity"));
+        assert!(!accepts(""));
+    }
+}
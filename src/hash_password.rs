@@ -0,0 +1,150 @@
+use crate::{
+    authentication, config,
+    error::{Error, Result},
+};
+use argon2::{PasswordHasher, password_hash::SaltString};
+use rand_core::OsRng;
+use std::io::{BufRead, IsTerminal, Write};
+
+const HELP: &str = "\
+Usage: image-watch hash-password
+
+Prints an Argon2id hash of a password, to be used as the value of AUTH_PASS_ARGON2 or after
+`username:` in a line of AUTH_USERS_FILE. The password is prompted for on a terminal and read as
+the first line of stdin otherwise.
+
+The hash uses the costs set by ARGON2_MEM_KIB, ARGON2_ITERATIONS and ARGON2_PARALLELISM, read
+from the environment and .env like the server does.";
+
+/// Runs `image-watch hash-password` with the arguments following the subcommand.
+pub fn run(args: impl Iterator<Item = String>) -> Result<()> {
+    if let Some(arg) = args.into_iter().next() {
+        if arg == "--help" || arg == "-h" {
+            println!("{}", HELP);
+            return Ok(());
+        }
+        return Err(Error::ConfigError(format!(
+            "hash-password takes no argument \"{}\", see --help",
+            arg
+        )));
+    }
+
+    let _ = dotenvy::dotenv();
+    let params = config::argon2_params_from_env()?;
+
+    let password = if std::io::stdin().is_terminal() {
+        let password = prompt("Password: ")?;
+        if prompt("Repeat password: ")? != password {
+            return Err(Error::ConfigError("The passwords do not match".to_string()));
+        }
+        password
+    } else {
+        read_line(&mut std::io::stdin().lock())?
+    };
+    println!("{}", hash(&password, params)?);
+
+    Ok(())
+}
+
+/// Hashes `password` with a random salt.
+fn hash(password: &str, params: argon2::Params) -> Result<String> {
+    if password.is_empty() {
+        return Err(Error::ConfigError("The password is empty".to_string()));
+    }
+
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = authentication::argon2(params)
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| Error::AuthConfigError(format!("Failed to hash the password: {}", e)))?;
+    Ok(hash.to_string())
+}
+
+/// The first line of `reader` without its line ending.
+fn read_line(reader: &mut impl BufRead) -> Result<String> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let trimmed_len = line.trim_end_matches(['\r', '\n']).len();
+    line.truncate(trimmed_len);
+    Ok(line)
+}
+
+/// Reads a line from the terminal on stdin without echoing it.
+fn prompt(message: &str) -> Result<String> {
+    eprint!("{}", message);
+    std::io::stderr().flush()?;
+
+    let echo_off = EchoOff::new()?;
+    let line = read_line(&mut std::io::stdin().lock());
+    drop(echo_off);
+    eprintln!();
+
+    line
+}
+
+/// Turns off the echo of the terminal on stdin until dropped.
+struct EchoOff(libc::termios);
+
+impl EchoOff {
+    fn new() -> Result<Self> {
+        // SAFETY: termios is plain data that tcgetattr fills in entirely before it is read
+        let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let original = termios;
+        termios.c_lflag &= !libc::ECHO;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(Self(original))
+    }
+}
+
+impl Drop for EchoOff {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.0) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use argon2::{PasswordHash, PasswordVerifier};
+
+    #[test]
+    fn only_the_first_line_is_the_password() {
+        let mut input = "pass word \r\nsecond line\n".as_bytes();
+        assert_eq!(read_line(&mut input).unwrap(), "pass word ");
+        assert_eq!(read_line(&mut "".as_bytes()).unwrap(), "");
+    }
+
+    #[test]
+    fn hashes_verify_with_the_given_costs() {
+        let params = argon2::Params::new(argon2::Params::MIN_M_COST, 1, 1, None).unwrap();
+        let first = hash("password", params.clone()).unwrap();
+        let second = hash("password", params.clone()).unwrap();
+        // each hash has a salt of its own
+        assert_ne!(first, second);
+
+        let parsed = PasswordHash::new(&first).unwrap();
+        let parsed_params = argon2::Params::try_from(&parsed).unwrap();
+        assert_eq!(
+            (parsed_params.m_cost(), parsed_params.t_cost()),
+            (argon2::Params::MIN_M_COST, 1)
+        );
+        let argon2 = authentication::argon2(params.clone());
+        assert!(argon2.verify_password(b"password", &parsed).is_ok());
+        assert!(argon2.verify_password(b"other", &parsed).is_err());
+
+        assert!(matches!(hash("", params), Err(Error::ConfigError(_))));
+    }
+
+    #[test]
+    fn arguments_other_than_help_are_rejected() {
+        assert!(run(["--help".to_string()].into_iter()).is_ok());
+        assert!(matches!(
+            run(["--cost=1".to_string()].into_iter()),
+            Err(Error::ConfigError(_))
+        ));
+    }
+}
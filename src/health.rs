@@ -0,0 +1,169 @@
+use axum::{Json, http::StatusCode};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+
+/// How often actors record that they are alive.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+
+fn since_epoch() -> Duration {
+    EPOCH.get_or_init(Instant::now).elapsed()
+}
+
+/// When an actor last got around to its run loop, as milliseconds since the first heartbeat of
+/// the process plus one; zero until the actor's first beat.
+#[derive(Clone, Debug, Default)]
+pub struct Heartbeat(Arc<AtomicU64>);
+
+impl Heartbeat {
+    /// Ticks at the heartbeat interval, starting right away.
+    pub fn timer() -> Interval {
+        let mut timer = tokio::time::interval(HEARTBEAT_INTERVAL);
+        timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        timer
+    }
+
+    pub fn beat(&self) {
+        self.0
+            .store(since_epoch().as_millis() as u64 + 1, Ordering::Relaxed);
+    }
+
+    /// Time since the last beat, `None` before the first.
+    fn age(&self) -> Option<Duration> {
+        match self.0.load(Ordering::Relaxed) {
+            0 => None,
+            beat => Some(since_epoch().saturating_sub(Duration::from_millis(beat - 1))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Alive,
+    Starting,
+    Wedged,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ActorHealth {
+    status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    heartbeat_age_ms: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Health {
+    status: Status,
+    actors: BTreeMap<&'static str, ActorHealth>,
+}
+
+/// Tells from the heartbeats of the core actors whether the server is up, still starting or
+/// has an actor that stopped making progress.
+#[derive(Clone, Debug)]
+pub struct HealthCheck {
+    heartbeats: Vec<(&'static str, Heartbeat)>,
+    max_heartbeat_age: Duration,
+}
+
+impl HealthCheck {
+    pub fn new(heartbeats: Vec<(&'static str, Heartbeat)>, max_heartbeat_age: Duration) -> Self {
+        Self {
+            heartbeats,
+            max_heartbeat_age,
+        }
+    }
+
+    /// 200 only while every actor is alive, 503 otherwise.
+    pub fn report(&self) -> (StatusCode, Json<Health>) {
+        let actors: BTreeMap<_, _> = self
+            .heartbeats
+            .iter()
+            .map(|(name, heartbeat)| {
+                let age = heartbeat.age();
+                let status = match age {
+                    None => Status::Starting,
+                    Some(age) if age > self.max_heartbeat_age => Status::Wedged,
+                    Some(_) => Status::Alive,
+                };
+                let health = ActorHealth {
+                    status,
+                    heartbeat_age_ms: age.map(|age| age.as_millis() as u64),
+                };
+                (*name, health)
+            })
+            .collect();
+        // a wedged actor outweighs one still starting
+        let status = actors
+            .values()
+            .map(|actor| actor.status)
+            .max()
+            .unwrap_or(Status::Alive);
+        if status != Status::Alive {
+            tracing::warn!("Health check failed: {:?}", &actors);
+        }
+        let status_code = match status {
+            Status::Alive => StatusCode::OK,
+            Status::Starting | Status::Wedged => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        (status_code, Json(Health { status, actors }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statuses(check: &HealthCheck) -> (StatusCode, Status, Vec<Status>) {
+        let (status_code, Json(health)) = check.report();
+        let actors = health.actors.values().map(|actor| actor.status).collect();
+        (status_code, health.status, actors)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn actors_are_starting_then_alive_until_their_heartbeat_is_too_old() {
+        let (first, second) = (Heartbeat::default(), Heartbeat::default());
+        let check = HealthCheck::new(
+            vec![("first", first.clone()), ("second", second.clone())],
+            Duration::from_secs(30),
+        );
+
+        first.beat();
+        assert_eq!(
+            statuses(&check),
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Status::Starting,
+                vec![Status::Alive, Status::Starting]
+            )
+        );
+
+        second.beat();
+        assert_eq!(
+            statuses(&check),
+            (StatusCode::OK, Status::Alive, vec![Status::Alive; 2])
+        );
+
+        tokio::time::advance(Duration::from_secs(31)).await;
+        second.beat();
+        assert_eq!(
+            statuses(&check),
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Status::Wedged,
+                vec![Status::Wedged, Status::Alive]
+            )
+        );
+        let (_, Json(health)) = check.report();
+        assert_eq!(health.actors["first"].heartbeat_age_ms, Some(31_000));
+    }
+}
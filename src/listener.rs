@@ -0,0 +1,110 @@
+use crate::{
+    config::ListenTarget,
+    error::{Error, Result},
+};
+use std::{
+    io::ErrorKind,
+    net::SocketAddr,
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+};
+use tokio::net::{TcpListener, TcpSocket, UnixListener};
+
+/// A bound listener for one of the listen targets.
+#[derive(Debug)]
+pub enum BoundListener {
+    Tcp(TcpListener),
+    /// Keeps the socket path so it can be removed after shutdown.
+    Unix(UnixListener, PathBuf),
+}
+
+pub async fn bind(listen_target: &ListenTarget, listen_backlog: u32) -> Result<BoundListener> {
+    match listen_target {
+        ListenTarget::Tcp(listen_address) => {
+            let socket_address = tokio::net::lookup_host(listen_address)
+                .await?
+                .next()
+                .ok_or_else(|| {
+                    Error::ConfigError(format!(
+                        "LISTEN_ADDRESS \"{}\" did not resolve to any address",
+                        listen_address
+                    ))
+                })?;
+            let socket = match socket_address {
+                SocketAddr::V4(_) => TcpSocket::new_v4()?,
+                SocketAddr::V6(_) => TcpSocket::new_v6()?,
+            };
+            socket.set_reuseaddr(true)?;
+            socket.bind(socket_address)?;
+            Ok(BoundListener::Tcp(socket.listen(listen_backlog)?))
+        }
+        ListenTarget::Unix(path) => {
+            remove_stale_socket(path)?;
+            let listener = UnixListener::bind(path)?;
+            tracing::info!("Listening on unix socket {}", path.display());
+            Ok(BoundListener::Unix(listener, path.clone()))
+        }
+    }
+}
+
+/// Removes a socket left behind by a server that did not shut down cleanly. Anything else at the
+/// path, including a socket something still listens on, is left alone.
+fn remove_stale_socket(path: &Path) -> Result<()> {
+    let metadata = match path.symlink_metadata() {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(Error::ConfigError(format!(
+            "LISTEN_ADDRESS {} exists and is not a socket",
+            path.display()
+        )));
+    }
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        return Err(Error::ConfigError(format!(
+            "LISTEN_ADDRESS {} is in use by another server",
+            path.display()
+        )));
+    }
+    tracing::info!("Removing stale socket {}", path.display());
+    std::fs::remove_file(path)?;
+    Ok(())
+}
+
+/// Removes the socket file once the server has stopped accepting connections on it.
+pub fn remove_socket(path: &Path) {
+    let _ = std::fs::remove_file(path)
+        .inspect_err(|e| tracing::warn!("Failed to remove socket {}: {}", path.display(), e));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn only_stale_sockets_are_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = |name: &str| ListenTarget::Unix(dir.path().join(name));
+
+        // a listener dropped without removing its socket leaves it behind
+        drop(std::os::unix::net::UnixListener::bind(dir.path().join("stale.sock")).unwrap());
+        let Ok(BoundListener::Unix(live, path)) = bind(&target("stale.sock"), 16).await else {
+            panic!("expected the stale socket to be replaced");
+        };
+        assert!(matches!(
+            bind(&target("stale.sock"), 16).await,
+            Err(Error::ConfigError(_))
+        ));
+        drop(live);
+        remove_socket(&path);
+        assert!(!path.exists());
+
+        std::fs::write(dir.path().join("file.sock"), b"").unwrap();
+        assert!(matches!(
+            bind(&target("file.sock"), 16).await,
+            Err(Error::ConfigError(_))
+        ));
+        assert!(dir.path().join("file.sock").exists());
+    }
+}
@@ -0,0 +1,325 @@
+use crate::file_tracker_actor::{FileTrackerActor, FileTrackerActorEvent, Revision};
+use axum::{
+    body::Body,
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use futures_util::{StreamExt, stream};
+use serde::{Deserialize, Serialize};
+use serde_with::{TimestampMilliSeconds, ser::SerializeAsWrap, serde_as};
+use std::{convert::Infallible, path::Path, time::SystemTime};
+use tokio::sync::mpsc;
+
+const ENTRIES_PER_CHUNK: usize = 1024;
+const REVISION_HEADER: &str = "x-revision";
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ManifestFormat {
+    Csv,
+    #[default]
+    Json,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ManifestQuery {
+    #[serde(default)]
+    format: ManifestFormat,
+    /// Comma-separated subset of `path,mtime`; all fields when absent.
+    fields: Option<String>,
+}
+
+/// The columns included for each entry.
+#[derive(Clone, Copy, Debug)]
+struct ManifestFields {
+    path: bool,
+    mtime: bool,
+}
+
+impl ManifestFields {
+    fn tag(self) -> &'static str {
+        match (self.path, self.mtime) {
+            (true, true) => "path+mtime",
+            (true, false) => "path",
+            (false, _) => "mtime",
+        }
+    }
+
+    const ALL: Self = Self {
+        path: true,
+        mtime: true,
+    };
+
+    fn parse(raw: &str) -> std::result::Result<Self, String> {
+        let mut fields = Self {
+            path: false,
+            mtime: false,
+        };
+        for field in raw.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            match field {
+                "path" => fields.path = true,
+                "mtime" => fields.mtime = true,
+                _ => {
+                    return Err(format!(
+                        "unknown field \"{}\", expected path or mtime",
+                        field
+                    ));
+                }
+            }
+        }
+        if !fields.path && !fields.mtime {
+            return Err("at least one field is required".to_string());
+        }
+        Ok(fields)
+    }
+}
+
+#[serde_as]
+#[derive(Serialize)]
+struct ManifestEntry<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<&'a Path>,
+    #[serde_as(as = "Option<TimestampMilliSeconds<i64>>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mtime: Option<SystemTime>,
+}
+
+impl ManifestFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            ManifestFormat::Csv => "text/csv; charset=utf-8",
+            ManifestFormat::Json => "application/json",
+        }
+    }
+
+    fn file_name(self) -> &'static str {
+        match self {
+            ManifestFormat::Csv => "manifest.csv",
+            ManifestFormat::Json => "manifest.json",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ManifestFormat::Csv => "csv",
+            ManifestFormat::Json => "json",
+        }
+    }
+
+    fn header(self, fields: ManifestFields) -> String {
+        match self {
+            ManifestFormat::Csv => {
+                let columns: Vec<_> = [(fields.path, "path"), (fields.mtime, "mtime")]
+                    .into_iter()
+                    .filter_map(|(included, name)| included.then_some(name))
+                    .collect();
+                format!("{}\n", columns.join(","))
+            }
+            ManifestFormat::Json => "[".to_string(),
+        }
+    }
+
+    fn footer(self) -> &'static str {
+        match self {
+            ManifestFormat::Csv => "",
+            ManifestFormat::Json => "]",
+        }
+    }
+
+    /// Appends an entry to `out`, after a separator unless it is the `first` one written. An
+    /// entry that cannot be rendered is left out, so the manifest stays well-formed.
+    fn write_entry(
+        self,
+        out: &mut String,
+        first: bool,
+        fields: ManifestFields,
+        path: &Path,
+        mtime: SystemTime,
+    ) -> bool {
+        let entry = match self {
+            ManifestFormat::Csv => {
+                let mut columns = Vec::new();
+                if fields.path {
+                    columns.push(escape_csv(&path.to_string_lossy()));
+                }
+                // rendered by the same rules as in JSON, so both agree on times before the epoch
+                let mtime = fields
+                    .mtime
+                    .then(|| {
+                        serde_json::to_string(
+                            &SerializeAsWrap::<_, TimestampMilliSeconds<i64>>::new(&mtime),
+                        )
+                    })
+                    .transpose();
+                mtime.map(|mtime| {
+                    columns.extend(mtime);
+                    format!("{}\n", columns.join(","))
+                })
+            }
+            ManifestFormat::Json => serde_json::to_string(&ManifestEntry {
+                path: fields.path.then_some(path),
+                mtime: fields.mtime.then_some(mtime),
+            })
+            .map(|json| if first { json } else { format!(",{}", json) }),
+        };
+        match entry {
+            Ok(entry) => {
+                out.push_str(&entry);
+                true
+            }
+            Err(e) => {
+                tracing::warn!("Failed to serialize manifest entry {:?}: {}", path, e);
+                false
+            }
+        }
+    }
+}
+
+fn escape_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// The entity tag of one representation of the manifest at `revision`.
+fn entity_tag(revision: Revision, format: ManifestFormat, fields: ManifestFields) -> String {
+    format!("\"{}-{}-{}\"", revision, format.name(), fields.tag())
+}
+
+fn matches_if_none_match(headers: &HeaderMap, entity_tag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|candidate| candidate.trim())
+        .any(|candidate| {
+            candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == entity_tag
+        })
+}
+
+#[tracing::instrument(level = "trace")]
+pub async fn serve_manifest(
+    file_tracker_actor_sender: mpsc::WeakSender<FileTrackerActorEvent>,
+    headers: HeaderMap,
+    ManifestQuery { format, fields }: ManifestQuery,
+) -> Response {
+    let fields = match fields.as_deref().map(ManifestFields::parse) {
+        None => ManifestFields::ALL,
+        Some(Ok(fields)) => fields,
+        Some(Err(message)) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+
+    let Some(file_tracker_actor_sender) = file_tracker_actor_sender.upgrade() else {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Service restarting").into_response();
+    };
+
+    // the baseline fetched below is at least as new as this revision, so a client tagged with it
+    // may fetch an unchanged manifest once more but never misses a change
+    let revision = match FileTrackerActor::request_revision(&file_tracker_actor_sender).await {
+        Ok(revision) => revision,
+        Err(e) => {
+            tracing::error!("Failed to request revision: {}", e);
+            return (StatusCode::SERVICE_UNAVAILABLE, "Service restarting").into_response();
+        }
+    };
+    let entity_tag = entity_tag(revision, format, fields);
+    if matches_if_none_match(&headers, &entity_tag) {
+        return Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .header(header::ETAG, &entity_tag)
+            .header(REVISION_HEADER, revision.seq)
+            .body(Body::empty())
+            .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response());
+    }
+
+    let baseline = match FileTrackerActor::request_baseline(&file_tracker_actor_sender).await {
+        Ok(baseline) => baseline,
+        Err(e) => {
+            tracing::error!("Failed to request baseline: {}", e);
+            return (StatusCode::SERVICE_UNAVAILABLE, "Service restarting").into_response();
+        }
+    };
+
+    // entries are rendered chunk by chunk while the body is streamed
+    let mut entries = baseline.0.into_iter().peekable();
+    let mut written = 0usize;
+    let chunks = std::iter::from_fn(move || {
+        entries.peek()?;
+        let mut chunk = String::new();
+        for entry in entries.by_ref().take(ENTRIES_PER_CHUNK) {
+            if format.write_entry(&mut chunk, written == 0, fields, &entry.path, entry.mtime) {
+                written += 1;
+            }
+        }
+        Some(chunk)
+    });
+    let body_stream = stream::once(async move { format.header(fields) })
+        .chain(stream::iter(chunks))
+        .chain(stream::once(async move { format.footer().to_string() }))
+        .map(Ok::<_, Infallible>);
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, format.content_type())
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", format.file_name()),
+        )
+        .header(header::ETAG, &entity_tag)
+        .header(REVISION_HEADER, revision.seq)
+        .body(Body::from_stream(body_stream))
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn times_before_the_epoch_agree_between_formats() {
+        let mtime = UNIX_EPOCH - Duration::from_millis(1500);
+        let fields = ManifestFields {
+            path: false,
+            mtime: true,
+        };
+
+        let mut csv = String::new();
+        assert!(ManifestFormat::Csv.write_entry(&mut csv, true, fields, Path::new("a"), mtime));
+        let mut json = String::new();
+        assert!(ManifestFormat::Json.write_entry(&mut json, true, fields, Path::new("a"), mtime));
+
+        assert_eq!(csv, "-1500\n");
+        assert_eq!(json, "{\"mtime\":-1500}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn entries_that_cannot_be_rendered_are_left_out() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let invalid = Path::new(OsStr::from_bytes(b"\xff.jpg"));
+        let mut json = ManifestFormat::Json.header(ManifestFields::ALL);
+        let mut written = 0;
+        for path in [invalid, Path::new("a.jpg"), Path::new("b.jpg")] {
+            if ManifestFormat::Json.write_entry(
+                &mut json,
+                written == 0,
+                ManifestFields::ALL,
+                path,
+                UNIX_EPOCH,
+            ) {
+                written += 1;
+            }
+        }
+        json.push_str(ManifestFormat::Json.footer());
+
+        assert_eq!(written, 2);
+        let entries: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["path"], "a.jpg");
+        assert_eq!(entries[1]["path"], "b.jpg");
+    }
+}
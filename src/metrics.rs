@@ -0,0 +1,129 @@
+use crate::error::Result;
+use axum::{
+    http::header,
+    response::{IntoResponse, Response},
+};
+use metrics::{
+    Unit, counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram,
+};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use std::time::Duration;
+use tokio::{sync::mpsc, time::MissedTickBehavior};
+use tracing::instrument;
+
+const RESCRAPES: &str = "image_watch_rescrapes_total";
+const RESCRAPE_DURATION: &str = "image_watch_rescrape_duration_seconds";
+const FILES_ADDED: &str = "image_watch_files_added_total";
+const FILES_REMOVED: &str = "image_watch_files_removed_total";
+const WEB_SOCKETS: &str = "image_watch_web_sockets";
+const LOGINS: &str = "image_watch_logins_total";
+
+const RESCRAPE_DURATION_BUCKETS: &[f64] = &[0.001, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// How often recorded histogram samples are folded into their buckets between scrapes.
+const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Installs the global recorder that the functions below report to.
+pub fn install() -> Result<PrometheusHandle> {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Full(RESCRAPE_DURATION.to_string()),
+            RESCRAPE_DURATION_BUCKETS,
+        )?
+        .install_recorder()?;
+
+    describe_counter!(RESCRAPES, "Full scans of the served files");
+    describe_histogram!(RESCRAPE_DURATION, Unit::Seconds, "Duration of full scans");
+    describe_counter!(FILES_ADDED, "Files added to the tracked files");
+    describe_counter!(FILES_REMOVED, "Files removed from the tracked files");
+    describe_gauge!(WEB_SOCKETS, "Currently connected web sockets");
+    describe_counter!(LOGINS, "Login attempts by result");
+
+    Ok(handle)
+}
+
+pub fn record_rescrape(duration: Duration) {
+    counter!(RESCRAPES).increment(1);
+    histogram!(RESCRAPE_DURATION).record(duration);
+}
+
+pub fn record_change(added: usize, removed: usize) {
+    counter!(FILES_ADDED).increment(added as u64);
+    counter!(FILES_REMOVED).increment(removed as u64);
+}
+
+pub fn set_web_sockets(count: usize) {
+    gauge!(WEB_SOCKETS).set(count as f64);
+}
+
+/// Only attempts the authentication actor answered count, not ones turned away busy. `result` is
+/// one of `success`, `failure` or `locked_out`.
+pub fn record_login(result: &'static str) {
+    counter!(LOGINS, "result" => result).increment(1);
+}
+
+/// The current values in the Prometheus text exposition format.
+pub fn render(handle: &PrometheusHandle) -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        handle.render(),
+    )
+        .into_response()
+}
+
+/// Runs until all senders of `receiver` are dropped; nothing is ever sent on it. Keeps histogram
+/// samples from piling up while nobody scrapes.
+#[instrument(level = "trace", skip(handle))]
+pub async fn run_upkeep(handle: PrometheusHandle, mut receiver: mpsc::Receiver<()>) {
+    let mut upkeep_timer = tokio::time::interval(UPKEEP_INTERVAL);
+    upkeep_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            msg = receiver.recv() => if msg.is_none() {
+                break;
+            },
+            _ = upkeep_timer.tick() => handle.run_upkeep(),
+        }
+    }
+
+    tracing::info!("metrics upkeep shut down");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recorded_values_are_rendered_under_their_names() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        metrics::with_local_recorder(&recorder, || {
+            record_rescrape(Duration::from_millis(20));
+            record_change(3, 1);
+            record_change(2, 0);
+            set_web_sockets(4);
+            record_login("success");
+            record_login("failure");
+            record_login("failure");
+        });
+
+        let rendered = handle.render();
+        let lines: Vec<_> = rendered.lines().collect();
+        for expected in [
+            "image_watch_rescrapes_total 1",
+            "image_watch_files_added_total 5",
+            "image_watch_files_removed_total 1",
+            "image_watch_web_sockets 4",
+            r#"image_watch_logins_total{result="success"} 1"#,
+            r#"image_watch_logins_total{result="failure"} 2"#,
+        ] {
+            assert!(
+                lines.contains(&expected),
+                "{} missing in {}",
+                expected,
+                rendered
+            );
+        }
+        assert!(rendered.contains("image_watch_rescrape_duration_seconds"));
+    }
+}
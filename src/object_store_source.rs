@@ -0,0 +1,119 @@
+use crate::{
+    axum_util,
+    config::S3Config,
+    error::{Error, Result},
+};
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+};
+use futures_util::TryStreamExt;
+use object_store::{
+    ObjectStore,
+    aws::{AmazonS3, AmazonS3Builder},
+    path::{Path as ObjectPath, PathPart},
+    signer::Signer,
+};
+use percent_encoding::percent_decode_str;
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+/// An object found by a listing, with its key relative to the configured prefix.
+#[derive(Debug)]
+pub struct ListedObject {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+    pub e_tag: Option<String>,
+}
+
+/// Lists images in an S3-compatible bucket and hands out presigned URLs for them.
+#[derive(Debug)]
+pub struct ObjectStoreSource {
+    store: AmazonS3,
+    prefix: ObjectPath,
+    presign_ttl: Duration,
+}
+
+impl ObjectStoreSource {
+    pub fn new(config: &S3Config) -> Result<Self> {
+        let store = AmazonS3Builder::from_env()
+            .with_bucket_name(&config.bucket)
+            .build()
+            .map_err(|e| Error::ConfigError(format!("S3 client could not be set up: {}", e)))?;
+        tracing::info!("Serving objects from bucket {}", &config.bucket);
+
+        Ok(Self {
+            store,
+            prefix: ObjectPath::from(config.prefix.as_str()),
+            presign_ttl: config.presign_ttl,
+        })
+    }
+
+    /// Lists all objects below the prefix. Objects in or named like dot-directories are left
+    /// out, as the directory backend does with dotfiles.
+    pub async fn list(&self) -> Result<Vec<ListedObject>> {
+        let objects: Vec<_> = self.store.list(Some(&self.prefix)).try_collect().await?;
+
+        Ok(objects
+            .into_iter()
+            .filter_map(|meta| {
+                let parts: Vec<_> = meta
+                    .location
+                    .prefix_match(&self.prefix)?
+                    .map(|part| {
+                        percent_decode_str(part.as_ref())
+                            .decode_utf8_lossy()
+                            .into_owned()
+                    })
+                    .collect();
+                if parts.is_empty() || parts.iter().any(|part| part.starts_with('.')) {
+                    return None;
+                }
+                Some(ListedObject {
+                    path: parts.iter().collect(),
+                    size: meta.size,
+                    modified: meta.last_modified.into(),
+                    e_tag: meta.e_tag,
+                })
+            })
+            .collect())
+    }
+
+    /// Redirects a request below the data route to a presigned URL for the object.
+    pub async fn redirect(&self, req: Request<Body>) -> Response {
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            return StatusCode::METHOD_NOT_ALLOWED.into_response();
+        }
+        let Ok(decoded) = percent_decode_str(req.uri().path()).decode_utf8() else {
+            return axum_util::not_found().await.into_response();
+        };
+        let key: Vec<_> = decoded
+            .split('/')
+            .filter(|segment| !segment.is_empty() && *segment != ".")
+            .collect();
+        if key.is_empty() || key.contains(&"..") {
+            return axum_util::not_found().await.into_response();
+        }
+
+        let location: ObjectPath = self
+            .prefix
+            .parts()
+            .chain(key.into_iter().map(PathPart::from))
+            .collect();
+        match self
+            .store
+            .signed_url(Method::GET, &location, self.presign_ttl)
+            .await
+        {
+            Ok(url) => Redirect::temporary(url.as_str()).into_response(),
+            Err(e) => {
+                tracing::error!("Failed to presign {}: {}", &location, e);
+                (StatusCode::BAD_GATEWAY, "Object store unavailable").into_response()
+            }
+        }
+    }
+}
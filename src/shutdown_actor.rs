@@ -0,0 +1,120 @@
+use std::{future::Future, time::Instant};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tracing::instrument;
+
+/// When an actor is awaited during shutdown; all actors of a stage are awaited before any of the
+/// next. An actor only stops once the actors of earlier stages dropped their senders to it.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum ShutdownStage {
+    /// Actors feeding changes in, so none arrive while the web sockets close.
+    FileChanges,
+    /// The file tracker, which closes its web sockets before it stops, and the actors following
+    /// its changes.
+    WebSockets,
+    Authentication,
+    /// Last, as every web socket and the authentication actor hold on to it until they stop.
+    TokenStore,
+    Metrics,
+}
+
+#[derive(Debug)]
+pub enum ShutdownActorEvent {
+    Register {
+        stage: ShutdownStage,
+        name: &'static str,
+        join_handle: JoinHandle<()>,
+    },
+}
+
+/// Holds the join handles of the long-lived actors and awaits them in stage order once there
+/// is nothing left to register.
+#[derive(Debug)]
+pub struct ShutdownActor {
+    join_handles: Vec<(ShutdownStage, &'static str, JoinHandle<()>)>,
+}
+
+impl ShutdownActor {
+    pub fn new() -> Self {
+        Self {
+            join_handles: Vec::new(),
+        }
+    }
+
+    /// Collects actors until all senders of `receiver` are dropped, then returns how many it
+    /// awaited.
+    #[instrument(level = "trace")]
+    pub async fn run(mut self, mut receiver: mpsc::UnboundedReceiver<ShutdownActorEvent>) -> usize {
+        while let Some(msg) = receiver.recv().await {
+            match msg {
+                ShutdownActorEvent::Register {
+                    stage,
+                    name,
+                    join_handle,
+                } => self.join_handles.push((stage, name, join_handle)),
+            }
+        }
+
+        // the sort is stable, so actors of a stage are awaited in the order they registered
+        self.join_handles.sort_by_key(|(stage, _, _)| *stage);
+        let actors = self.join_handles.len();
+        let start = Instant::now();
+        for (stage, name, join_handle) in self.join_handles {
+            if let Err(e) = join_handle.await {
+                tracing::error!(actor = name, "Actor failed: {}", e);
+            }
+            tracing::info!(
+                actor = name,
+                ?stage,
+                elapsed_ms = start.elapsed().as_millis() as u64,
+                "actor stopped"
+            );
+        }
+
+        actors
+    }
+}
+
+/// Spawns actors and registers them with a `ShutdownActor`, which awaits them on `shutdown`.
+#[derive(Debug)]
+pub struct ShutdownActorHandler {
+    sender: mpsc::UnboundedSender<ShutdownActorEvent>,
+    join_handle: JoinHandle<usize>,
+}
+
+impl ShutdownActorHandler {
+    pub fn new() -> Self {
+        // unbounded so actors can be registered outside of async code, there are only a handful
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let join_handle = tokio::spawn(ShutdownActor::new().run(receiver));
+        Self {
+            sender,
+            join_handle,
+        }
+    }
+
+    pub fn spawn(
+        &self,
+        stage: ShutdownStage,
+        name: &'static str,
+        actor: impl Future<Output = ()> + Send + 'static,
+    ) {
+        let join_handle = tokio::spawn(actor);
+        let _ = self
+            .sender
+            .send(ShutdownActorEvent::Register {
+                stage,
+                name,
+                join_handle,
+            })
+            .inspect_err(|e| tracing::error!("Error registering actor {}: {}", name, e));
+    }
+
+    /// Waits for all registered actors to stop, in stage order, and returns how many there
+    /// were. The actors have to be told to stop first, by dropping the senders to them.
+    pub async fn shutdown(self) -> usize {
+        drop(self.sender);
+        self.join_handle
+            .await
+            .expect("Expected shutdown actor to be joinable")
+    }
+}
@@ -1,3 +1,37 @@
+use crate::error::{Error, Result};
+use std::{
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+use tokio::sync::oneshot;
+
+static RESPONSE_TIMEOUT: OnceLock<Duration> = OnceLock::new();
+static SHUTDOWN_STARTED: OnceLock<Instant> = OnceLock::new();
+
+/// Sets how long `receive_response` waits for a reply, zero waits indefinitely.
+pub fn set_response_timeout(timeout: Duration) {
+    let _ = RESPONSE_TIMEOUT.set(timeout);
+}
+
+/// Awaits an actor's reply to a query, giving up after the configured timeout so a wedged actor
+/// cannot hold requests open forever.
+pub async fn receive_response<T>(response_receiver: oneshot::Receiver<T>) -> Result<T> {
+    receive_response_within(response_receiver, RESPONSE_TIMEOUT.get().copied()).await
+}
+
+async fn receive_response_within<T>(
+    response_receiver: oneshot::Receiver<T>,
+    timeout: Option<Duration>,
+) -> Result<T> {
+    match timeout.filter(|timeout| !timeout.is_zero()) {
+        Some(timeout) => tokio::time::timeout(timeout, response_receiver)
+            .await
+            .map_err(|_| Error::ResponseTimeout)?
+            .map_err(Error::from),
+        None => Ok(response_receiver.await?),
+    }
+}
+
 pub async fn shutdown_signal() {
     use tokio::signal::unix::{SignalKind, signal};
 
@@ -8,4 +42,72 @@ pub async fn shutdown_signal() {
         _ = sigterm.recv() => {},
         _ = sigint.recv() => {},
     }
+    mark_shutdown_started();
+}
+
+/// Records when shutdown began; only the first call counts, as several tasks await the signal.
+fn mark_shutdown_started() -> Instant {
+    *SHUTDOWN_STARTED.get_or_init(Instant::now)
+}
+
+/// When the shutdown signal arrived, `None` if the server stopped without one.
+pub fn shutdown_started() -> Option<Instant> {
+    SHUTDOWN_STARTED.get().copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn replies_are_awaited_up_to_the_timeout() {
+        let timeout = Some(Duration::from_secs(1));
+
+        let (response_sender, response_receiver) = oneshot::channel();
+        response_sender.send(7).unwrap();
+        assert_eq!(
+            receive_response_within(response_receiver, timeout)
+                .await
+                .unwrap(),
+            7
+        );
+
+        // an actor that dropped the query fails right away
+        let (_, response_receiver) = oneshot::channel::<()>();
+        let start = tokio::time::Instant::now();
+        assert!(matches!(
+            receive_response_within(response_receiver, timeout).await,
+            Err(Error::TokioSyncOneshotReceiveError(_))
+        ));
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        let (_response_sender, response_receiver) = oneshot::channel::<()>();
+        let start = tokio::time::Instant::now();
+        assert!(matches!(
+            receive_response_within(response_receiver, timeout).await,
+            Err(Error::ResponseTimeout)
+        ));
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+
+        for timeout in [None, Some(Duration::ZERO)] {
+            let (_response_sender, response_receiver) = oneshot::channel::<()>();
+            let wait = receive_response_within(response_receiver, timeout);
+            assert!(
+                tokio::time::timeout(Duration::from_secs(3600), wait)
+                    .await
+                    .is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn shutdown_is_timed_from_the_first_signal() {
+        let before = Instant::now();
+        let started = mark_shutdown_started();
+        std::thread::sleep(Duration::from_millis(5));
+
+        assert_eq!(mark_shutdown_started(), started);
+        assert_eq!(shutdown_started(), Some(started));
+        assert!(started >= before);
+    }
 }
@@ -1,106 +1,1760 @@
 use crate::{
     authentication::{
-        Token,
+        Token, Username,
         authentication_token_store_actor::{
             AuthenticationTokenStoreActor, AuthenticationTokenStoreActorEvent,
         },
     },
-    error::Result,
-    file_change_data::FileChangeData,
+    error::{Error, Result},
+    file_change_data::{FileAddData, FileChangeData},
+    file_tracker_actor::{FileTrackerActor, FileTrackerActorEvent},
 };
 use axum::extract::ws::{CloseFrame, Message, WebSocket, close_code};
-use tokio::sync::mpsc;
+use futures_util::{
+    SinkExt, StreamExt,
+    stream::{SplitSink, SplitStream},
+};
+use serde::{Deserialize, Serialize};
+use serde_with::{BoolFromInt, serde_as};
+use std::{
+    collections::{HashSet, VecDeque},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, PoisonError},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    sync::{Notify, mpsc, oneshot, watch},
+    task::{JoinError, JoinHandle},
+};
 use tracing::instrument;
 
+/// Changes queued for a web socket before the file tracker has to decide what to do about a
+/// client that does not keep up.
+pub const CHANGE_QUEUE_CAPACITY: usize = 8;
+
+/// How long a close frame may take, as a client that stopped reading would never take it.
+const CLOSE_FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Snapshot pages a client may ask for ahead of those it is sent.
+const SNAPSHOT_REQUEST_CAPACITY: usize = 4;
+
+/// What happens to a client whose queue of changes is full.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum WebSocketOverflow {
+    /// Drops the oldest queued change and resends the baseline once the client caught up, so
+    /// a slow client sees fewer intermediate states but stays connected and correct.
+    Drop,
+    /// Waits for the client, which holds up changes to every other client and the tracker
+    /// itself for as long as the slowest client takes.
+    #[default]
+    Block,
+    /// Closes the web socket with a hint to reconnect, costing the client a full baseline.
+    Close,
+}
+
+/// A ring buffer of changes for one web socket that the tracker never waits on, used unless
+/// the overflow policy blocks.
+#[derive(Debug)]
+pub struct ChangeBuffer {
+    overflow: WebSocketOverflow,
+    state: std::sync::Mutex<ChangeBufferState>,
+    notify: Notify,
+    /// Set once the client is to be closed, which may happen while the actor waits on a send.
+    closing: watch::Sender<bool>,
+}
+
+#[derive(Debug, Default)]
+struct ChangeBufferState {
+    changes: VecDeque<FileChangeData>,
+    overflowed: bool,
+}
+
+/// What the web socket actor takes from its change buffer.
+#[derive(Debug)]
+enum Buffered {
+    Change(FileChangeData),
+    /// Changes were dropped, or the client is to be closed, depending on the overflow policy.
+    Overflowed,
+}
+
+impl ChangeBuffer {
+    pub fn new(overflow: WebSocketOverflow) -> Self {
+        Self {
+            overflow,
+            state: std::sync::Mutex::new(ChangeBufferState::default()),
+            notify: Notify::new(),
+            closing: watch::Sender::new(false),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ChangeBufferState> {
+        // the state stays consistent even if a holder of the lock panicked
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Queues a change, returning false once the client is to be closed.
+    fn push(&self, change: FileChangeData) -> bool {
+        let mut state = self.lock();
+        if state.overflowed && matches!(self.overflow, WebSocketOverflow::Close) {
+            return false;
+        }
+        if state.changes.len() >= CHANGE_QUEUE_CAPACITY {
+            state.overflowed = true;
+            match self.overflow {
+                WebSocketOverflow::Drop => {
+                    state.changes.pop_front();
+                }
+                WebSocketOverflow::Block | WebSocketOverflow::Close => {
+                    drop(state);
+                    self.closing.send_replace(true);
+                    self.notify.notify_one();
+                    return false;
+                }
+            }
+        }
+        state.changes.push_back(change);
+        drop(state);
+        self.notify.notify_one();
+        true
+    }
+
+    /// Completes once the client is to be closed.
+    async fn closing(&self) {
+        let _ = self.closing.subscribe().wait_for(|closing| *closing).await;
+    }
+
+    /// The next change, or the overflow once the changes before it are taken when dropping and
+    /// right away when closing.
+    async fn pop(&self) -> Buffered {
+        loop {
+            {
+                let mut state = self.lock();
+                let close = matches!(self.overflow, WebSocketOverflow::Close);
+                if state.overflowed && (close || state.changes.is_empty()) {
+                    state.overflowed = close;
+                    return Buffered::Overflowed;
+                }
+                if let Some(change) = state.changes.pop_front() {
+                    return Buffered::Change(change);
+                }
+            }
+            self.notify.notified().await;
+        }
+    }
+}
+
+/// Subprotocol a client offers, next to its bearer token, to receive changes as MessagePack.
+pub const MSGPACK_PROTOCOL: &str = "image-watch.msgpack";
+
+/// How changes are encoded for a client.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum WebSocketEncoding {
+    /// Text frames, for clients that did not ask for anything else.
+    #[default]
+    Json,
+    /// Binary frames holding the same structure with field names, a fraction of the size when
+    /// many paths change.
+    MessagePack,
+}
+
+impl WebSocketEncoding {
+    fn encode(self, change: &FileChangeData) -> Result<Message> {
+        Ok(match self {
+            WebSocketEncoding::Json => Message::Text(serde_json::to_string(change)?.into()),
+            WebSocketEncoding::MessagePack => {
+                Message::Binary(rmp_serde::to_vec_named(change)?.into())
+            }
+        })
+    }
+}
+
+/// Options a client passes as query parameters when opening the web socket.
+#[serde_as]
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct WebSocketHandshake {
+    /// Unix timestamp in milliseconds; older files are left out of the initial baseline.
+    since: Option<u64>,
+    /// Comma-separated file extensions; only matching files are sent to this client.
+    extensions: Option<String>,
+    /// Directory below which files are sent to this client, all files when absent.
+    prefix: Option<String>,
+    /// Fingerprint of the complete list the client still holds from an earlier connection.
+    fingerprint: Option<String>,
+    /// Whether to leave the files out of the first message, as `0` or `1`, for clients that ask
+    /// for them in snapshot pages instead.
+    #[serde_as(as = "BoolFromInt")]
+    #[serde(default)]
+    paged: bool,
+    /// Chosen by subprotocol rather than query.
+    #[serde(skip)]
+    encoding: WebSocketEncoding,
+}
+
+impl WebSocketHandshake {
+    pub fn since(&self) -> Option<SystemTime> {
+        self.since
+            .map(|since| UNIX_EPOCH + Duration::from_millis(since))
+    }
+
+    pub fn fingerprint(&self) -> Option<&str> {
+        self.fingerprint.as_deref()
+    }
+
+    pub fn paged(&self) -> bool {
+        self.paged
+    }
+
+    pub fn with_encoding(self, encoding: WebSocketEncoding) -> Self {
+        Self { encoding, ..self }
+    }
+
+    pub fn encoding(&self) -> WebSocketEncoding {
+        self.encoding
+    }
+
+    pub fn filter(&self) -> WebSocketFilter {
+        WebSocketFilter::new(
+            self.prefix.as_deref(),
+            self.extensions
+                .as_deref()
+                .map(|extensions| extensions.split(',')),
+        )
+    }
+}
+
+/// Which files a client is sent.
+#[derive(Clone, Debug, Default)]
+pub struct WebSocketFilter {
+    prefix: Option<PathBuf>,
+    extensions: Option<HashSet<String>>,
+}
+
+impl WebSocketFilter {
+    pub fn new<'a>(
+        prefix: Option<&str>,
+        extensions: Option<impl IntoIterator<Item = &'a str>>,
+    ) -> Self {
+        let prefix = prefix
+            .map(|prefix| prefix.trim().trim_matches('/'))
+            .filter(|prefix| !prefix.is_empty())
+            .map(PathBuf::from);
+        let extensions = extensions
+            .map(|extensions| {
+                extensions
+                    .into_iter()
+                    .map(str::trim)
+                    .filter(|extension| !extension.is_empty())
+                    .map(str::to_string)
+                    .collect::<HashSet<_>>()
+            })
+            .filter(|extensions| !extensions.is_empty());
+        Self { prefix, extensions }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prefix.is_none() && self.extensions.is_none()
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        self.prefix
+            .as_ref()
+            .is_none_or(|prefix| path.starts_with(prefix))
+            && self.extensions.as_ref().is_none_or(|extensions| {
+                path.extension()
+                    .and_then(|extension| extension.to_str())
+                    .is_some_and(|extension| extensions.contains(extension))
+            })
+    }
+}
+
+/// Messages a client sends as JSON text frames.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    /// Replaces the filter of the handshake; a baseline matching the new filter follows.
+    Filter {
+        prefix: Option<String>,
+        extensions: Option<Vec<String>>,
+    },
+    /// Asks for up to `limit` files of the baseline, skipping the first `offset`, as seen through
+    /// the current filter.
+    Snapshot { offset: usize, limit: usize },
+}
+
+/// A page of the baseline a client asked for.
+#[derive(Debug)]
+struct SnapshotRequest {
+    offset: usize,
+    limit: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct WebSocketConnectionInfo {
+    pub id: uuid::Uuid,
+    pub username: Option<Username>,
+    pub remote_address: SocketAddr,
+}
+
+impl WebSocketConnectionInfo {
+    pub fn new(username: Option<Username>, remote_address: SocketAddr) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4(),
+            username,
+            remote_address,
+        }
+    }
+
+    fn username(&self) -> &dyn std::fmt::Display {
+        match &self.username {
+            Some(username) => username,
+            None => &"<unknown>",
+        }
+    }
+
+    pub fn audit_accepted(&self) {
+        tracing::info!(
+            target: "audit",
+            connection_id = %self.id,
+            username = %self.username(),
+            remote_address = %self.remote_address,
+            "web socket accepted"
+        );
+    }
+
+    fn audit_disconnected(&self, reason: &str) {
+        tracing::info!(
+            target: "audit",
+            connection_id = %self.id,
+            username = %self.username(),
+            remote_address = %self.remote_address,
+            reason,
+            "web socket disconnected"
+        );
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct WebSocketSettings {
+    pub token_refresh_interval: Duration,
+    /// Maximum inbound frames per window before the client is disconnected, 0 disables the limit.
+    pub inbound_frame_limit: u32,
+    pub inbound_frame_window: Duration,
+    /// Interval at which the full baseline is re-sent regardless of changes, zero disables it.
+    pub full_resync_interval: Duration,
+    /// Maximum number of entries per message, larger changes are split; 0 disables splitting.
+    pub max_changes_per_message: usize,
+    /// Connection age after which the client is asked to reconnect, zero disables it.
+    pub max_lifetime: Duration,
+    /// Delay suggested to clients in the close reason before they reconnect.
+    pub reconnect_delay: Duration,
+    /// Interval at which the client is pinged, zero disables pings.
+    pub ping_interval: Duration,
+    /// Time the client has to answer a ping with any frame before it is dropped.
+    pub pong_timeout: Duration,
+    /// Window in which changes are coalesced into a single message, zero sends each right away.
+    pub flush_interval: Duration,
+    pub overflow: WebSocketOverflow,
+}
+
+/// Counts inbound frames in fixed windows; clients have no reason to send more than a few.
+#[derive(Debug)]
+struct InboundFrameLimiter {
+    limit: u32,
+    window: Duration,
+    window_start: tokio::time::Instant,
+    frames: u32,
+}
+
+impl InboundFrameLimiter {
+    fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            window_start: tokio::time::Instant::now(),
+            frames: 0,
+        }
+    }
+
+    /// Records a frame and returns whether the client is still within its limit.
+    fn admit(&mut self) -> bool {
+        if self.limit == 0 {
+            return true;
+        }
+        let now = tokio::time::Instant::now();
+        if now.duration_since(self.window_start) >= self.window {
+            self.window_start = now;
+            self.frames = 0;
+        }
+        self.frames += 1;
+        self.frames <= self.limit
+    }
+}
+
+async fn tick_if_enabled(timer: &mut Option<tokio::time::Interval>) {
+    match timer {
+        Some(timer) => {
+            timer.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+async fn sleep_until_if_enabled(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Shortens `lifetime` by a random amount of up to a tenth, so connections opened together are
+/// not all recycled at once.
+fn jittered(lifetime: Duration) -> Duration {
+    let bytes = uuid::Uuid::new_v4().into_bytes();
+    // the last bytes of a v4 UUID are all random
+    let random = u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]);
+    lifetime.mul_f64(1.0 - 0.1 * (random as f64 / u32::MAX as f64))
+}
+
+async fn receive_resync(
+    pending_resync: &mut Option<oneshot::Receiver<FileAddData>>,
+) -> Option<FileAddData> {
+    let result = match pending_resync {
+        Some(receiver) => receiver.await.ok(),
+        None => std::future::pending().await,
+    };
+    *pending_resync = None;
+    result
+}
+
+async fn receive_snapshot(
+    pending_snapshot: &mut Option<JoinHandle<Result<FileChangeData>>>,
+) -> Option<FileChangeData> {
+    let result = match pending_snapshot {
+        Some(join_handle) => join_handle.await,
+        None => std::future::pending().await,
+    };
+    *pending_snapshot = None;
+    match result {
+        Ok(Ok(page)) => Some(page),
+        Ok(Err(e)) => {
+            tracing::error!("failed to get snapshot page: {}", e);
+            None
+        }
+        Err(e) => {
+            tracing::error!("snapshot page task failed: {}", e);
+            None
+        }
+    }
+}
+
+/// The next snapshot page the client asked for, pending forever once the reader is gone.
+async fn receive_snapshot_request(
+    snapshot_receiver: &mut Option<mpsc::Receiver<SnapshotRequest>>,
+) -> SnapshotRequest {
+    if let Some(receiver) = snapshot_receiver {
+        if let Some(request) = receiver.recv().await {
+            return request;
+        }
+        *snapshot_receiver = None;
+    }
+    std::future::pending().await
+}
+
+/// The latest filter the client asked for, pending forever once the reader is gone.
+async fn receive_filter(
+    filter_receiver: &mut Option<watch::Receiver<WebSocketFilter>>,
+) -> WebSocketFilter {
+    if let Some(receiver) = filter_receiver {
+        if receiver.changed().await.is_ok() {
+            return receiver.borrow_and_update().clone();
+        }
+        *filter_receiver = None;
+    }
+    std::future::pending().await
+}
+
+/// Why the client's side of the connection ended.
+#[derive(Debug)]
+enum InboundEnd {
+    ClosedByClient,
+    FrameLimitExceeded,
+}
+
+/// Reads inbound frames until the client closes the connection or exceeds its frame limit,
+/// passing on filters and snapshot pages the client asks for and when it was last heard from. It
+/// runs as a task of its own so that a send blocked on a slow client cannot delay either.
+async fn read_inbound(
+    mut stream: SplitStream<WebSocket>,
+    mut inbound_frame_limiter: InboundFrameLimiter,
+    filter_sender: watch::Sender<WebSocketFilter>,
+    snapshot_sender: mpsc::Sender<SnapshotRequest>,
+    last_seen_sender: watch::Sender<tokio::time::Instant>,
+) -> InboundEnd {
+    while let Some(msg) = stream.next().await {
+        last_seen_sender.send_replace(tokio::time::Instant::now());
+        // the stream only ends once the close reply is flushed, which waits for pending sends
+        if let Ok(Message::Close(_)) = msg {
+            break;
+        }
+        if !inbound_frame_limiter.admit() {
+            return InboundEnd::FrameLimitExceeded;
+        }
+        if let Ok(Message::Text(text)) = msg {
+            match serde_json::from_str::<ClientMessage>(&text) {
+                Ok(ClientMessage::Filter { prefix, extensions }) => {
+                    let filter = WebSocketFilter::new(
+                        prefix.as_deref(),
+                        extensions
+                            .as_ref()
+                            .map(|extensions| extensions.iter().map(String::as_str)),
+                    );
+                    tracing::debug!("websocket client set filter {:?}", &filter);
+                    filter_sender.send_replace(filter);
+                }
+                Ok(ClientMessage::Snapshot { offset, limit }) => {
+                    if let Err(e) = snapshot_sender.try_send(SnapshotRequest { offset, limit }) {
+                        tracing::warn!("ignoring snapshot request: {}", e);
+                    }
+                }
+                Err(e) => tracing::debug!("ignoring websocket client message: {}", e),
+            }
+        }
+    }
+    InboundEnd::ClosedByClient
+}
+
+/// Why the server closes a web socket. Each cause has its own close code, and the close reason
+/// is a JSON hint telling clients whether to reconnect:
+///
+/// | cause                  | code                  | reason                                |
+/// |------------------------|-----------------------|---------------------------------------|
+/// | server shutdown        | 1001 going away       | `{"reconnect":true,"after_ms":2000}`  |
+/// | inbound frame limit    | 1008 policy violation | `{"reconnect":true,"after_ms":2000}`  |
+/// | client too slow        | 1013 try again later  | `{"reconnect":true,"after_ms":2000}`  |
+/// | maximum lifetime       | 1012 service restart  | `{"reconnect":true,"after_ms":0}`     |
+/// | session revoked        | 4001                  | `{"reconnect":false}`                 |
+/// | token no longer valid  | 4002                  | `{"reconnect":false}`                 |
+///
+/// `after_ms` is `WS_RECONNECT_DELAY` for all recoverable causes except recycling, where the
+/// server itself is fine. A client that stops answering pings gets no close frame, as it would
+/// not arrive anyway.
+#[derive(Clone, Copy, Debug)]
+enum CloseCause {
+    Shutdown,
+    FrameLimitExceeded,
+    Overflowed,
+    MaxLifetime,
+    Revoked,
+    TokenInvalid,
+}
+
+impl CloseCause {
+    fn code(self) -> u16 {
+        match self {
+            CloseCause::Shutdown => close_code::AWAY,
+            CloseCause::FrameLimitExceeded => close_code::POLICY,
+            CloseCause::Overflowed => close_code::AGAIN,
+            CloseCause::MaxLifetime => close_code::RESTART,
+            // from the range reserved for applications
+            CloseCause::Revoked => 4001,
+            CloseCause::TokenInvalid => 4002,
+        }
+    }
+
+    fn reconnect_hint(self, reconnect_delay: Duration) -> ReconnectHint {
+        let after = match self {
+            CloseCause::Shutdown | CloseCause::FrameLimitExceeded | CloseCause::Overflowed => {
+                reconnect_delay
+            }
+            CloseCause::MaxLifetime => Duration::ZERO,
+            CloseCause::Revoked | CloseCause::TokenInvalid => {
+                return ReconnectHint {
+                    reconnect: false,
+                    after_ms: None,
+                };
+            }
+        };
+        ReconnectHint {
+            reconnect: true,
+            after_ms: Some(after.as_millis() as u64),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ReconnectHint {
+    reconnect: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    after_ms: Option<u64>,
+}
+
 #[derive(Debug)]
 pub enum WebSocketActorEvent {
     Change(FileChangeData),
+    Revoke,
 }
 
 #[derive(Debug)]
 pub struct WebSocketActor {
-    ws: WebSocket,
+    sink: SplitSink<WebSocket, Message>,
+    inbound: JoinHandle<InboundEnd>,
     authentication_token_store_actor_sender: mpsc::Sender<AuthenticationTokenStoreActorEvent>,
+    file_tracker_actor_sender: mpsc::WeakSender<FileTrackerActorEvent>,
     token_refresh_timer: tokio::time::Interval,
+    full_resync_timer: Option<tokio::time::Interval>,
+    lifetime_deadline: Option<tokio::time::Instant>,
+    ping_timer: Option<tokio::time::Interval>,
+    pong_timeout: Duration,
+    /// When the last ping not yet answered was sent.
+    unanswered_ping: Option<tokio::time::Instant>,
+    last_seen_receiver: watch::Receiver<tokio::time::Instant>,
+    flush_interval: Duration,
+    /// Changes received since the last flush, merged into one.
+    pending_change: Option<FileChangeData>,
+    flush_deadline: Option<tokio::time::Instant>,
+    pending_resync: Option<oneshot::Receiver<FileAddData>>,
+    /// The snapshot page being fetched, the next one is only asked for once it is sent.
+    pending_snapshot: Option<JoinHandle<Result<FileChangeData>>>,
+    change_buffer: Arc<ChangeBuffer>,
+    filter_receiver: Option<watch::Receiver<WebSocketFilter>>,
+    snapshot_receiver: Option<mpsc::Receiver<SnapshotRequest>>,
     token: Token,
+    connection_info: WebSocketConnectionInfo,
+    filter: WebSocketFilter,
+    encoding: WebSocketEncoding,
+    max_changes_per_message: usize,
+    reconnect_delay: Duration,
 }
 
 impl WebSocketActor {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         ws: WebSocket,
         authentication_token_store_actor_sender: mpsc::Sender<AuthenticationTokenStoreActorEvent>,
-        token_refresh_interval: std::time::Duration,
+        file_tracker_actor_sender: mpsc::WeakSender<FileTrackerActorEvent>,
+        settings: &WebSocketSettings,
         token: Token,
+        connection_info: WebSocketConnectionInfo,
+        filter: WebSocketFilter,
+        encoding: WebSocketEncoding,
+        change_buffer: Arc<ChangeBuffer>,
     ) -> Self {
-        let mut token_refresh_timer = tokio::time::interval(token_refresh_interval);
+        let mut token_refresh_timer = tokio::time::interval(settings.token_refresh_interval);
         token_refresh_timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // the connection starts out with a fresh baseline, so the first resync is one interval out
+        let full_resync_timer = (!settings.full_resync_interval.is_zero()).then(|| {
+            let mut timer = tokio::time::interval_at(
+                tokio::time::Instant::now() + settings.full_resync_interval,
+                settings.full_resync_interval,
+            );
+            timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            timer
+        });
+        let lifetime_deadline = (!settings.max_lifetime.is_zero())
+            .then(|| tokio::time::Instant::now() + jittered(settings.max_lifetime));
+        let ping_timer = (!settings.ping_interval.is_zero()).then(|| {
+            let mut timer = tokio::time::interval_at(
+                tokio::time::Instant::now() + settings.ping_interval,
+                settings.ping_interval,
+            );
+            timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            timer
+        });
+        let inbound_frame_limiter =
+            InboundFrameLimiter::new(settings.inbound_frame_limit, settings.inbound_frame_window);
+        let (sink, stream) = ws.split();
+        let (filter_sender, filter_receiver) = watch::channel(filter.clone());
+        let (snapshot_sender, snapshot_receiver) = mpsc::channel(SNAPSHOT_REQUEST_CAPACITY);
+        let (last_seen_sender, last_seen_receiver) = watch::channel(tokio::time::Instant::now());
+        let inbound = tokio::task::spawn(read_inbound(
+            stream,
+            inbound_frame_limiter,
+            filter_sender,
+            snapshot_sender,
+            last_seen_sender,
+        ));
         Self {
-            ws,
+            sink,
+            inbound,
             authentication_token_store_actor_sender,
+            file_tracker_actor_sender,
             token_refresh_timer,
+            full_resync_timer,
+            lifetime_deadline,
+            ping_timer,
+            pong_timeout: settings.pong_timeout,
+            unanswered_ping: None,
+            last_seen_receiver,
+            flush_interval: settings.flush_interval,
+            pending_change: None,
+            flush_deadline: None,
+            pending_resync: None,
+            pending_snapshot: None,
+            change_buffer,
+            filter_receiver: Some(filter_receiver),
+            snapshot_receiver: Some(snapshot_receiver),
             token,
+            connection_info,
+            filter,
+            encoding,
+            max_changes_per_message: settings.max_changes_per_message,
+            reconnect_delay: settings.reconnect_delay,
         }
     }
 
     #[instrument(level = "trace")]
-    async fn ws_send_change(&mut self, change: FileChangeData) -> Result<()> {
-        Ok(self
-            .ws
-            .send(Message::Text(serde_json::to_string(&change)?.into()))
-            .await?)
+    async fn ws_send_change(
+        sink: &mut SplitSink<WebSocket, Message>,
+        filter: &WebSocketFilter,
+        encoding: WebSocketEncoding,
+        max_changes_per_message: usize,
+        mut change: FileChangeData,
+    ) -> Result<()> {
+        // snapshot pages are cut from the files matching the filter already
+        if !filter.is_empty() && change.snapshot.is_none() {
+            let was_empty = change.is_empty();
+            change.retain_paths(|path| filter.matches(path));
+            // changes that only touched other files are of no interest to this client
+            if change.is_empty() && !was_empty && !change.full {
+                return Ok(());
+            }
+        }
+        for message in change.split(max_changes_per_message) {
+            sink.send(encoding.encode(&message)?).await?;
+        }
+        Ok(())
+    }
+
+    /// Asks the file tracker for its baseline from a task of its own, as the tracker may itself be
+    /// blocked sending a change to this actor; the reply is picked up in the run loop, straight
+    /// from the tracker so it is seen before any change sent after it.
+    fn request_resync(&mut self) {
+        if self.pending_resync.is_some() {
+            return;
+        }
+        let Some(file_tracker_actor_sender) = self.file_tracker_actor_sender.upgrade() else {
+            return;
+        };
+        let (response_sender, response_receiver) = oneshot::channel();
+        self.pending_resync = Some(response_receiver);
+        tokio::task::spawn(async move {
+            // on failure the reply is dropped along with the request, which ends the wait
+            let _ = file_tracker_actor_sender
+                .send(FileTrackerActorEvent::RequestBaseline { response_sender })
+                .await
+                .inspect_err(|e| tracing::debug!("failed to request full resync: {}", e));
+        });
+    }
+
+    /// Fetches a snapshot page on a task of its own, as the file tracker may be blocked sending a
+    /// change to this actor; the page is picked up in the run loop.
+    fn request_snapshot(&mut self, request: SnapshotRequest) {
+        let Some(file_tracker_actor_sender) = self.file_tracker_actor_sender.upgrade() else {
+            return;
+        };
+        let limit = match self.max_changes_per_message {
+            0 => request.limit.max(1),
+            max_changes_per_message => request.limit.clamp(1, max_changes_per_message),
+        };
+        let filter = self.filter.clone();
+        self.pending_snapshot = Some(tokio::task::spawn(async move {
+            FileTrackerActor::get_baseline_slice(
+                &file_tracker_actor_sender,
+                request.offset,
+                limit,
+                filter,
+            )
+            .await
+        }));
+    }
+
+    /// Whether the client sent anything since the last ping; anything at all counts as an answer.
+    fn ping_answered(&self) -> bool {
+        self.unanswered_ping
+            .is_none_or(|ping_sent_at| *self.last_seen_receiver.borrow() >= ping_sent_at)
+    }
+
+    /// Pings the client unless the last ping is still awaiting its answer. Gives up if even the
+    /// ping cannot be sent in time, as happens once a dead connection fills the send buffer.
+    async fn ping(&mut self) -> std::result::Result<(), &'static str> {
+        if !self.ping_answered() {
+            return Ok(());
+        }
+        let ping = self.sink.send(Message::Ping(Default::default()));
+        match tokio::time::timeout(self.pong_timeout, ping).await {
+            Ok(Ok(())) => {
+                self.unanswered_ping = Some(tokio::time::Instant::now());
+                Ok(())
+            }
+            Ok(Err(e)) => {
+                tracing::warn!("failed to send ping: {}", e);
+                Err("send failed")
+            }
+            Err(_) => Err("pong timeout"),
+        }
     }
 
     #[instrument(level = "trace")]
-    fn ws_send_close_frame(
+    async fn ws_send_close_frame(&mut self, cause: CloseCause) {
+        let reason =
+            serde_json::to_string(&cause.reconnect_hint(self.reconnect_delay)).unwrap_or_default();
+        let close_frame = self.sink.send(Message::Close(Some(CloseFrame {
+            code: cause.code(),
+            reason: reason.into(),
+        })));
+        match tokio::time::timeout(CLOSE_FRAME_TIMEOUT, close_frame).await {
+            Ok(result) => {
+                let _ = result.inspect_err(|e| tracing::warn!("failed to send close frame: {}", e));
+            }
+            Err(_) => tracing::warn!("client did not take the close frame in time"),
+        }
+    }
+
+    /// Sends a change unless the client goes away first, returning why the connection ended if
+    /// it did.
+    async fn send_change_while_connected(
+        &mut self,
+        change: FileChangeData,
+    ) -> std::result::Result<(), &'static str> {
+        let end = tokio::select! {
+            result = Self::ws_send_change(
+                &mut self.sink,
+                &self.filter,
+                self.encoding,
+                self.max_changes_per_message,
+                change,
+            ) => {
+                return result.map_err(|err| {
+                    tracing::error!("failed to send change: {}", err);
+                    "send failed"
+                });
+            },
+            end = &mut self.inbound => end,
+            _ = self.change_buffer.closing() => {
+                tracing::warn!("websocket client fell behind, closing");
+                self.ws_send_close_frame(CloseCause::Overflowed).await;
+                return Err("client too slow");
+            },
+        };
+        Err(self.handle_inbound_end(end).await)
+    }
+
+    /// Holds a change back to be sent with those following it within the flush interval. Changes
+    /// that cannot be merged go out after the pending one, a full change right away.
+    async fn queue_change(
+        &mut self,
+        change: FileChangeData,
+    ) -> std::result::Result<(), &'static str> {
+        if self.flush_interval.is_zero() {
+            return self.send_change_while_connected(change).await;
+        }
+        match &mut self.pending_change {
+            Some(pending_change) if pending_change.can_merge(&change) => {
+                let was_empty = pending_change.is_empty() || change.is_empty();
+                pending_change.merge(change);
+                // only the effect matters, and the changes cancelled each other out
+                if pending_change.is_empty() && !was_empty {
+                    self.pending_change = None;
+                    self.flush_deadline = None;
+                }
+                Ok(())
+            }
+            _ => {
+                self.flush().await?;
+                if change.full {
+                    return self.send_change_while_connected(change).await;
+                }
+                self.pending_change = Some(change);
+                self.flush_deadline = Some(tokio::time::Instant::now() + self.flush_interval);
+                Ok(())
+            }
+        }
+    }
+
+    async fn flush(&mut self) -> std::result::Result<(), &'static str> {
+        self.flush_deadline = None;
+        match self.pending_change.take() {
+            Some(change) => self.send_change_while_connected(change).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn handle_inbound_end(
         &mut self,
-    ) -> impl Future<Output = std::result::Result<(), axum::Error>> {
-        self.ws.send(Message::Close(Some(CloseFrame {
-            code: close_code::AWAY,
-            reason: "".into(),
-        })))
+        end: std::result::Result<InboundEnd, JoinError>,
+    ) -> &'static str {
+        match end {
+            Ok(InboundEnd::ClosedByClient) => {
+                tracing::info!("websocket closed");
+                "closed by client"
+            }
+            Ok(InboundEnd::FrameLimitExceeded) => {
+                tracing::warn!("websocket client exceeded inbound frame limit");
+                self.ws_send_close_frame(CloseCause::FrameLimitExceeded)
+                    .await;
+                "inbound frame limit exceeded"
+            }
+            Err(e) => {
+                tracing::error!("websocket reader failed: {}", e);
+                "read failed"
+            }
+        }
     }
 
     #[instrument(level = "trace")]
     pub async fn run(mut self, mut receiver: mpsc::Receiver<WebSocketActorEvent>) {
-        loop {
+        let reason = loop {
             tokio::select! {
+                // a baseline that is ready predates any change still queued, so it goes first
+                biased;
+                baseline = receive_resync(&mut self.pending_resync) => {
+                    if let Some(baseline) = baseline
+                        && let Err(reason) = self.queue_change(FileChangeData::full(baseline)).await
+                    {
+                        break reason;
+                    }
+                },
+                page = receive_snapshot(&mut self.pending_snapshot) => {
+                    // the page reflects every change the tracker applied, so those held back go first
+                    if let Some(page) = page {
+                        if let Err(reason) = self.flush().await {
+                            break reason;
+                        }
+                        if let Err(reason) = self.send_change_while_connected(page).await {
+                            break reason;
+                        }
+                    }
+                },
+                buffered = self.change_buffer.pop() => {
+                    match buffered {
+                        Buffered::Change(change) => {
+                            if let Err(reason) = self.queue_change(change).await {
+                                break reason;
+                            }
+                        },
+                        Buffered::Overflowed if matches!(self.change_buffer.overflow, WebSocketOverflow::Drop) => {
+                            // the client missed changes, which only a fresh baseline makes up for
+                            tracing::info!("websocket client fell behind, resending baseline");
+                            self.request_resync();
+                        },
+                        Buffered::Overflowed => {
+                            tracing::warn!("websocket client fell behind, closing");
+                            self.ws_send_close_frame(CloseCause::Overflowed).await;
+                            break "client too slow";
+                        },
+                    }
+                },
                 msg = receiver.recv() => {
                     match msg {
                         Some(WebSocketActorEvent::Change(change)) => {
-                            let result = self.ws_send_change(change).await;
-                            if let Err(err) = result {
-                                tracing::error!("failed to send change: {}", err);
-                                break;
+                            if let Err(reason) = self.queue_change(change).await {
+                                break reason;
                             }
                         },
+                        Some(WebSocketActorEvent::Revoke) => {
+                            self.ws_send_close_frame(CloseCause::Revoked).await;
+                            break "session revoked";
+                        },
                         None => {
-                            let _ = self.ws_send_close_frame().await.inspect_err(|e| tracing::warn!("failed to send close frame: {}", e));
-                            break;
+                            self.ws_send_close_frame(CloseCause::Shutdown).await;
+                            break "server shutdown";
                         },
                     }
                 },
-                msg = self.ws.recv() => {
-                    if msg.is_none() {
-                        tracing::info!("websocket closed");
-                        break;
-                    }
+                end = &mut self.inbound => {
+                    break self.handle_inbound_end(end).await;
                 },
                 _ = self.token_refresh_timer.tick() => {
-                    let result = AuthenticationTokenStoreActor::check_and_refresh_token(&mut self.authentication_token_store_actor_sender, self.token.clone()).await;
-                    if !result.inspect_err(|e| tracing::error!("failed to refresh token: {}", e)).unwrap_or(false) {
-                        break;
+                    match AuthenticationTokenStoreActor::refresh_token(&mut self.authentication_token_store_actor_sender, self.token.clone()).await {
+                        Ok(true) => {},
+                        Ok(false) => {
+                            self.ws_send_close_frame(CloseCause::TokenInvalid).await;
+                            break "token invalid";
+                        },
+                        // the token store only goes away while the server shuts down
+                        Err(e) => {
+                            tracing::error!("failed to refresh token: {}", e);
+                            self.ws_send_close_frame(CloseCause::Shutdown).await;
+                            break "token invalid";
+                        },
                     }
+                },
+                request = receive_snapshot_request(&mut self.snapshot_receiver), if self.pending_snapshot.is_none() => {
+                    self.request_snapshot(request);
+                },
+                filter = receive_filter(&mut self.filter_receiver) => {
+                    // the client replaces what it holds with the baseline that follows
+                    self.filter = filter;
+                    self.request_resync();
+                },
+                _ = tick_if_enabled(&mut self.full_resync_timer) => {
+                    self.request_resync();
+                },
+                _ = sleep_until_if_enabled(self.flush_deadline) => {
+                    if let Err(reason) = self.flush().await {
+                        break reason;
+                    }
+                },
+                _ = tick_if_enabled(&mut self.ping_timer) => {
+                    if let Err(reason) = self.ping().await {
+                        break reason;
+                    }
+                },
+                _ = sleep_until_if_enabled(
+                    self.unanswered_ping.map(|ping_sent_at| ping_sent_at + self.pong_timeout)
+                ) => {
+                    if !self.ping_answered() {
+                        tracing::info!("websocket client did not answer ping");
+                        break "pong timeout";
+                    }
+                    self.unanswered_ping = None;
+                },
+                _ = sleep_until_if_enabled(self.lifetime_deadline) => {
+                    // a fresh connection may well end up on another instance
+                    self.ws_send_close_frame(CloseCause::MaxLifetime).await;
+                    break "max lifetime reached";
                 }
             }
+        };
+
+        // the reader holds the other half of the socket, which closes once both are gone
+        self.inbound.abort();
+        if let Some(pending_snapshot) = &self.pending_snapshot {
+            pending_snapshot.abort();
         }
+        self.connection_info.audit_disconnected(reason);
     }
 
+    pub async fn revoke(sender: &mpsc::Sender<WebSocketActorEvent>) -> Result<()> {
+        sender.send(WebSocketActorEvent::Revoke).await?;
+        Ok(())
+    }
+
+    /// Hands a change to the actor as its overflow policy has it, failing once the actor is gone
+    /// or the client is to be closed.
     #[instrument(level = "trace")]
     pub async fn send_change(
         sender: &mpsc::Sender<WebSocketActorEvent>,
+        change_buffer: &ChangeBuffer,
         change: FileChangeData,
     ) -> Result<()> {
-        sender.send(WebSocketActorEvent::Change(change)).await?;
+        match change_buffer.overflow {
+            WebSocketOverflow::Block => {
+                sender.send(WebSocketActorEvent::Change(change)).await?;
+            }
+            WebSocketOverflow::Drop | WebSocketOverflow::Close => {
+                if sender.is_closed() || !change_buffer.push(change) {
+                    return Err(Error::ServiceBusy);
+                }
+            }
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+impl WebSocketSettings {
+    /// Settings with every optional timer and limit disabled.
+    pub(crate) fn for_test() -> Self {
+        Self {
+            token_refresh_interval: Duration::from_secs(3600),
+            inbound_frame_limit: 0,
+            inbound_frame_window: Duration::from_secs(1),
+            full_resync_interval: Duration::ZERO,
+            max_changes_per_message: 0,
+            max_lifetime: Duration::ZERO,
+            reconnect_delay: Duration::from_secs(1),
+            ping_interval: Duration::ZERO,
+            pong_timeout: Duration::from_secs(1),
+            flush_interval: Duration::ZERO,
+            overflow: WebSocketOverflow::Block,
+        }
+    }
+}
+
+#[cfg(test)]
+impl WebSocketActor {
+    /// An actor on `ws` with the test settings, which does not refresh its token within a test.
+    pub(crate) fn for_test(
+        ws: WebSocket,
+        authentication_token_store_actor_sender: mpsc::Sender<AuthenticationTokenStoreActorEvent>,
+        file_tracker_actor_sender: mpsc::WeakSender<FileTrackerActorEvent>,
+    ) -> Self {
+        Self::for_test_with_settings(
+            ws,
+            authentication_token_store_actor_sender,
+            file_tracker_actor_sender,
+            WebSocketSettings::for_test(),
+        )
+    }
+
+    pub(crate) fn for_test_with_settings(
+        ws: WebSocket,
+        authentication_token_store_actor_sender: mpsc::Sender<AuthenticationTokenStoreActorEvent>,
+        file_tracker_actor_sender: mpsc::WeakSender<FileTrackerActorEvent>,
+        settings: WebSocketSettings,
+    ) -> Self {
+        let mut actor = Self::new(
+            ws,
+            authentication_token_store_actor_sender,
+            file_tracker_actor_sender,
+            &settings,
+            Token::generate(),
+            WebSocketConnectionInfo::new(None, SocketAddr::from(([127, 0, 0, 1], 0))),
+            WebSocketFilter::default(),
+            WebSocketEncoding::Json,
+            Arc::new(ChangeBuffer::new(settings.overflow)),
+        );
+        actor.token_refresh_timer.reset();
+        actor
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_change_data::{FileEntry, FileMetadata};
+    use axum::{Router, extract::ws::WebSocketUpgrade, routing::get};
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, tungstenite};
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    /// Connects a client to a local server and returns both ends of the web socket.
+    async fn connect() -> (WebSocket, Client) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (socket_sender, socket_receiver) = oneshot::channel();
+        let socket_sender = Arc::new(std::sync::Mutex::new(Some(socket_sender)));
+        let app = Router::new().route(
+            "/",
+            get(async move |ws: WebSocketUpgrade| {
+                ws.on_upgrade(async move |socket| {
+                    if let Some(socket_sender) = socket_sender.lock().unwrap().take() {
+                        let _ = socket_sender.send(socket);
+                    }
+                })
+            }),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let (client, _) = tokio_tungstenite::connect_async(format!("ws://{}/", address))
+            .await
+            .unwrap();
+        (socket_receiver.await.unwrap(), client)
+    }
+
+    async fn receive_json(client: &mut Client) -> serde_json::Value {
+        match client.next().await {
+            Some(Ok(tungstenite::Message::Text(text))) => serde_json::from_str(&text).unwrap(),
+            other => panic!("expected a text frame, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_changes_and_closes_on_shutdown() {
+        let (ws, mut client) = connect().await;
+        let (authentication_token_store_actor_sender, _authentication_token_store_actor_receiver) =
+            mpsc::channel(8);
+        let (file_tracker_actor_sender, _file_tracker_actor_receiver) = mpsc::channel(8);
+        let actor = WebSocketActor::for_test(
+            ws,
+            authentication_token_store_actor_sender,
+            file_tracker_actor_sender.downgrade(),
+        );
+        let change_buffer = actor.change_buffer.clone();
+        let (sender, receiver) = mpsc::channel(CHANGE_QUEUE_CAPACITY);
+        let join_handle = tokio::spawn(actor.run(receiver));
+
+        let change = FileChangeData::new(
+            Vec::new(),
+            vec![FileEntry::new(
+                PathBuf::from("a.jpg"),
+                UNIX_EPOCH + Duration::from_secs(1),
+                FileMetadata::default(),
+            )],
+            Vec::new(),
+        );
+        WebSocketActor::send_change(&sender, &change_buffer, change)
+            .await
+            .unwrap();
+        let message = receive_json(&mut client).await;
+        assert_eq!(message["added"], serde_json::json!([["a.jpg", 1000]]));
+
+        drop(sender);
+        match client.next().await {
+            Some(Ok(tungstenite::Message::Close(Some(frame)))) => {
+                assert_eq!(u16::from(frame.code), close_code::AWAY);
+            }
+            other => panic!("expected a close frame, got {:?}", other),
+        }
+        join_handle.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn inbound_frame_limits_apply_per_window() {
+        let mut limiter = InboundFrameLimiter::new(2, Duration::from_secs(1));
+        assert!(limiter.admit());
+        assert!(limiter.admit());
+        assert!(!limiter.admit());
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(limiter.admit());
+        assert!(limiter.admit());
+        assert!(!limiter.admit());
+
+        let mut unlimited = InboundFrameLimiter::new(0, Duration::from_secs(1));
+        assert!((0..1000).all(|_| unlimited.admit()));
+    }
+
+    #[tokio::test]
+    async fn clients_flooding_inbound_frames_are_disconnected() {
+        let (ws, mut client) = connect().await;
+        let (authentication_token_store_actor_sender, _authentication_token_store_actor_receiver) =
+            mpsc::channel(8);
+        let (file_tracker_actor_sender, _file_tracker_actor_receiver) = mpsc::channel(8);
+        let actor = WebSocketActor::for_test_with_settings(
+            ws,
+            authentication_token_store_actor_sender,
+            file_tracker_actor_sender.downgrade(),
+            WebSocketSettings {
+                inbound_frame_limit: 3,
+                inbound_frame_window: Duration::from_secs(3600),
+                ..WebSocketSettings::for_test()
+            },
+        );
+        let (_sender, receiver) = mpsc::channel(CHANGE_QUEUE_CAPACITY);
+        let join_handle = tokio::spawn(actor.run(receiver));
+
+        for _ in 0..4 {
+            client
+                .send(tungstenite::Message::Text("{}".into()))
+                .await
+                .unwrap();
+        }
+        match client.next().await {
+            Some(Ok(tungstenite::Message::Close(Some(frame)))) => {
+                assert_eq!(u16::from(frame.code), close_code::POLICY);
+            }
+            other => panic!("expected a close frame, got {:?}", other),
+        }
+        // the actor ends although the server still holds its sender
+        join_handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn large_changes_are_sent_in_several_messages() {
+        let (ws, mut client) = connect().await;
+        let (authentication_token_store_actor_sender, _authentication_token_store_actor_receiver) =
+            mpsc::channel(8);
+        let (file_tracker_actor_sender, _file_tracker_actor_receiver) = mpsc::channel(8);
+        let actor = WebSocketActor::for_test_with_settings(
+            ws,
+            authentication_token_store_actor_sender,
+            file_tracker_actor_sender.downgrade(),
+            WebSocketSettings {
+                max_changes_per_message: 2,
+                ..WebSocketSettings::for_test()
+            },
+        );
+        let change_buffer = actor.change_buffer.clone();
+        let (sender, receiver) = mpsc::channel(CHANGE_QUEUE_CAPACITY);
+        let join_handle = tokio::spawn(actor.run(receiver));
+
+        let added = ["a.jpg", "b.jpg", "c.jpg"].map(|path| {
+            FileEntry::new(
+                PathBuf::from(path),
+                UNIX_EPOCH + Duration::from_secs(1),
+                FileMetadata::default(),
+            )
+        });
+        let change = FileChangeData::new(Vec::new(), added.to_vec(), Vec::new());
+        WebSocketActor::send_change(&sender, &change_buffer, change)
+            .await
+            .unwrap();
+        assert_eq!(
+            receive_json(&mut client).await["added"],
+            serde_json::json!([["a.jpg", 1000], ["b.jpg", 1000]])
+        );
+        assert_eq!(
+            receive_json(&mut client).await["added"],
+            serde_json::json!([["c.jpg", 1000]])
+        );
+
+        drop(sender);
+        join_handle.await.unwrap();
+    }
+
+    #[test]
+    fn message_pack_frames_decode_to_the_same_change() {
+        let mut change = FileChangeData::new(
+            vec![PathBuf::from("old.jpg")],
+            vec![FileEntry::new(
+                PathBuf::from("a.jpg"),
+                UNIX_EPOCH + Duration::from_millis(1500),
+                FileMetadata {
+                    size: Some(2048),
+                    ..FileMetadata::default()
+                },
+            )],
+            vec![(PathBuf::from("b.jpg"), PathBuf::from("c.jpg"))],
+        );
+        change.total = Some(2);
+
+        let Message::Binary(frame) = WebSocketEncoding::MessagePack.encode(&change).unwrap() else {
+            panic!("expected a binary frame");
+        };
+        let decoded: FileChangeData = rmp_serde::from_slice(&frame).unwrap();
+
+        assert_eq!(decoded.removed.0, change.removed.0);
+        assert_eq!(decoded.moved.0, change.moved.0);
+        assert_eq!(decoded.total, Some(2));
+        let [entry] = decoded.added.0.as_slice() else {
+            panic!("expected one added file, got {:?}", decoded.added);
+        };
+        assert_eq!(entry.path, PathBuf::from("a.jpg"));
+        assert_eq!(entry.mtime, UNIX_EPOCH + Duration::from_millis(1500));
+        assert_eq!(entry.metadata.size, Some(2048));
+    }
+
+    #[tokio::test]
+    async fn full_baselines_are_resent_periodically() {
+        let (ws, mut client) = connect().await;
+        let (authentication_token_store_actor_sender, _authentication_token_store_actor_receiver) =
+            mpsc::channel(8);
+        let (file_tracker_actor_sender, mut file_tracker_actor_receiver) = mpsc::channel(8);
+        let full_resync_interval = Duration::from_millis(50);
+        let actor = WebSocketActor::for_test_with_settings(
+            ws,
+            authentication_token_store_actor_sender,
+            file_tracker_actor_sender.downgrade(),
+            WebSocketSettings {
+                full_resync_interval,
+                ..WebSocketSettings::for_test()
+            },
+        );
+        let (sender, receiver) = mpsc::channel(CHANGE_QUEUE_CAPACITY);
+        let start = tokio::time::Instant::now();
+        let join_handle = tokio::spawn(actor.run(receiver));
+
+        for mtime_secs in [1, 2] {
+            let Some(FileTrackerActorEvent::RequestBaseline { response_sender }) =
+                file_tracker_actor_receiver.recv().await
+            else {
+                panic!("expected the baseline to be requested");
+            };
+            // the connection starts out with a fresh baseline
+            assert!(start.elapsed() >= full_resync_interval * mtime_secs);
+            response_sender
+                .send(FileAddData(vec![FileEntry::new(
+                    PathBuf::from("a.jpg"),
+                    UNIX_EPOCH + Duration::from_secs(mtime_secs.into()),
+                    FileMetadata::default(),
+                )]))
+                .unwrap();
+
+            let message = receive_json(&mut client).await;
+            assert_eq!(message["full"], true);
+            assert_eq!(
+                message["added"],
+                serde_json::json!([["a.jpg", mtime_secs * 1000]])
+            );
+        }
+
+        drop(sender);
+        join_handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn frames_are_read_while_a_send_is_pending_until_the_actor_ends() {
+        let (ws, mut client) = connect().await;
+        let (authentication_token_store_actor_sender, _authentication_token_store_actor_receiver) =
+            mpsc::channel(8);
+        let (file_tracker_actor_sender, _file_tracker_actor_receiver) = mpsc::channel(8);
+        let actor = WebSocketActor::for_test(
+            ws,
+            authentication_token_store_actor_sender,
+            file_tracker_actor_sender.downgrade(),
+        );
+        let change_buffer = actor.change_buffer.clone();
+        let mut filter_receiver = actor.filter_receiver.clone().unwrap();
+        let (sender, receiver) = mpsc::channel(CHANGE_QUEUE_CAPACITY);
+        let join_handle = tokio::spawn(actor.run(receiver));
+
+        // far more than the socket buffers hold, so the send waits for the client to read
+        let long_name = "x".repeat(1000);
+        let added: Vec<_> = (0..15_000)
+            .map(|index| {
+                FileEntry::new(
+                    PathBuf::from(format!("{}-{}.jpg", long_name, index)),
+                    UNIX_EPOCH,
+                    FileMetadata::default(),
+                )
+            })
+            .collect();
+        WebSocketActor::send_change(
+            &sender,
+            &change_buffer,
+            FileChangeData::new(Vec::new(), added, Vec::new()),
+        )
+        .await
+        .unwrap();
+        client
+            .send(tungstenite::Message::Text(
+                r#"{"type":"filter","prefix":"sub"}"#.into(),
+            ))
+            .await
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(5), filter_receiver.changed())
+            .await
+            .expect("the filter was not read while the send was pending")
+            .unwrap();
+        assert_eq!(
+            filter_receiver.borrow_and_update().prefix,
+            Some(PathBuf::from("sub"))
+        );
+        assert!(!join_handle.is_finished());
+
+        let message = receive_json(&mut client).await;
+        assert_eq!(message["added"].as_array().unwrap().len(), 15_000);
+        drop(sender);
+        join_handle.await.unwrap();
+        // the client never answers the close frame, so only the abort ends the reader
+        tokio::time::timeout(Duration::from_secs(5), filter_receiver.changed())
+            .await
+            .expect("the reader outlived the actor")
+            .unwrap_err();
+    }
+
+    #[test]
+    fn close_reasons_tell_clients_whether_and_when_to_reconnect() {
+        let reconnect_delay = Duration::from_millis(1500);
+        for (cause, expected) in [
+            (
+                CloseCause::Shutdown,
+                r#"{"reconnect":true,"after_ms":1500}"#,
+            ),
+            (
+                CloseCause::FrameLimitExceeded,
+                r#"{"reconnect":true,"after_ms":1500}"#,
+            ),
+            (
+                CloseCause::Overflowed,
+                r#"{"reconnect":true,"after_ms":1500}"#,
+            ),
+            (
+                CloseCause::MaxLifetime,
+                r#"{"reconnect":true,"after_ms":0}"#,
+            ),
+            (CloseCause::Revoked, r#"{"reconnect":false}"#),
+            (CloseCause::TokenInvalid, r#"{"reconnect":false}"#),
+        ] {
+            let reason = serde_json::to_string(&cause.reconnect_hint(reconnect_delay)).unwrap();
+            assert_eq!(reason, expected, "{:?}", cause);
+            // close reasons are limited to 123 bytes
+            assert!(reason.len() <= 123);
+        }
+    }
+
+    #[tokio::test]
+    async fn revoked_clients_are_told_not_to_reconnect() {
+        let (ws, mut client) = connect().await;
+        let (authentication_token_store_actor_sender, _authentication_token_store_actor_receiver) =
+            mpsc::channel(8);
+        let (file_tracker_actor_sender, _file_tracker_actor_receiver) = mpsc::channel(8);
+        let actor = WebSocketActor::for_test(
+            ws,
+            authentication_token_store_actor_sender,
+            file_tracker_actor_sender.downgrade(),
+        );
+        let (sender, receiver) = mpsc::channel(CHANGE_QUEUE_CAPACITY);
+        let join_handle = tokio::spawn(actor.run(receiver));
+
+        WebSocketActor::revoke(&sender).await.unwrap();
+        match client.next().await {
+            Some(Ok(tungstenite::Message::Close(Some(frame)))) => {
+                assert_eq!(u16::from(frame.code), 4001);
+                assert_eq!(frame.reason.as_str(), r#"{"reconnect":false}"#);
+            }
+            other => panic!("expected a close frame, got {:?}", other),
+        }
+        join_handle.await.unwrap();
+    }
+
+    #[test]
+    fn lifetimes_are_shortened_by_up_to_a_tenth() {
+        let lifetime = Duration::from_secs(3600);
+        for _ in 0..1000 {
+            let jittered = jittered(lifetime);
+            assert!(jittered <= lifetime && jittered >= lifetime.mul_f64(0.9));
+        }
+    }
+
+    #[tokio::test]
+    async fn connections_are_recycled_after_their_maximum_lifetime() {
+        let (ws, mut client) = connect().await;
+        let (authentication_token_store_actor_sender, _authentication_token_store_actor_receiver) =
+            mpsc::channel(8);
+        let (file_tracker_actor_sender, _file_tracker_actor_receiver) = mpsc::channel(8);
+        let max_lifetime = Duration::from_millis(200);
+        let start = tokio::time::Instant::now();
+        let actor = WebSocketActor::for_test_with_settings(
+            ws,
+            authentication_token_store_actor_sender,
+            file_tracker_actor_sender.downgrade(),
+            WebSocketSettings {
+                max_lifetime,
+                ..WebSocketSettings::for_test()
+            },
+        );
+        let (_sender, receiver) = mpsc::channel(CHANGE_QUEUE_CAPACITY);
+        let join_handle = tokio::spawn(actor.run(receiver));
+
+        match client.next().await {
+            Some(Ok(tungstenite::Message::Close(Some(frame)))) => {
+                assert_eq!(u16::from(frame.code), close_code::RESTART);
+            }
+            other => panic!("expected a close frame, got {:?}", other),
+        }
+        assert!(start.elapsed() >= max_lifetime.mul_f64(0.9));
+        // the actor ends although the server still holds its sender
+        join_handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn resyncs_wait_for_room_in_the_tracker_queue() {
+        let (ws, _client) = connect().await;
+        let (authentication_token_store_actor_sender, _authentication_token_store_actor_receiver) =
+            mpsc::channel(8);
+        let (file_tracker_actor_sender, mut file_tracker_actor_receiver) = mpsc::channel(1);
+        file_tracker_actor_sender
+            .try_send(FileTrackerActorEvent::RequestRevision {
+                response_sender: oneshot::channel().0,
+            })
+            .unwrap();
+        let mut actor = WebSocketActor::for_test(
+            ws,
+            authentication_token_store_actor_sender,
+            file_tracker_actor_sender.downgrade(),
+        );
+
+        actor.request_resync();
+        assert!(matches!(
+            file_tracker_actor_receiver.recv().await,
+            Some(FileTrackerActorEvent::RequestRevision { .. })
+        ));
+        let Some(FileTrackerActorEvent::RequestBaseline { response_sender }) =
+            file_tracker_actor_receiver.recv().await
+        else {
+            panic!("expected the baseline to be requested once there is room");
+        };
+        response_sender
+            .send(FileAddData(vec![FileEntry::new(
+                PathBuf::from("a.jpg"),
+                UNIX_EPOCH,
+                FileMetadata::default(),
+            )]))
+            .unwrap();
+
+        let baseline = receive_resync(&mut actor.pending_resync).await.unwrap();
+        assert_eq!(baseline.0.len(), 1);
+        assert!(actor.pending_resync.is_none());
+    }
+
+    #[tokio::test]
+    async fn accepts_and_disconnects_are_audited() {
+        let (logs, _guard) = crate::test_util::capture_logs();
+        let (ws, _client) = connect().await;
+        let (authentication_token_store_actor_sender, _authentication_token_store_actor_receiver) =
+            mpsc::channel(8);
+        let (file_tracker_actor_sender, _file_tracker_actor_receiver) = mpsc::channel(8);
+        let actor = WebSocketActor::for_test(
+            ws,
+            authentication_token_store_actor_sender,
+            file_tracker_actor_sender.downgrade(),
+        );
+        let connection_info = actor.connection_info.clone();
+        let (sender, receiver) = mpsc::channel(CHANGE_QUEUE_CAPACITY);
+
+        connection_info.audit_accepted();
+        let join_handle = tokio::spawn(actor.run(receiver));
+        drop(sender);
+        join_handle.await.unwrap();
+
+        let audit = logs.with_target("audit");
+        assert_eq!(audit.len(), 2, "{:?}", audit);
+        for line in &audit {
+            assert!(line.contains(&format!("connection_id={}", connection_info.id)));
+            assert!(line.contains("username=<unknown>"));
+            assert!(line.contains("remote_address=127.0.0.1:0"));
+        }
+        assert!(audit[0].contains("web socket accepted"));
+        assert!(audit[1].contains("web socket disconnected"));
+        assert!(audit[1].contains("reason=\"server shutdown\""));
+    }
+
+    #[test]
+    fn filters_keep_files_below_the_prefix_with_a_listed_extension() {
+        let uri: axum::http::Uri = "/ws?prefix=/a/&extensions=jpg,%20png,".parse().unwrap();
+        let axum::extract::Query(handshake) =
+            axum::extract::Query::<WebSocketHandshake>::try_from_uri(&uri).unwrap();
+        let filter = handshake.filter();
+        assert!(filter.matches(Path::new("a/x.jpg")));
+        assert!(filter.matches(Path::new("a/b/x.png")));
+        assert!(!filter.matches(Path::new("a/x.gif")));
+        assert!(!filter.matches(Path::new("ab/x.jpg")));
+        assert!(!filter.matches(Path::new("b/x.jpg")));
+
+        let filter = WebSocketFilter::new(Some(" / "), Some(["", " "]));
+        assert!(filter.is_empty());
+        assert!(filter.matches(Path::new("x.gif")));
+        assert!(WebSocketHandshake::default().filter().is_empty());
+    }
+
+    #[tokio::test]
+    async fn changes_are_filtered_until_the_client_asks_for_another_filter() {
+        let (ws, mut client) = connect().await;
+        let (authentication_token_store_actor_sender, _authentication_token_store_actor_receiver) =
+            mpsc::channel(8);
+        let (file_tracker_actor_sender, mut file_tracker_actor_receiver) = mpsc::channel(8);
+        let mut actor = WebSocketActor::for_test(
+            ws,
+            authentication_token_store_actor_sender,
+            file_tracker_actor_sender.downgrade(),
+        );
+        actor.filter = WebSocketFilter::new(Some("a"), Some(["jpg"]));
+        let change_buffer = actor.change_buffer.clone();
+        let (sender, receiver) = mpsc::channel(CHANGE_QUEUE_CAPACITY);
+        let join_handle = tokio::spawn(actor.run(receiver));
+
+        let entries = |paths: &[&str]| {
+            paths
+                .iter()
+                .map(|path| {
+                    FileEntry::new(PathBuf::from(path), UNIX_EPOCH, FileMetadata::default())
+                })
+                .collect::<Vec<_>>()
+        };
+        // a change touching only other files is not sent at all
+        for added in [
+            &["a/x.png", "b/x.jpg"][..],
+            &["a/x.jpg", "a/y.png", "b/y.jpg"],
+        ] {
+            WebSocketActor::send_change(
+                &sender,
+                &change_buffer,
+                FileChangeData::new(Vec::new(), entries(added), Vec::new()),
+            )
+            .await
+            .unwrap();
+        }
+        let message = receive_json(&mut client).await;
+        assert_eq!(message["added"], serde_json::json!([["a/x.jpg", 0]]));
+
+        client
+            .send(tungstenite::Message::Text(
+                r#"{"type":"filter","prefix":"b","extensions":null}"#.into(),
+            ))
+            .await
+            .unwrap();
+        let Some(FileTrackerActorEvent::RequestBaseline { response_sender }) =
+            file_tracker_actor_receiver.recv().await
+        else {
+            panic!("expected the baseline to be requested");
+        };
+        response_sender
+            .send(FileAddData(entries(&["a/x.jpg", "b/x.jpg", "b/y.png"])))
+            .unwrap();
+        let message = receive_json(&mut client).await;
+        assert_eq!(message["full"], true);
+        assert_eq!(
+            message["added"],
+            serde_json::json!([["b/x.jpg", 0], ["b/y.png", 0]])
+        );
+
+        drop(sender);
+        join_handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn clients_answering_pings_stay_connected() {
+        let (ws, mut client) = connect().await;
+        let (authentication_token_store_actor_sender, _authentication_token_store_actor_receiver) =
+            mpsc::channel(8);
+        let (file_tracker_actor_sender, _file_tracker_actor_receiver) = mpsc::channel(8);
+        let ping_interval = Duration::from_millis(50);
+        let actor = WebSocketActor::for_test_with_settings(
+            ws,
+            authentication_token_store_actor_sender,
+            file_tracker_actor_sender.downgrade(),
+            WebSocketSettings {
+                ping_interval,
+                pong_timeout: Duration::from_millis(100),
+                ..WebSocketSettings::for_test()
+            },
+        );
+        let (sender, receiver) = mpsc::channel(CHANGE_QUEUE_CAPACITY);
+        let join_handle = tokio::spawn(actor.run(receiver));
+
+        // reading lets the client answer each ping with a pong
+        let mut pings = 0;
+        let deadline = tokio::time::Instant::now() + ping_interval * 8;
+        while let Ok(frame) = tokio::time::timeout_at(deadline, client.next()).await {
+            match frame {
+                Some(Ok(tungstenite::Message::Ping(_))) => pings += 1,
+                other => panic!("expected a ping, got {:?}", other),
+            }
+        }
+        assert!(pings >= 3, "{} pings", pings);
+        assert!(!join_handle.is_finished());
+
+        drop(sender);
+        join_handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn clients_not_answering_pings_are_dropped() {
+        let (logs, _guard) = crate::test_util::capture_logs();
+        let (ws, mut client) = connect().await;
+        let (authentication_token_store_actor_sender, _authentication_token_store_actor_receiver) =
+            mpsc::channel(8);
+        let (file_tracker_actor_sender, _file_tracker_actor_receiver) = mpsc::channel(8);
+        let ping_interval = Duration::from_millis(50);
+        let pong_timeout = Duration::from_millis(100);
+        let actor = WebSocketActor::for_test_with_settings(
+            ws,
+            authentication_token_store_actor_sender,
+            file_tracker_actor_sender.downgrade(),
+            WebSocketSettings {
+                ping_interval,
+                pong_timeout,
+                ..WebSocketSettings::for_test()
+            },
+        );
+        let (_sender, receiver) = mpsc::channel(CHANGE_QUEUE_CAPACITY);
+        let start = tokio::time::Instant::now();
+
+        // the client does not read until the actor ended, so it never answers a ping
+        tokio::time::timeout(Duration::from_secs(5), actor.run(receiver))
+            .await
+            .expect("the actor kept a client that does not answer");
+        assert!(start.elapsed() >= ping_interval + pong_timeout);
+        let audit = logs.with_target("audit");
+        assert!(
+            audit.last().unwrap().contains("reason=\"pong timeout\""),
+            "{:?}",
+            audit
+        );
+
+        // the connection is dropped without a close frame
+        loop {
+            match client.next().await {
+                Some(Ok(tungstenite::Message::Ping(_))) => {}
+                Some(Ok(tungstenite::Message::Close(frame))) => {
+                    panic!("expected no close frame, got {:?}", frame)
+                }
+                _ => break,
+            }
+        }
+    }
+}